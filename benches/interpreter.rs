@@ -0,0 +1,36 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+use stackofstacks::assembler::assemble;
+use stackofstacks::fast;
+use stackofstacks::vm::{self, BufferIo, Vm};
+
+// Sample programs with a step limit that keeps each run in the milliseconds
+const PROGRAMS:[(&str, u64); 3] = [
+    ("helloworld.sos", u64::MAX),
+    ("loop.sos", u64::MAX),
+    ("turing/110.sos", 2_000_000),
+];
+
+fn interpreters(c:&mut Criterion){
+    let mut group = c.benchmark_group("interpreter");
+    for (filename, limit) in PROGRAMS{
+        let path = format!("{}/{}", env!("CARGO_MANIFEST_DIR"), filename);
+        let code = assemble(std::fs::read(path).unwrap()).unwrap().code;
+        let machine = || {
+            let mut vm = Vm::new(code.clone(), false);
+            vm.max_steps = Some(limit);
+            vm
+        };
+
+        group.bench_with_input(BenchmarkId::new("plain", filename), &code, |b, _| {
+            b.iter(|| vm::run(&mut machine(), &mut BufferIo::default(), &mut []))
+        });
+        group.bench_with_input(BenchmarkId::new("fast", filename), &code, |b, _| {
+            b.iter(|| fast::run(&mut machine(), &mut BufferIo::default()))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, interpreters);
+criterion_main!(benches);
@@ -0,0 +1,161 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use stackofstacks::assembler::{assemble, tokenise};
+use stackofstacks::explore::Rng;
+use stackofstacks::fast;
+use stackofstacks::fold;
+use stackofstacks::optimize;
+use stackofstacks::vm::{self, BufferIo, Effect, Observer, Vm};
+
+// Every workload is generated from these, so runs on different days measure the same thing
+const SEED:u64 = 110;
+const SOURCE_BLOCKS:[usize; 2] = [1_000, 10_000];
+const GENERATIONS:[u32; 2] = [10, 40];
+const LOOP_COUNTS:[i64; 2] = [1_000, 100_000];
+const CAT_INPUT:[usize; 2] = [64 << 10, 1 << 20];
+
+// cat.sos ends in a comment without a newline, which takes the code before it along when assembling
+fn read(filename:&str) -> Vec<u8>{
+    let mut source = std::fs::read(format!("{}/{}", env!("CARGO_MANIFEST_DIR"), filename)).unwrap();
+    source.push(b'\n');
+    source
+}
+
+// Lower case name for n, labels can only have those
+fn name(mut n:usize) -> String{
+    let mut name = String::new();
+    loop{
+        name.push((b'a' + (n % 26) as u8) as char);
+        n /= 26;
+        if n == 0 {return name}
+    }
+}
+
+// Source with labels, macros referring to them, comments and plain code, blocks pieces long
+fn source(blocks:usize) -> Vec<u8>{
+    let mut rng = Rng::new(SEED);
+    let mut source = b"#!./target/release/stackofstacks --strict\n".to_vec();
+    for block in 0..blocks{
+        let text = match rng.next_u64() % 6{
+            0 => "['H'].['i'].[10].\n".to_owned(),
+            1 => format!("[{}-{}]@\t\t# jump back\n", name(block / 2), rng.next_u64() % 64),
+            2 => format!("['{}'].\n", (b'a' + (rng.next_u64() % 26) as u8) as char),
+            3 => format!("!!^{:b}\t{:b}\n", rng.next_u64() % 1024, rng.next_u64() % 16),
+            4 => format!("# {}\n", "comment ".repeat((rng.next_u64() % 8) as usize)),
+            _ => "=!-=/\t!11011011*@\t$~$\n".to_owned(),
+        };
+        source.extend(text.as_bytes());
+        if block % 2 == 0{
+            source.extend(format!(":{}\n", name(block / 2)).as_bytes());
+        }
+    }
+    source
+}
+
+fn parsing(c:&mut Criterion){
+    let mut group = c.benchmark_group("parsing");
+    for blocks in SOURCE_BLOCKS{
+        let source = source(blocks);
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_with_input(BenchmarkId::new("tokenise", blocks), &source, |b, source| {
+            b.iter(|| tokenise(source.clone()).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("assemble", blocks), &source, |b, source| {
+            b.iter(|| assemble(source.clone()).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn optimizing(c:&mut Criterion){
+    let mut group = c.benchmark_group("optimizing");
+    for blocks in SOURCE_BLOCKS{
+        let code = assemble(source(blocks)).unwrap().code;
+        group.throughput(Throughput::Bytes(code.len() as u64));
+        group.bench_with_input(BenchmarkId::new("fold", blocks), &code, |b, code| {
+            b.iter(|| fold::fold(code, false))
+        });
+        group.bench_with_input(BenchmarkId::new("optimize", blocks), &code, |b, code| {
+            b.iter(|| optimize::optimize(code, false))
+        });
+    }
+    group.finish();
+}
+
+// Stops the run once count newlines were written, one per generation for Rule 110
+struct Lines{
+    count:u32,
+}
+
+impl Observer for Lines{
+    fn after(&mut self, _vm:&Vm, _cp:usize, effect:&Effect){
+        if *effect == Effect::Write(b'\n'){
+            self.count -= 1;
+        }
+    }
+
+    fn stop(&self) -> bool{
+        self.count == 0
+    }
+}
+
+// Runs code with input on every interpreter, and on the optimized code too when the optimizer can take it
+fn tiers(c:&mut Criterion, group:&str, workloads:Vec<(String, Vec<u8>, Vec<u8>, u64)>){
+    let mut group = c.benchmark_group(group);
+    for (id, code, input, limit) in workloads{
+        let optimized = optimize::optimize(&fold::fold(&code, false).code, false);
+        let mut variants = vec!(("plain", code.clone()), ("fast", code));
+        if optimized.dynamic.is_none(){
+            variants.push(("optimized", optimized.code));
+        }
+
+        for (tier, code) in variants{
+            let machine = || {
+                let mut vm = Vm::new(code.clone(), false);
+                vm.max_steps = Some(limit);
+                vm
+            };
+            group.bench_function(BenchmarkId::new(tier, &id), |b| {
+                if tier == "plain"{
+                    b.iter(|| vm::run(&mut machine(), &mut BufferIo::new(input.clone()), &mut []))
+                }else{
+                    b.iter(|| fast::run(&mut machine(), &mut BufferIo::new(input.clone())))
+                }
+            });
+        }
+    }
+    group.finish();
+}
+
+fn rule110(c:&mut Criterion){
+    let code = assemble(read("turing/110.sos")).unwrap().code;
+    let workloads = GENERATIONS.iter().map(|generations| {
+        // the steps the generations take, so every interpreter can be held to exactly those
+        let mut vm = Vm::new(code.clone(), false);
+        vm::run(&mut vm, &mut BufferIo::default(), &mut [&mut Lines{count: *generations}]).unwrap();
+        (format!("{} generations", generations), code.clone(), vec!(), vm.steps)
+    }).collect();
+    tiers(c, "rule110", workloads);
+}
+
+fn loops(c:&mut Criterion){
+    let source = String::from_utf8(read("loop.sos")).unwrap();
+    let workloads = LOOP_COUNTS.iter().map(|count| {
+        let code = assemble(source.replace("!!^1111", &format!("!!^{:b}", count)).into_bytes()).unwrap().code;
+        (format!("{} iterations", count), code, vec!(), u64::MAX)
+    }).collect();
+    tiers(c, "loop", workloads);
+}
+
+fn cat(c:&mut Criterion){
+    let code = assemble(read("cat.sos")).unwrap().code;
+    let workloads = CAT_INPUT.iter().map(|size| {
+        let mut input = vec![0; *size];
+        Rng::new(SEED).fill(&mut input);
+        (format!("{} KiB", size >> 10), code.clone(), input, u64::MAX)
+    }).collect();
+    tiers(c, "cat", workloads);
+}
+
+criterion_group!(benches, parsing, optimizing, rule110, loops, cat);
+criterion_main!(benches);
@@ -0,0 +1,2 @@
+Adds two digits read from input and writes the sum as a digit
+,>,[<+>-]<------------------------------------------------.
@@ -0,0 +1,2 @@
+Copies input to output until EOF
+,[.,]
@@ -0,0 +1,2 @@
+Writes every byte value from 255 down to 1 by wrapping below 0
+-[.-]
@@ -0,0 +1,2 @@
+Hello World with a newline (nested loops and moving back with a loop)
+++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.
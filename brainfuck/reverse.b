@@ -0,0 +1,2 @@
+Writes its input backwards
+>,[>,]<[.<]
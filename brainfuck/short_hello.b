@@ -0,0 +1,3 @@
+Hello comma World without a newline
+Counts down through 0 and walks left of where it started
+--<-<<+[+[<+>--->->->-<<<]>]<<--.<++++++.<<-..<<.<+.>>.>>.<<<.+++.>>.>>-.<<<+.
@@ -0,0 +1,18 @@
+// Any source either assembles or gives an error, and what assembles survives the formatter
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use stackofstacks::assembler::assemble;
+use stackofstacks::format::{self, Options};
+
+fuzz_target!(|source:&[u8]| {
+    let Ok(program) = assemble(source.to_vec()) else {return};
+
+    for offset in 0..program.code.len(){
+        let _ = program.symbolise(offset);
+    }
+
+    let formatted = format::format(source, &Options::default()).expect("formatting an assembling source");
+    assert_eq!(assemble(formatted).unwrap().code, program.code);
+});
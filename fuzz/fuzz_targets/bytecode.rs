@@ -0,0 +1,13 @@
+// Decoding never fails and compiling the decoded code gives the bytecode back
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use stackofstacks::assembler::{assemble, disassemble};
+use stackofstacks::bytecode::{bytecode, compile};
+
+fuzz_target!(|bytes:&[u8]| {
+    let code = bytecode(bytes);
+    assert_eq!(compile(&code), bytes);
+    assert_eq!(assemble(disassemble(&code)).unwrap().code, code);
+});
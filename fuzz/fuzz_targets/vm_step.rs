@@ -0,0 +1,25 @@
+// Runs arbitrary bytecode for a bounded number of steps, in both modes, with arbitrary input
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use stackofstacks::bytecode::bytecode;
+use stackofstacks::vm::{BufferIo, Effect, Vm};
+
+const MAX_STEPS:u64 = 10_000;
+
+fuzz_target!(|data:(bool, Vec<u8>, Vec<u8>)| {
+    let (strict, program, input) = data;
+    if program.is_empty() {return}
+
+    let mut vm = Vm::new(bytecode(&program), strict);
+    let mut io = BufferIo::new(input);
+    for _ in 0..MAX_STEPS{
+        match vm.step(&mut io){
+            Ok(Effect::Halt) => break,
+            Ok(_) if vm.cp < vm.code.len() => {},
+            Ok(effect) => panic!("cp {} left the code after {:?}", vm.cp, effect),
+            Err(_) => break,
+        }
+    }
+});
@@ -0,0 +1,306 @@
+// Static stack effect analysis
+//
+// Follows every path through the control flow graph that cfg can recover and computes, for every
+// instruction, the range of heights both stacks can have right before it executes. Heights are
+// tracked separately for each possible active stack so '$' keeps them apart precisely. Loops
+// that keep pushing are widened to an unbounded height, which is what gets reported as growth.
+
+use std::fmt;
+
+use crate::cfg::{self, Target};
+
+// Number of times an instruction's state may grow before its heights are widened
+const WIDEN_AFTER:u32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interval{
+    pub lo:usize,
+    pub hi:Option<usize>, // None is unbounded
+}
+
+impl Interval{
+    pub fn exact(n:usize) -> Interval{
+        Interval{lo: n, hi: Some(n)}
+    }
+
+    fn join(self, other:Interval) -> Interval{
+        Interval{
+            lo: self.lo.min(other.lo),
+            hi: match (self.hi, other.hi) {(Some(a), Some(b)) => Some(a.max(b)), _ => None},
+        }
+    }
+
+    // Jump to the extremes wherever other went beyond self
+    fn widen(self, other:Interval) -> Interval{
+        Interval{
+            lo: if other.lo < self.lo {0} else {self.lo},
+            hi: match (self.hi, other.hi) {(Some(a), Some(b)) if b <= a => Some(a), _ => None},
+        }
+    }
+
+    fn pop_push(self, pops:usize, pushes:usize, strict:bool) -> Interval{
+        // in strict mode the paths that underflow fault, so only the ones that had enough go on
+        let lo = if strict {self.lo.max(pops)} else {self.lo};
+        Interval{
+            lo: lo.saturating_sub(pops) + pushes,
+            hi: self.hi.map(|hi| hi.saturating_sub(pops) + pushes),
+        }
+    }
+}
+
+impl fmt::Display for Interval{
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result{
+        match self.hi{
+            Some(hi) if hi == self.lo => write!(f, "{}", hi),
+            Some(hi) => write!(f, "{}..{}", self.lo, hi),
+            None => write!(f, "{}..", self.lo),
+        }
+    }
+}
+
+// Heights of stack 0 and 1 before an instruction, for each stack that may be the active one then
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct State{
+    pub by_active:[Option<[Interval;2]>;2],
+}
+
+impl State{
+    fn join(self, other:State, widen:bool) -> State{
+        let mut by_active = self.by_active;
+        for (mine, theirs) in by_active.iter_mut().zip(other.by_active){
+            *mine = match (*mine, theirs){
+                (Some(a), Some(b)) if widen => Some([a[0].widen(a[0].join(b[0])), a[1].widen(a[1].join(b[1]))]),
+                (Some(a), Some(b)) => Some([a[0].join(b[0]), a[1].join(b[1])]),
+                (a, b) => a.or(b),
+            };
+        }
+        State{by_active}
+    }
+
+    // Heights of stack 0 and 1 whichever stack is active
+    pub fn heights(&self) -> [Option<Interval>;2]{
+        let mut heights = [None, None];
+        for stacks in self.by_active.iter().flatten(){
+            for (height, interval) in heights.iter_mut().zip(stacks){
+                *height = Some(height.map_or(*interval, |h:Interval| h.join(*interval)));
+            }
+        }
+        heights
+    }
+}
+
+// Items popped from and pushed to (active, other) stack
+fn stack_effect(op:u8) -> ((usize, usize), (usize, usize)){
+    match op{
+        b'!' | b'?' => ((0, 1), (0, 0)),
+        b'^' | b'|' | b'&' | b'+' | b'-' | b'*' | b'/' => ((2, 1), (0, 0)),
+        b'=' => ((1, 2), (0, 0)),
+        b'@' | b'.' => ((1, 0), (0, 0)),
+        b'0' | b'1' => ((1, 1), (0, 0)),
+        b'~' => ((1, 1), (1, 1)),
+        _ => ((0, 0), (0, 0)), // '$'
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity{
+    Note,
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Finding{
+    Underflow{offset:usize, stack:usize, certain:bool},
+    Unbounded{offset:usize, stack:usize},
+    Unreachable{start:usize, end:usize}, // end is exclusive
+    DynamicJump{offset:usize},
+    OutOfCode{offset:usize},
+}
+
+impl Finding{
+    pub fn offset(&self) -> usize{
+        match self{
+            Finding::Underflow{offset, ..} | Finding::Unbounded{offset, ..} | Finding::DynamicJump{offset} | Finding::OutOfCode{offset} => *offset,
+            Finding::Unreachable{start, ..} => *start,
+        }
+    }
+
+    pub fn severity(&self, strict:bool) -> Severity{
+        match self{
+            Finding::Underflow{certain: true, ..} | Finding::OutOfCode{..} if strict => Severity::Error,
+            Finding::DynamicJump{..} => Severity::Note,
+            _ => Severity::Warning,
+        }
+    }
+}
+
+impl fmt::Display for Finding{
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result{
+        match self{
+            Finding::Underflow{stack, certain: true, ..} => write!(f, "stack {} underflows here", stack),
+            Finding::Underflow{stack, certain: false, ..} => write!(f, "stack {} may underflow here", stack),
+            Finding::Unbounded{stack, ..} => write!(f, "stack {} may grow without bound in this loop", stack),
+            Finding::Unreachable{start, end} => write!(f, "unreachable code ({} instructions)", end - start),
+            Finding::DynamicJump{..} => write!(f, "jump target is not constant, paths through it are not analysed"),
+            Finding::OutOfCode{..} => write!(f, "execution may leave code memmory here"),
+        }
+    }
+}
+
+// Which stack (if any) op can pop more from than it holds, and whether it does so for every height in range
+fn underflow(stacks:&[Interval;2], active:usize, op:u8) -> Option<(usize, bool)>{
+    let ((pops, _), (other_pops, _)) = stack_effect(op);
+    let mut found = None;
+
+    // active stack first, just like the VM pops
+    for (stack, needed) in [(active, pops), (!active&1, other_pops)]{
+        let height = stacks[stack];
+        if height.hi.is_some_and(|hi| hi < needed){
+            return Some((stack, true));
+        }
+        if height.lo < needed && found.is_none(){
+            found = Some((stack, false));
+        }
+    }
+
+    found
+}
+
+pub struct Analysis{
+    pub states:Vec<Option<State>>, // per instruction, None when never reached
+    pub findings:Vec<Finding>,     // sorted by offset
+    pub dynamic:bool,              // a reachable jump could not be resolved
+}
+
+impl Analysis{
+    pub fn heights(&self, offset:usize) -> [Option<Interval>;2]{
+        self.states.get(offset).copied().flatten().map_or([None, None], |s| s.heights())
+    }
+}
+
+pub fn analyse(code:&[u8], strict:bool) -> Analysis{
+    let mut states:Vec<Option<State>> = vec![None; code.len()];
+    let mut visits:Vec<u32> = vec![0; code.len()];
+    let mut findings:Vec<Finding> = vec!();
+    let mut dynamic = false;
+
+    if code.is_empty(){
+        return Analysis{states, findings, dynamic};
+    }
+
+    let mut unbounded:Vec<[bool;2]> = vec![[false;2]; code.len()];
+    let mut out_of_code:Vec<bool> = vec![false; code.len()];
+    let mut dynamic_jumps:Vec<bool> = vec![false; code.len()];
+    let jumps = cfg::jumps(code);
+
+    let start = Interval::exact(0);
+    states[0] = Some(State{by_active: [Some([start, start]), None]});
+    let mut worklist:Vec<usize> = vec!(0);
+
+    while let Some(offset) = worklist.pop(){
+        let Some(state) = states[offset] else {continue};
+        let op = code[offset];
+        let ((pops, pushes), (other_pops, other_pushes)) = stack_effect(op);
+
+        let mut next = State{by_active: [None, None]};
+        for (active, stacks) in state.by_active.iter().enumerate(){
+            let Some(stacks) = stacks else {continue};
+            let other = !active&1;
+
+            if strict && underflow(stacks, active, op).is_some_and(|(_, certain)| certain){
+                continue;
+            }
+
+            let mut after = *stacks;
+            after[active] = after[active].pop_push(pops, pushes, strict);
+            after[other] = after[other].pop_push(other_pops, other_pushes, strict);
+
+            let new_active = if op == b'$' {other} else {active};
+            next.by_active[new_active] = Some(match next.by_active[new_active]{
+                Some(existing) => [existing[0].join(after[0]), existing[1].join(after[1])],
+                None => after,
+            });
+        }
+
+        if next.by_active == [None, None] {continue}
+
+        let successors = cfg::successors(code, &jumps, offset, strict);
+        if successors.dynamic{
+            dynamic = true;
+            dynamic_jumps[offset] = true;
+        }
+
+        for (target, _) in successors.targets{
+            match target{
+                Target::Offset(target) => {
+                    let merged = match states[target]{
+                        None => next,
+                        Some(existing) => {
+                            let joined = existing.join(next, false);
+                            if joined == existing {continue}
+                            if visits[target] < WIDEN_AFTER {
+                                joined
+                            }else{
+                                // only report growth where widening gave up on a bound, not where an unbounded height flowed in
+                                let widened = existing.join(next, true);
+                                for (i, (before, after)) in joined.heights().iter().zip(widened.heights()).enumerate(){
+                                    if before.is_some_and(|h| h.hi.is_some()) && after.is_some_and(|h| h.hi.is_none()){
+                                        unbounded[target][i] = true;
+                                    }
+                                }
+                                widened
+                            }
+                        },
+                    };
+                    visits[target] += 1;
+                    states[target] = Some(merged);
+                    worklist.push(target);
+                },
+                Target::OutOfCode => out_of_code[offset] = true,
+                Target::Halt => {},
+            }
+        }
+    }
+
+    for offset in 0..code.len(){
+        if let Some(state) = states[offset]{
+            let variants:Vec<Option<(usize, bool)>> = state.by_active.iter().enumerate()
+                .filter_map(|(active, stacks)| stacks.map(|stacks| underflow(&stacks, active, code[offset])))
+                .collect();
+            if let Some((stack, _)) = variants.iter().flatten().next(){
+                let certain = variants.iter().all(|v| v.is_some_and(|(_, certain)| certain));
+                findings.push(Finding::Underflow{offset, stack: *stack, certain});
+            }
+        }
+        for (stack, grows) in unbounded[offset].iter().enumerate(){
+            if *grows{
+                findings.push(Finding::Unbounded{offset, stack});
+            }
+        }
+        if out_of_code[offset]{
+            findings.push(Finding::OutOfCode{offset});
+        }
+        if dynamic_jumps[offset]{
+            findings.push(Finding::DynamicJump{offset});
+        }
+    }
+
+    // with unresolved jumps around anything not reached may still be a target of one of those
+    if !dynamic{
+        let mut offset = 0;
+        while offset < code.len(){
+            if states[offset].is_none(){
+                let start = offset;
+                while offset < code.len() && states[offset].is_none(){
+                    offset += 1;
+                }
+                findings.push(Finding::Unreachable{start, end: offset});
+            }
+            offset += 1;
+        }
+    }
+    findings.sort_by_key(Finding::offset);
+
+    Analysis{states, findings, dynamic}
+}
@@ -0,0 +1,511 @@
+use std::fmt;
+use std::collections::HashMap;
+
+use crate::TOKENS;
+
+// 1 based line and column in the source file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Location{
+    pub line:usize,
+    pub column:usize,
+}
+
+impl Location{
+    // Where the byte after text ends up when text starts here (CR is not counted, just like tokenise does)
+    pub fn advance(mut self, text:&[u8]) -> Location{
+        for b in text{
+            if *b == b'\n'{
+                self.line += 1;
+                self.column = 1;
+            }else if *b != b'\r'{
+                self.column += 1;
+            }
+        }
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error{
+    pub message:String,
+    pub location:Option<Location>,
+}
+
+impl Error{
+    fn new(message:String, location:Option<Location>) -> Error{
+        Error{message, location}
+    }
+}
+
+impl fmt::Display for Error{
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result{
+        write!(f, "{}", self.message)
+    }
+}
+
+// Label names used in a macro with their start within the macro text
+type References = Vec<(Vec<u8>, usize)>;
+
+// Computes the value of a macro, also returns the labels it refers to
+fn evaluate(input:&[u8], labels:&HashMap<Vec<u8>,usize>) -> Result<(i64, References), String>{
+
+    #[derive(Debug)]
+    enum Token{
+        Number(Vec<u8>, usize),
+        Operator(u8),
+    }
+
+    let mut tokens:Vec<Token> = vec!();
+    let mut buffer:Vec<u8> = vec!();
+    let mut start = 0;
+
+    for (index, c) in input.iter().enumerate(){
+
+        if [b'+',b'-'].contains(c){
+            if !buffer.is_empty() {
+                tokens.push(Token::Number(buffer, start));
+                buffer = vec!();
+            }
+
+            tokens.push(Token::Operator(*c));
+            start = index+1;
+        }else{
+            buffer.push(*c);
+        }
+
+    }
+    if !buffer.is_empty() {
+        tokens.push(Token::Number(buffer, start));
+    }
+
+    //check well formed ness
+    //check for empty macro
+    if tokens.is_empty(){
+        return Err("Macro parsing error: Empty macro!".to_owned());
+    }
+
+    //if start with operator eg: -1 the  prepend 0 so -4 becomes 0-4
+    if matches!(tokens.first(), Some(Token::Operator(_))){
+        tokens = {let mut x = vec!(Token::Number(vec!(b'0'), 0)); x.extend(tokens); x};
+    }
+
+    //check if macro ends with Number
+    if !matches!(tokens.last(), Some(Token::Number(..))){
+        return Err(format!("Macro parsing error: Macro cannot end with operator: [{}]", String::from_utf8_lossy(input)));
+    }
+
+    //chekc if macro has format of: number (operator number)*
+    let mut should_be_number = true;
+    for token in &tokens{
+
+        match token{
+            Token::Number(..) => {
+                if !should_be_number{
+                    return Err(format!("Macro parsing error: Unexpected (extra) Number in macro: [{}]", String::from_utf8_lossy(input)));
+                }
+            },
+            Token::Operator(_) => {
+                if should_be_number{
+                    return Err(format!("Macro parsing error: Unexpected (extra) Operator in macro: [{}]", String::from_utf8_lossy(input)));
+                }
+            },
+        }
+
+        should_be_number = !should_be_number;
+    }
+
+    #[derive(Debug)]
+    enum T2Token{
+        Int(i64),
+        Add,
+        Sub,
+    }
+
+    let mut references:References = vec!();
+
+    //Validate all tokens individually, and return a list of T2 tokens
+    let mut resolve_tokens = |tokens:Vec<Token>| -> Result<Vec<T2Token>, String>{
+        let mut out = vec!();
+
+        for token in &tokens{
+
+            let new_token = match token{
+                Token::Number(v, start) => {
+
+                    let Ok(s) = std::str::from_utf8(v) else {
+                        return Err(format!("Macro parsing error: '{}' is an invalid number representation", String::from_utf8_lossy(v)));
+                    };
+
+                    let int = match s.parse(){
+                        Ok(int) => int, //int parsed
+                        Err(_) => {
+
+                            let int;
+                            // You want to use from_str_radix. It's implemented on the integer types.
+                            if v.len()==3 && v[0] == b'\'' && v[2] == b'\'' { //matches 'X' notation
+                                int = i64::from(v[1]);
+
+                            }else if let [b'0', prefix @ (b'b' | b'x' | b'o' | b'd'), ..] = v[..]{
+                                let number_string = &s[2..];
+                                let radix = match prefix{
+                                    b'b' => 2,
+                                    b'o' => 8,
+                                    b'd' => 10, //Just to be complete
+                                    _ => 16,
+                                };
+
+                                int = match i64::from_str_radix(number_string, radix){
+                                    Ok(i) => i,
+                                    Err(_) => {
+                                        return Err(format!("Macro parsing error: '{}' is an invalid number representation", s));
+                                    },
+                                }
+                            }else{
+                                int = match labels.get(v){
+                                    Some(u) => *u as i64,
+                                    None => {
+                                        return Err(format!("Macro parsing error: Label '{}' not found in labels", s));
+                                    }
+                                };
+                                references.push((v.clone(), *start));
+                            }
+
+                            int
+                        },
+                    };
+
+
+                    T2Token::Int(int)
+                }
+                Token::Operator(b'+') => T2Token::Add,
+                Token::Operator(_) => T2Token::Sub, // only '+' and '-' split numbers
+            };
+
+            out.push(new_token);
+        }
+
+        Ok(out)
+
+    };
+
+    let t2tokens = resolve_tokens(tokens)?;
+
+    // checked above to be a number followed by pairs of an operator and a number
+    let malformed = || format!("Macro parsing error: Malformed macro: [{}]", String::from_utf8_lossy(input));
+    let [T2Token::Int(first), rest @ ..] = &t2tokens[..] else {return Err(malformed())};
+
+    let mut acc = *first;
+
+    for pair in rest.chunks(2){
+        acc = match pair{
+            [T2Token::Add, T2Token::Int(num)] => acc.wrapping_add(*num),
+            [T2Token::Sub, T2Token::Int(num)] => acc.wrapping_sub(*num),
+            _ => return Err(malformed()),
+        };
+    }
+
+    Ok((acc, references))
+}
+
+fn expand(value:i64)->Vec<u8>{
+
+    let ret = format!("!{:064b}", value);
+    assert!(ret.len() == 65); //Must be 65 characters wide
+
+
+    for token in ret.bytes(){
+        if !TOKENS.contains(&token){
+            panic!("INTERPRETER's FAULT: Invalid tokens in macro output!");
+        }
+    }
+
+    Vec::from(ret)
+}
+
+#[derive(Debug)]
+pub enum Token{
+    Script(Vec<u8>),
+    Macro(Vec<u8>),
+    Label(Vec<u8>),
+}
+
+
+pub fn tokenise(script_bytes:Vec<u8>) -> Result<Vec<Token>, Error>{
+    Ok(tokenise_located(script_bytes)?.into_iter().map(|(token, _)| token).collect())
+}
+
+// Same as tokenise but also tells where every token came from: one location per instruction
+// for script tokens, the location of the opening '[' or ':' for macros and labels
+fn tokenise_located(script_bytes:Vec<u8>) -> Result<Vec<(Token, Vec<Location>)>, Error>{
+
+    #[derive(Copy, Clone)]
+    enum State{
+        Script,
+        Comment,
+        Macro,
+        Label,
+    }
+
+    let mut tokenised_script:Vec<(Token, Vec<Location>)>  = vec!();
+    let mut buffer:Vec<u8> = vec!();
+    let mut locations:Vec<Location> = vec!();
+
+    let mut state = State::Script;
+    let mut line_count = 1;
+    let mut char_count = 1;
+
+    for token in script_bytes{
+
+        let location = Location{line: line_count, column: char_count};
+        if token >= 0x80 {
+            return Err(Error::new(format!("Parsing error: Illegal (Non ASCII) character found at {}:{}", line_count, char_count), Some(location)));
+        }
+        if token != 0x0D{ //CR not counted
+            char_count += 1;
+        }
+        if token == 0x0A{
+            char_count = 1;
+            line_count += 1;
+        }
+
+
+        match state{
+            State::Script =>{
+                if TOKENS.contains(&token){
+                    buffer.push(token);
+                    locations.push(location);
+                }else{
+                    match token{
+                        b'#' => {
+                            state = State::Comment;
+                        },
+                        b'[' => {
+                            tokenised_script.push((Token::Script(buffer), locations));
+                            buffer = vec!();
+                            locations = vec!(location);
+
+                            state = State::Macro;
+                        },
+                        b':' => {
+                            tokenised_script.push((Token::Script(buffer), locations));
+                            buffer = vec!();
+                            locations = vec!(location);
+
+                            state = State::Label;
+                        },
+                        _ => (), //preceived as comment
+                    }
+                }
+            },
+            State::Comment =>{
+                if token == b'\n' {
+                    state = State::Script;
+                }
+            },
+            State::Macro =>{
+                match token{
+                    b']' => {
+
+                        tokenised_script.push((Token::Macro(buffer), locations));
+                        buffer = vec!();
+                        locations = vec!();
+
+                        state = State::Script;
+                    },
+                    b => {
+                        buffer.push(b);
+                    }
+                }
+            }
+            State::Label =>{
+                if token.is_ascii_lowercase(){
+                    buffer.push(token);
+                }else{
+
+                    tokenised_script.push((Token::Label(buffer), locations));
+                    buffer = vec!();
+                    locations = vec!();
+
+                    state = State::Script;
+                }
+            }
+        }
+
+
+    }
+
+    match state{
+        State::Script =>{
+            tokenised_script.push((Token::Script(buffer), locations));
+        },
+        State::Comment =>{
+            //not needed here, its just discarded while interwoven with Source state
+        },
+        State::Macro =>{
+            return Err(Error::new("Macro violation: Macro is not closed by EOF.".to_owned(), locations.first().copied()));
+        },
+        State::Label =>{
+            tokenised_script.push((Token::Label(buffer), locations));
+        },
+    }
+
+    Ok(tokenised_script)
+}
+
+// Offset of every label in order of definition, macros are fixed size so this only needs the tokens
+fn label_offsets(tokens:&[Token]) -> Vec<(Vec<u8>, usize)>{
+
+    let mut labels:Vec<(Vec<u8>, usize)> = vec!();
+    let mut index = 0;
+
+    for token in tokens{
+        match token{
+            Token::Script(v) => {
+                index += v.len();
+            },
+            Token::Macro(_) => {
+                //And heres the crux, we need to know NOW how long expanded macro size will be, and macro needs the label offset chicken and egg story
+                //For now macro's have output size fixed at 65 !+binary number
+                index += 65;
+            },
+            Token::Label(v) => {
+                labels.push((v.to_vec(), index));
+            },
+        }
+    }
+
+    labels
+}
+
+//parse to pure_script
+pub fn parse(tokens:&[Token]) -> Result<Vec<u8>, Error>{
+
+    let labels:HashMap<Vec<u8>, usize> = label_offsets(tokens).into_iter().collect();
+    let mut pure_script:Vec<u8> = vec!();
+
+    for token in tokens{
+        match token{
+            Token::Script(v) => {
+                pure_script.extend(v);
+            },
+            Token::Macro(v) => {
+                let (value, _) = evaluate(v, &labels).map_err(|message| Error::new(message, None))?;
+                pure_script.extend(expand(value));
+            },
+            Token::Label(_) => {},
+        }
+    }
+
+    Ok(pure_script)
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Label{
+    pub name:String,
+    pub offset:usize,
+    pub location:Location, // of the ':'
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Macro{
+    pub text:String,                         // between the brackets
+    pub offset:usize,                        // of the expanded push in code
+    pub location:Location,                   // of the '['
+    pub value:i64,
+    pub references:Vec<(String, Location)>,  // labels used and where
+}
+
+// A parsed program together with what is needed to map offsets in the pure script back to the source
+pub struct Program{
+    pub code:Vec<u8>,
+    pub source:Vec<u8>,
+    pub labels:Vec<Label>,          // in order of definition, which is also offset order
+    pub macros:Vec<Macro>,
+    pub locations:Vec<Location>,    // one per instruction in code (empty when there is no source)
+}
+
+impl Program{
+    // A program without source, eg: decoded bytecode
+    pub fn from_code(code:Vec<u8>) -> Program{
+        Program{code, source: vec!(), labels: vec!(), macros: vec!(), locations: vec!()}
+    }
+
+    pub fn location(&self, offset:usize) -> Option<Location>{
+        self.locations.get(offset).copied()
+    }
+
+    // The label whose region holds offset (a region runs up to the next label) and the distance into it
+    pub fn label_at(&self, offset:usize) -> Option<(&str, usize)>{
+        // labels are defined front to back, so they are sorted by offset already
+        let index = self.labels.partition_point(|l| l.offset <= offset).checked_sub(1)?;
+        let label = &self.labels[index];
+        Some((label.name.as_str(), offset - label.offset))
+    }
+
+    // Name for offset in reports: label+distance or the bare offset when there are no labels before it
+    pub fn symbolise(&self, offset:usize) -> String{
+        match self.label_at(offset){
+            Some((name, 0)) => name.to_owned(),
+            Some((name, distance)) => format!("{}+{}", name, distance),
+            None => format!("{}", offset),
+        }
+    }
+}
+
+pub fn assemble(script_bytes:Vec<u8>) -> Result<Program, Error>{
+
+    let source = script_bytes.clone();
+    let (tokens, token_locations):(Vec<Token>, Vec<Vec<Location>>) = tokenise_located(script_bytes)?.into_iter().unzip();
+
+    let label_map:HashMap<Vec<u8>, usize> = label_offsets(&tokens).into_iter().collect();
+
+    let mut code:Vec<u8> = vec!();
+    let mut locations:Vec<Location> = vec!();
+    let mut labels:Vec<Label> = vec!();
+    let mut macros:Vec<Macro> = vec!();
+
+    for (token, token_locations) in tokens.iter().zip(token_locations){
+        match token{
+            Token::Script(v) => {
+                code.extend(v);
+                locations.extend(token_locations);
+            },
+            Token::Macro(v) => {
+                let location = token_locations[0];
+                let (value, references) = evaluate(v, &label_map).map_err(|message| Error::new(message, Some(location)))?;
+                let inside = location.advance(b"[");
+
+                macros.push(Macro{
+                    text: String::from_utf8(v.clone()).unwrap(),
+                    offset: code.len(),
+                    location,
+                    value,
+                    references: references.into_iter()
+                        .map(|(name, start)| (String::from_utf8(name).unwrap(), inside.advance(&v[..start])))
+                        .collect(),
+                });
+                code.extend(expand(value));
+                locations.extend([location; 65]);
+            },
+            Token::Label(v) => {
+                labels.push(Label{name: String::from_utf8(v.clone()).unwrap(), offset: code.len(), location: token_locations[0]});
+            },
+        }
+    }
+
+    Ok(Program{code, source, labels, macros, locations})
+}
+
+// Instructions per line of disassembly
+const DISASSEMBLY_WIDTH:usize = 64;
+
+// Source for code (eg: decoded bytecode) that assembles back to exactly that code, with offsets in comments
+pub fn disassemble(code:&[u8]) -> Vec<u8>{
+    let mut out = vec!();
+    for (index, chunk) in code.chunks(DISASSEMBLY_WIDTH).enumerate(){
+        out.extend(chunk);
+        out.extend(format!("\t# {:#06X}\n", index * DISASSEMBLY_WIDTH).bytes());
+    }
+    out
+}
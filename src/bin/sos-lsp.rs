@@ -0,0 +1,16 @@
+// Language server for Stack Of Stacks, talks LSP over STDIN/STDOUT
+
+use std::io::{stdin, stdout};
+use std::process::exit;
+
+use stackofstacks::lsp;
+
+fn main() {
+    match lsp::serve(stdin().lock(), stdout().lock()){
+        Ok(code) => exit(code),
+        Err(e) => {
+            eprintln!("sos-lsp: {}", e);
+            exit(1);
+        }
+    }
+}
@@ -0,0 +1,840 @@
+// Brainfuck to Stack Of Stacks
+//
+// The tape lives on the two stacks: the cell under the head on top of stack 0 with the cells left
+// of it below, the cells right of the head on stack 1, nearest on top. Cells are kept complemented
+// (-1-c), so the -1 popped from an empty stack reads as a 0 cell and the tape is endless both
+// ways. That needs the program to run without --strict. Moving the head pushes a -1 for '~' to
+// swap against the next cell and drops it again by and-ing it into the cell below. Cells are
+// bytes as far as anyone can tell: only the low 8 bits count when testing for 0 or writing. ','
+// sets the cell to 0 on EOF. '[' and ']' become 'K*@' jumps to labels after the matching bracket.
+//
+// Stack Of Stacks to Brainfuck
+//
+// A value is 64 cells holding one bit each, bit 0 first, behind a marker cell that is 1 for every
+// value on a stack. Together that makes a slot. Home is a row of cells in the middle of the tape
+// with CP (in bits), the active stack and flags. Stack 0's slots go to the right of it and stack
+// 1's to the left, mirrored, so the code working on a stack is the same for both with '<' and '>'
+// swapped. Getting to the top of a stack is a walk over the markers, and the cells past the top
+// are free to work in. Each step copies CP, finds the instruction at CP in a tree of tests on its
+// bits and notes the operation and the next CP. The code for every operation is there once.
+// Values only cross between a stack and home (a jump offset, '~') a bit at a time, with a walk
+// for every bit set. Popping an empty stack pushes -1s under what is left first; in strict mode
+// that and running out of code end the program like a halt does. ',' can't tell a 0 byte from
+// EOF, so 0 bytes read as EOF. That needs a tape endless both ways like interpret has.
+
+use std::fmt::Write;
+
+use crate::vm::Stop;
+use crate::TOKENS;
+
+pub const COMMANDS:[u8; 8] = [b'+', b'-', b'<', b'>', b'[', b']', b',', b'.'];
+
+// Code for a command that moves or does I/O
+fn command(command:u8) -> &'static str{
+    match command{
+        b'>' => "!~$&$",
+        b'<' => "$!~$&",
+        b',' => "=^?=!-=/*!^^", // clears the cell, reads r and makes it 0 on EOF: r * (r+1 != 0)
+        b'.' => "=!^.",
+        _ => unreachable!("+, - and brackets are translated on their own"),
+    }
+}
+
+// Leaves 1 on top when the cell is not 0 and 0 when it is
+const NOT_ZERO:&str = "=!^!!^11111111&=/";
+
+// Lower case name for n, labels can only have those
+fn name(mut n:usize) -> String{
+    let mut name = String::new();
+    loop{
+        name.push((b'a' + (n % 26) as u8) as char);
+        n /= 26;
+        if n == 0 {return name}
+    }
+}
+
+// Adding delta to the cell, which takes away from what is kept
+fn add(delta:i64) -> String{
+    match delta{
+        0 => "".to_owned(),
+        1 => "!+".to_owned(),
+        -1 => "!-".to_owned(),
+        _ if delta > 0 => format!("!!^{:b}-", delta),
+        _ => format!("!!^{:b}+", delta.unsigned_abs()),
+    }
+}
+
+// Source for program, one line for every line with commands on it, those commands in a comment
+pub fn transpile(program:&[u8]) -> Result<String, String>{
+    let mut out = "#!./target/release/stackofstacks\n# translated from Brainfuck, runs without --strict\n\n".to_owned();
+    let mut open:Vec<(usize, usize, usize)> = vec!(); // loop number, line and column of the '['
+    let mut loops = 0;
+
+    for (index, line) in program.split(|b| *b == b'\n').enumerate(){
+        let commands:Vec<(usize, u8)> = line.iter().copied().enumerate().filter(|(_, b)| COMMANDS.contains(b)).collect();
+        if commands.is_empty() {continue}
+
+        let mut code = String::new();
+        let mut rest = &commands[..];
+        while let Some((column, first)) = rest.first(){
+            match first{
+                b'+' | b'-' => {
+                    // a run of them adds up to a single push
+                    let run = rest.iter().take_while(|(_, b)| matches!(b, b'+' | b'-')).count();
+                    code.push_str(&add(rest[..run].iter().map(|(_, b)| if *b == b'+' {1} else {-1}).sum()));
+                    rest = &rest[run..];
+                    continue;
+                },
+                b'[' => {
+                    let _ = write!(code, "{}!!^1^[end{n}-body{n}]*@:body{n} ", NOT_ZERO, n = name(loops));
+                    open.push((loops, index+1, column+1));
+                    loops += 1;
+                },
+                b']' => {
+                    let Some((n, _, _)) = open.pop() else {
+                        return Err(format!("Unmatched ']' at {}:{}", index+1, column+1));
+                    };
+                    let _ = write!(code, " {}[body{n}-end{n}]*@:end{n} ", NOT_ZERO, n = name(n));
+                },
+                _ => code.push_str(command(*first)),
+            }
+            rest = &rest[1..];
+        }
+        let commands:Vec<u8> = commands.iter().map(|(_, b)| *b).collect();
+        let _ = writeln!(out, "{}\t# {}", code.trim(), String::from_utf8_lossy(&commands));
+    }
+
+    if let Some((_, line, column)) = open.last(){
+        return Err(format!("Unmatched '[' at {}:{}", line, column));
+    }
+    out.push_str("!@\n");
+    Ok(out)
+}
+
+// Runs program with byte cells on a tape endless both ways, ',' reads 0 on EOF.
+// This is what a translated program has to do.
+pub fn interpret(program:&[u8], input:&[u8], max_steps:u64) -> Result<(Vec<u8>, Stop), String>{
+    let commands:Vec<u8> = program.iter().copied().filter(|b| COMMANDS.contains(b)).collect();
+    let mut matching = vec![0; commands.len()];
+    let mut open = vec!();
+    for (index, c) in commands.iter().enumerate(){
+        match c{
+            b'[' => open.push(index),
+            b']' => {
+                let start = open.pop().ok_or("Unmatched ']'")?;
+                matching[start] = index;
+                matching[index] = start;
+            },
+            _ => {},
+        }
+    }
+    if !open.is_empty() {return Err("Unmatched '['".to_owned())}
+
+    let mut tape = vec![0u8; 1];
+    let mut head = 0;
+    let mut input = input.iter();
+    let mut output = vec!();
+    let mut pc = 0;
+    let mut steps = 0;
+    while pc < commands.len(){
+        if steps == max_steps {return Ok((output, Stop::StepLimit))}
+        steps += 1;
+        match commands[pc]{
+            b'+' => tape[head] = tape[head].wrapping_add(1),
+            b'-' => tape[head] = tape[head].wrapping_sub(1),
+            b'>' => {
+                head += 1;
+                if head == tape.len() {tape.push(0)}
+            },
+            b'<' => {
+                if head == 0 {tape.insert(0, 0)} else {head -= 1}
+            },
+            b',' => tape[head] = input.next().copied().unwrap_or(0),
+            b'.' => output.push(tape[head]),
+            b'[' => if tape[head] == 0 {pc = matching[pc]},
+            _ => if tape[head] != 0 {pc = matching[pc]},
+        }
+        pc += 1;
+    }
+    Ok((output, Stop::Halted))
+}
+
+
+const SLOT:i64 = 65; // marker and 64 bits
+
+// Cells of a slot, counted from the first free one (0) towards the bottom (-1 is the top)
+fn marker(slot:i64) -> i64{
+    slot*SLOT
+}
+
+fn bit(slot:i64, index:i64) -> i64{
+    slot*SLOT + 1 + index
+}
+
+// Bit index of track in a work area past the top with tracks bits of a value side by side
+fn work(tracks:i64, index:i64, track:i64) -> i64{
+    1 + index*tracks + track
+}
+
+// Cell n after that work area
+fn scratch(tracks:i64, n:i64) -> i64{
+    1 + 64*tracks + n
+}
+
+// Home cells, CP and its copy take bits cells each
+const RUN:i64 = 0; // 0 once halted
+const ACTIVE:i64 = 1;
+const EXECUTE:i64 = 2; // set when the operation cells tell what to do
+const TEMPORARY:i64 = 3;
+const ACTIVE_COPY:i64 = 4;
+const ACTIVE_FLAG:i64 = 5;
+const OPERATION:i64 = 6; // 4 bits, index in TOKENS
+const OPERATION_FLAGS:i64 = 10;
+const CP:i64 = 14;
+
+// Brainfuck being written and where it leaves the head
+struct Emitter{
+    code:String,
+    pos:i64,
+    frame:Option<usize>, // stack whose top pos counts from, None for home
+    bits:i64,            // of CP
+    strict:bool,
+}
+
+impl Emitter{
+    fn cp(&self, index:i64) -> i64{
+        CP + index
+    }
+
+    fn copy(&self, index:i64) -> i64{
+        CP + self.bits + index
+    }
+
+    fn copy_flag(&self, index:i64) -> i64{
+        CP + 2*self.bits + index
+    }
+
+    // Stack 0's bottom marker, a slot away from the last home cell that stays 0 to stop walks
+    fn home(&self) -> i64{
+        CP + 3*self.bits + SLOT
+    }
+
+    fn go(&mut self, cell:i64){
+        let (right, left) = if self.frame == Some(1) {('<', '>')} else {('>', '<')};
+        let step = if cell > self.pos {right} else {left};
+        self.code.extend(std::iter::repeat_n(step, (cell - self.pos).unsigned_abs() as usize));
+        self.pos = cell;
+    }
+
+    fn add(&mut self, cell:i64, n:i64){
+        self.go(cell);
+        self.code.extend(std::iter::repeat_n(if n > 0 {'+'} else {'-'}, n.unsigned_abs() as usize));
+    }
+
+    fn clear(&mut self, cell:i64){
+        self.go(cell);
+        self.code.push_str("[-]");
+    }
+
+    // Loops while cell is not 0, body has to leave the head where it found it
+    fn repeat(&mut self, cell:i64, body:impl FnOnce(&mut Emitter)){
+        self.go(cell);
+        self.code.push('[');
+        let frame = self.frame;
+        body(self);
+        assert_eq!(self.frame, frame, "loop has to end where it started");
+        self.go(cell);
+        self.code.push(']');
+    }
+
+    // Runs body if cell is 1, leaving it 0
+    fn when(&mut self, cell:i64, body:impl FnOnce(&mut Emitter)){
+        self.repeat(cell, |e| {
+            e.add(cell, -1);
+            body(e);
+        });
+    }
+
+    fn branch(&mut self, cell:i64, flag:i64, then:impl FnOnce(&mut Emitter), otherwise:impl FnOnce(&mut Emitter)){
+        self.add(flag, 1);
+        self.when(cell, |e| {
+            e.add(flag, -1);
+            then(e);
+        });
+        self.when(flag, otherwise);
+    }
+
+    // Empties from into the cells, adding factor times its value to each
+    fn transfer(&mut self, from:i64, to:&[(i64, i64)]){
+        self.when(from, |e| {
+            for (cell, factor) in to{
+                e.add(*cell, *factor);
+            }
+        });
+    }
+
+    fn move_to(&mut self, from:i64, to:&[i64]){
+        self.repeat(from, |e| {
+            e.add(from, -1);
+            for cell in to{
+                e.add(*cell, 1);
+            }
+        });
+    }
+
+    // After pushing (slots 1) or popping (-1), so cells keep counting from the first free slot
+    fn rebase(&mut self, slots:i64){
+        self.pos -= slots*SLOT;
+    }
+
+    fn walk_out(&mut self, stack:usize){
+        assert_eq!(self.frame, None);
+        let (start, step) = if stack == 0 {(self.home(), '>')} else {(-1 - SLOT, '<')};
+        self.go(start);
+        let _ = write!(self.code, "[{}]", String::from(step).repeat(SLOT as usize));
+        self.frame = Some(stack);
+        self.pos = 0;
+    }
+
+    fn walk_home(&mut self){
+        let stack = self.frame.expect("walking home from home");
+        self.go(marker(-1));
+        let _ = write!(self.code, "[{}]", if stack == 0 {"<"} else {">"}.repeat(SLOT as usize));
+        self.frame = None;
+        self.pos = if stack == 0 {self.home() - SLOT} else {-1};
+    }
+
+    // Ends the program from the top of a stack
+    fn stop(&mut self){
+        let stack = self.frame.expect("stopping from home");
+        self.walk_home();
+        self.clear(RUN);
+        self.walk_out(stack);
+    }
+}
+
+// Cell is 1 - cell afterwards
+fn not(e:&mut Emitter, cell:i64, temporary:i64){
+    e.add(temporary, 1);
+    e.when(cell, |e| e.add(temporary, -1));
+    e.move_to(temporary, &[cell]);
+}
+
+// Splits the 0 to 3 in from into its low bit and its high bit, added to carry
+fn split(e:&mut Emitter, from:i64, low:i64, carry:i64){
+    e.repeat(from, |e| {
+        e.add(from, -1);
+        e.add(low, 1);
+        e.repeat(from, |e| {
+            e.add(from, -1);
+            e.add(low, -1);
+            e.add(carry, 1);
+            e.repeat(from, |e| {
+                e.add(from, -1);
+                e.add(low, 1);
+            });
+        });
+    });
+}
+
+// The bit for '&', '|' or '^' from the sum of two bits in from
+fn logic(e:&mut Emitter, token:u8, from:i64, to:i64){
+    e.repeat(from, |e| {
+        e.add(from, -1);
+        match token{
+            b'&' => e.repeat(from, |e| {
+                e.add(from, -1);
+                e.add(to, 1);
+            }),
+            b'|' => {
+                e.add(to, 1);
+                e.clear(from);
+            },
+            _ => {
+                e.add(to, 1);
+                e.repeat(from, |e| {
+                    e.add(from, -1);
+                    e.add(to, -1);
+                });
+            },
+        }
+    });
+}
+
+// Adds track b (if any) and the carries already in track carry to track a, leaving the sum in
+// track out and nothing in a and carry
+fn ripple(e:&mut Emitter, tracks:i64, a:i64, b:Option<i64>, carry:i64, out:i64, overflow:i64){
+    for index in 0..64{
+        let sum = work(tracks, index, a);
+        if let Some(b) = b{
+            e.move_to(work(tracks, index, b), &[sum]);
+        }
+        e.move_to(work(tracks, index, carry), &[sum]);
+        let next = if index < 63 {work(tracks, index+1, carry)} else {overflow};
+        split(e, sum, work(tracks, index, out), next);
+    }
+    e.clear(overflow);
+}
+
+fn negate(e:&mut Emitter, tracks:i64, track:i64, temporary:i64, carry:i64, overflow:i64){
+    for index in 0..64{
+        not(e, work(tracks, index, track), work(tracks, index, temporary));
+    }
+    e.add(work(tracks, 0, carry), 1);
+    ripple(e, tracks, track, None, carry, temporary, overflow);
+    for index in 0..64{
+        e.move_to(work(tracks, index, temporary), &[work(tracks, index, track)]);
+    }
+}
+
+fn shift_up(e:&mut Emitter, tracks:i64, track:i64){
+    e.clear(work(tracks, 63, track));
+    for index in (1..64).rev(){
+        e.move_to(work(tracks, index-1, track), &[work(tracks, index, track)]);
+    }
+}
+
+// Bit 0 has to be empty already
+fn shift_down(e:&mut Emitter, tracks:i64, track:i64){
+    for index in 0..63{
+        e.move_to(work(tracks, index+1, track), &[work(tracks, index, track)]);
+    }
+}
+
+// Makes sure the stack has depth values, pushing -1s under them. Ends strict programs when it has to.
+fn ensure(e:&mut Emitter, depth:i64){
+    let (empty, temporary) = (SLOT+1, SLOT+2);
+    for below in 1..=depth{
+        e.add(empty, 1);
+        e.transfer(marker(-below), &[(empty, -1), (temporary, 1)]);
+        e.move_to(temporary, &[marker(-below)]);
+        e.when(empty, |e| {
+            // only one value short at a time, the first free slot takes the top or -1
+            for index in 0..64{
+                if below == 2{
+                    e.move_to(bit(-1, index), &[bit(0, index)]);
+                    e.add(bit(-1, index), 1);
+                }else{
+                    e.add(bit(0, index), 1);
+                }
+            }
+            e.add(marker(0), 1);
+            if e.strict {e.stop()} else {e.rebase(1)}
+        });
+    }
+}
+
+fn push_ones(e:&mut Emitter){
+    e.add(marker(0), 1);
+    for index in 0..64{
+        e.add(bit(0, index), 1);
+    }
+    e.rebase(1);
+}
+
+fn shift(e:&mut Emitter, one:bool){
+    ensure(e, 1);
+    e.clear(bit(-1, 63));
+    for index in (1..64).rev(){
+        e.move_to(bit(-1, index-1), &[bit(-1, index)]);
+    }
+    if one {e.add(bit(-1, 0), 1)}
+}
+
+fn duplicate(e:&mut Emitter){
+    ensure(e, 1);
+    for index in 0..64{
+        e.move_to(bit(-1, index), &[bit(0, index), bit(1, index)]);
+        e.move_to(bit(1, index), &[bit(-1, index)]);
+    }
+    e.add(marker(0), 1);
+    e.rebase(1);
+}
+
+fn write_byte(e:&mut Emitter){
+    let (present, temporary, byte) = (SLOT+1, SLOT+2, 1);
+    // a strict program ends before writing anything when there is nothing to write
+    if !e.strict {ensure(e, 1)}
+    e.move_to(marker(-1), &[present, temporary]);
+    e.move_to(temporary, &[marker(-1)]);
+    e.when(present, |e| {
+        for index in 0..8{
+            e.transfer(bit(-1, index), &[(byte, 1 << index)]);
+        }
+        e.go(byte);
+        e.code.push('.');
+        e.clear(byte);
+    });
+    if e.strict {ensure(e, 1)}
+    for index in 0..64{
+        e.clear(bit(-1, index));
+    }
+    e.clear(marker(-1));
+    e.rebase(-1);
+}
+
+fn read_byte(e:&mut Emitter){
+    let [byte, copy, temporary, read, flag, parity_flag, half] = [1, 2, 3, 4, 5, 6, 7].map(|n| SLOT + n);
+    e.go(byte);
+    e.code.push(',');
+    e.move_to(byte, &[copy, temporary]);
+    e.move_to(temporary, &[byte]);
+    e.repeat(copy, |e| {
+        e.clear(copy);
+        e.add(read, 1);
+    });
+    e.branch(read, flag, |e| {
+        // halving the byte 8 times, counting down and flipping the bit
+        for index in 0..8{
+            let parity = bit(0, index);
+            e.repeat(byte, |e| {
+                e.add(byte, -1);
+                e.branch(parity, parity_flag, |e| e.add(half, 1), |e| e.add(parity, 1));
+            });
+            e.move_to(half, &[byte]);
+        }
+    }, |e| {
+        for index in 0..64{
+            e.add(bit(0, index), 1);
+        }
+    });
+    e.add(marker(0), 1);
+    e.rebase(1);
+}
+
+fn multiply(e:&mut Emitter){
+    // a, b, product, carry, temporary
+    let (count, overflow) = (scratch(5, 0), scratch(5, 1));
+    e.add(count, 64);
+    e.repeat(count, |e| {
+        e.add(count, -1);
+        e.when(work(5, 0, 1), |e| {
+            for index in 0..64{
+                let sum = work(5, index, 2);
+                e.move_to(work(5, index, 0), &[sum, work(5, index, 4)]);
+                e.move_to(work(5, index, 4), &[work(5, index, 0)]);
+                e.move_to(work(5, index, 3), &[sum]);
+                let next = if index < 63 {work(5, index+1, 3)} else {overflow};
+                split(e, sum, work(5, index, 4), next);
+                e.move_to(work(5, index, 4), &[sum]);
+            }
+            e.clear(overflow);
+        });
+        shift_up(e, 5, 0);
+        shift_down(e, 5, 1);
+    });
+}
+
+fn divide(e:&mut Emitter){
+    // a (then the quotient), b, remainder, difference, carry, temporary
+    let [sign_a, sign_b, nonzero, count, fits, flag, negative, overflow] = [0, 1, 2, 3, 4, 5, 6, 7].map(|n| scratch(6, n));
+
+    // divides the magnitudes, MIN's is 1 << 63 which is fine unsigned
+    for (track, sign) in [(0, sign_a), (1, sign_b)]{
+        e.move_to(work(6, 63, track), &[sign, work(6, 63, 5)]);
+        e.move_to(work(6, 63, 5), &[work(6, 63, track)]);
+        e.move_to(sign, &[negative, flag]);
+        e.move_to(flag, &[sign]);
+        e.when(negative, |e| negate(e, 6, track, 5, 4, overflow));
+    }
+    for index in 0..64{
+        let test = work(6, index, 5);
+        e.move_to(work(6, index, 1), &[test, work(6, index, 3)]);
+        e.move_to(work(6, index, 3), &[work(6, index, 1)]);
+        e.repeat(test, |e| {
+            e.clear(test);
+            e.clear(nonzero);
+            e.add(nonzero, 1);
+        });
+    }
+
+    e.add(count, 64);
+    e.repeat(count, |e| {
+        e.add(count, -1);
+        shift_up(e, 6, 2);
+        e.move_to(work(6, 63, 0), &[work(6, 0, 2)]);
+        shift_up(e, 6, 0);
+        // remainder + !b + 1, carrying out of the top when b fits
+        e.add(work(6, 0, 4), 1);
+        for index in 0..64{
+            let (difference, temporary) = (work(6, index, 3), work(6, index, 5));
+            e.move_to(work(6, index, 2), &[difference, temporary]);
+            e.move_to(temporary, &[work(6, index, 2)]);
+            e.add(difference, 1);
+            e.transfer(work(6, index, 1), &[(difference, -1), (temporary, 1)]);
+            e.move_to(temporary, &[work(6, index, 1)]);
+            e.move_to(work(6, index, 4), &[difference]);
+            let next = if index < 63 {work(6, index+1, 4)} else {fits};
+            split(e, difference, temporary, next);
+            e.move_to(temporary, &[difference]);
+        }
+        e.branch(fits, flag, |e| {
+            for index in 0..64{
+                e.clear(work(6, index, 2));
+                e.move_to(work(6, index, 3), &[work(6, index, 2)]);
+            }
+            e.add(work(6, 0, 0), 1);
+        }, |e| {
+            for index in 0..64{
+                e.clear(work(6, index, 3));
+            }
+        });
+    });
+    for index in 0..64{
+        e.clear(work(6, index, 1));
+        e.clear(work(6, index, 2));
+    }
+
+    // division by 0 is 0
+    e.add(flag, 1);
+    e.when(nonzero, |e| e.add(flag, -1));
+    e.when(flag, |e| {
+        for index in 0..64{
+            e.clear(work(6, index, 0));
+        }
+    });
+    e.move_to(sign_a, &[negative]);
+    e.move_to(sign_b, &[negative]);
+    logic(e, b'^', negative, flag);
+    e.when(flag, |e| negate(e, 6, 0, 5, 4, overflow));
+}
+
+fn binary(e:&mut Emitter, token:u8){
+    ensure(e, 2);
+    let tracks = match token{
+        b'*' => 5,
+        b'/' => 6,
+        _ => 4, // a, b, carry, result
+    };
+    for index in 0..64{
+        e.move_to(bit(-1, index), &[work(tracks, index, 1)]);
+        e.move_to(bit(-2, index), &[work(tracks, index, 0)]);
+    }
+    e.clear(marker(-1));
+    let result = match token{
+        b'+' | b'-' => {
+            if token == b'-'{
+                // a + !b + 1
+                for index in 0..64{
+                    not(e, work(4, index, 1), work(4, index, 3));
+                }
+                e.add(work(4, 0, 2), 1);
+            }
+            ripple(e, 4, 0, Some(1), 2, 3, scratch(4, 0));
+            3
+        },
+        b'*' => {
+            multiply(e);
+            2
+        },
+        b'/' => {
+            divide(e);
+            0
+        },
+        _ => {
+            for index in 0..64{
+                e.move_to(work(4, index, 1), &[work(4, index, 0)]);
+                logic(e, token, work(4, index, 0), work(4, index, 3));
+            }
+            3
+        },
+    };
+    for index in 0..64{
+        e.move_to(work(tracks, index, result), &[bit(-2, index)]);
+    }
+    e.rebase(-1);
+}
+
+fn jump(e:&mut Emitter){
+    // offset, CP, target, carry
+    let [halt, flag, outside, outside_flag, overflow] = [0, 1, 2, 3, 4].map(|n| scratch(4, n));
+    ensure(e, 1);
+    for index in 0..64{
+        e.move_to(bit(-1, index), &[work(4, index, 0)]);
+    }
+
+    // -1 halts
+    e.add(halt, 1);
+    for index in 0..64{
+        let (offset, copy, temporary, zero) = (work(4, index, 0), work(4, index, 2), work(4, index, 3), work(4, index, 1));
+        e.move_to(offset, &[copy, temporary]);
+        e.move_to(temporary, &[offset]);
+        e.add(zero, 1);
+        e.when(copy, |e| e.add(zero, -1));
+        e.when(zero, |e| e.clear(halt));
+    }
+    e.branch(halt, flag, |e| e.stop(), |e| {
+        // CP already is the offset of the '@' + 1, the target is that plus the offset popped
+        let stack = e.frame.unwrap();
+        e.walk_home();
+        for index in 0..e.bits{
+            e.when(e.cp(index), |e| {
+                e.walk_out(stack);
+                e.add(work(4, index, 1), 1);
+                e.walk_home();
+            });
+        }
+        e.walk_out(stack);
+        ripple(e, 4, 0, Some(1), 3, 2, overflow);
+
+        for index in e.bits..64{
+            let target = work(4, index, 2);
+            e.repeat(target, |e| {
+                e.clear(target);
+                e.clear(outside);
+                e.add(outside, 1);
+            });
+        }
+        // targets past the code CP can hold are left to the dispatch, it knows the length
+        e.branch(outside, outside_flag, |e| {
+            for index in 0..e.bits{
+                e.clear(work(4, index, 2));
+            }
+            if e.strict {e.stop()}
+        }, |e| {
+            for index in 0..e.bits{
+                e.when(work(4, index, 2), |e| {
+                    e.walk_home();
+                    e.add(e.cp(index), 1);
+                    e.walk_out(stack);
+                });
+            }
+        });
+    });
+    for index in 0..64{
+        e.clear(work(4, index, 0));
+    }
+    e.clear(marker(-1));
+    e.rebase(-1);
+}
+
+// '~' from home to home, carrying the bits set one at a time
+fn exchange(e:&mut Emitter, stack:usize){
+    let other = 1 - stack;
+    e.walk_out(other);
+    ensure(e, 1);
+    e.walk_home();
+    e.walk_out(stack);
+    ensure(e, 1);
+    for index in 0..64{
+        e.when(bit(-1, index), |e| {
+            e.walk_home();
+            e.walk_out(other);
+            e.add(bit(0, index), 1);
+            e.walk_home();
+            e.walk_out(stack);
+        });
+    }
+    e.walk_home();
+    e.walk_out(other);
+    for index in 0..64{
+        e.when(bit(-1, index), |e| {
+            e.walk_home();
+            e.walk_out(stack);
+            e.add(bit(-1, index), 1);
+            e.walk_home();
+            e.walk_out(other);
+        });
+        e.move_to(bit(0, index), &[bit(-1, index)]);
+    }
+    e.walk_home();
+}
+
+// Body for the active stack, from home to home
+fn on_active(e:&mut Emitter, body:&dyn Fn(&mut Emitter, usize)){
+    e.move_to(ACTIVE, &[ACTIVE_COPY, TEMPORARY]);
+    e.move_to(TEMPORARY, &[ACTIVE]);
+    e.branch(ACTIVE_COPY, ACTIVE_FLAG, |e| body(e, 1), |e| body(e, 0));
+}
+
+fn operation(e:&mut Emitter, token:u8){
+    match token{
+        b'$' => {
+            e.add(TEMPORARY, 1);
+            e.when(ACTIVE, |e| e.add(TEMPORARY, -1));
+            e.move_to(TEMPORARY, &[ACTIVE]);
+        },
+        b'~' => on_active(e, &exchange),
+        _ => on_active(e, &|e, stack| {
+            e.walk_out(stack);
+            match token{
+                b'!' => push_ones(e),
+                b'0' | b'1' => shift(e, token == b'1'),
+                b'=' => duplicate(e),
+                b'.' => write_byte(e),
+                b'?' => read_byte(e),
+                b'@' => jump(e),
+                _ => binary(e, token),
+            }
+            e.walk_home();
+        }),
+    }
+}
+
+// Tests the operation bits from level down for the operations in [first, first + 2^(level+1))
+fn operations(e:&mut Emitter, code:&[u8], level:i64, first:usize){
+    let last = first + (1 << (level+1));
+    if !TOKENS[first..last].iter().any(|t| code.contains(t)){
+        for index in 0..=level{
+            e.clear(OPERATION + index);
+        }
+    }else if level < 0{
+        operation(e, TOKENS[first]);
+    }else{
+        e.branch(OPERATION + level, OPERATION_FLAGS + level, |e| operations(e, code, level-1, first + (1 << level)),
+            |e| operations(e, code, level-1, first));
+    }
+}
+
+// Tests the bits of the copy of CP from level down for the offsets in [first, first + 2^(level+1))
+fn dispatch(e:&mut Emitter, code:&[u8], level:i64, first:usize){
+    if first >= code.len(){
+        for index in 0..=level{
+            e.clear(e.copy(index));
+        }
+        if e.strict{
+            e.clear(RUN);
+        }else{
+            for index in 0..e.bits{
+                e.clear(e.cp(index));
+            }
+        }
+    }else if level < 0{
+        for index in 0..e.bits{
+            e.clear(e.cp(index));
+            if (first + 1) >> index & 1 == 1 {e.add(e.cp(index), 1)}
+        }
+        let token = TOKENS.iter().position(|t| *t == code[first]).expect("code is made of tokens");
+        for index in 0..4{
+            if token >> index & 1 == 1 {e.add(OPERATION + index, 1)}
+        }
+        e.add(EXECUTE, 1);
+    }else{
+        e.branch(e.copy(level), e.copy_flag(level), |e| dispatch(e, code, level-1, first + (1 << level)),
+            |e| dispatch(e, code, level-1, first));
+    }
+}
+
+// Brainfuck doing what code does, for a tape endless both ways
+pub fn export(code:&[u8], strict:bool) -> String{
+    let bits = i64::from((usize::BITS - code.len().leading_zeros()).max(1));
+    let mut e = Emitter{code: String::new(), pos: 0, frame: None, bits, strict};
+    if !code.is_empty(){
+        e.add(RUN, 1);
+        e.repeat(RUN, |e| {
+            for index in 0..bits{
+                e.move_to(e.cp(index), &[e.copy(index), TEMPORARY]);
+                e.move_to(TEMPORARY, &[e.cp(index)]);
+            }
+            dispatch(e, code, bits-1, 0);
+            e.when(EXECUTE, |e| operations(e, code, 3, 0));
+        });
+    }
+
+    let mut out = format!("Stack Of Stacks translated to Brainfuck{}\n", if strict {" in strict mode"} else {""});
+    for line in e.code.as_bytes().chunks(100){
+        out.push_str(std::str::from_utf8(line).unwrap());
+        out.push('\n');
+    }
+    out
+}
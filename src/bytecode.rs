@@ -0,0 +1,39 @@
+use std::collections::HashMap;
+
+use crate::TOKENS;
+
+pub fn compile(code:&[u8]) -> Vec<u8>{
+    let mut opcodes:HashMap<u8, u8> = HashMap::new();
+
+    let mut high_bits = true;
+    let mut out:Vec<u8> = vec!();
+
+    for (index, token) in TOKENS.iter().enumerate(){
+        opcodes.insert(*token, index as u8);
+    }
+
+    //Since opcode 0 (PUSH(-1)) always works and is benign on itself, we dont care is lasat nibble contains just that.
+    for token in code{
+        if high_bits{
+            out.push( *opcodes.get(token).unwrap() << 4 );
+        }else{
+            let i:usize = out.len()-1;
+            out[i] |= *opcodes.get(token).unwrap();
+        }
+        high_bits = !high_bits;
+    }
+
+    out
+}
+
+
+pub fn bytecode(bytecode:&[u8]) -> Vec<u8>{
+    let mut code:Vec<u8> = vec!();
+
+    for byte in bytecode{
+        code.push(TOKENS[usize::from(byte>>4)]);
+        code.push(TOKENS[usize::from(byte&0b1111)]);
+    }
+
+    code
+}
@@ -0,0 +1,166 @@
+// C backend for the pure script
+//
+// Emits a single C file that does what vm::run does. Every offset gets a label since jumps can
+// land anywhere, and straight code falls through from one to the next. At every '@' the jumps
+// cfg resolves (a constant or 'K*@' in front of it) become a goto guarded by a check of the
+// offset popped, as something could have jumped in between the constant and the '@'. Any other
+// offset goes through a switch on CP, the fallback table. Arithmetic is done unsigned so it
+// wraps the way the interpreter's does without undefined behaviour. Compiling with
+// -DSOS_MAX_STEPS=N stops the program after N steps like --max-steps, and -DSOS_STACKS writes
+// both stacks to STDERR when it ends, stack 0 first.
+
+use std::fmt::Write;
+
+use crate::assembler::Program;
+use crate::cfg::{self, Jump, Target};
+
+const PRELUDE:&str = r#"#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+struct sos_stack{
+    int64_t *items;
+    size_t len, cap;
+};
+
+static struct sos_stack stacks[2];
+static int active = 0;
+static uint64_t steps = 0;
+
+static void finish(int status){
+    fflush(stdout);
+#ifdef SOS_STACKS
+    for(int s = 0; s < 2; s++){
+        fputc('[', stderr);
+        for(size_t i = 0; i < stacks[s].len; i++){
+            fprintf(stderr, i ? ", %lld" : "%lld", (long long)stacks[s].items[i]);
+        }
+        fputs("]\n", stderr);
+    }
+#endif
+    exit(status);
+}
+
+static void fault(const char *message){
+    fflush(stdout);
+    fputs(message, stderr);
+    finish(1);
+}
+
+static void out_of_code(uint64_t cp){
+    fflush(stdout);
+    fprintf(stderr, "Strict mode violation: Outside of code memmory (offset: 0x0x%016llX)\n", (unsigned long long)cp);
+    finish(1);
+}
+
+static void push(int s, int64_t value){
+    struct sos_stack *stack = &stacks[s];
+    if(stack->len == stack->cap){
+        stack->cap = stack->cap ? stack->cap * 2 : 64;
+        stack->items = realloc(stack->items, stack->cap * sizeof(int64_t));
+        if(!stack->items) fault("Out of memory\n");
+    }
+    stack->items[stack->len++] = value;
+}
+
+static int64_t pop(int s){
+    if(stacks[s].len) return stacks[s].items[--stacks[s].len];
+    if(STRICT) fault("Strict mode violation: Stack depleted\n");
+    return -1;
+}
+
+#ifdef SOS_MAX_STEPS
+#define STEP() do{ \
+        if(steps >= (uint64_t)(SOS_MAX_STEPS)){ \
+            fflush(stdout); \
+            fprintf(stderr, "Step limit reached after %llu steps\n", (unsigned long long)steps); \
+            finish(1); \
+        } \
+        steps++; \
+    }while(0)
+#else
+#define STEP() steps++
+#endif
+
+#define BINARY(expr) do{ int64_t b = pop(active); int64_t a = pop(active); push(active, (expr)); }while(0)
+#define UNARY(expr) do{ int64_t a = pop(active); push(active, (expr)); }while(0)
+#define WRAP(op) ((int64_t)((uint64_t)a op (uint64_t)b))
+"#;
+
+// C for op, anything but '@'
+fn instruction(op:u8) -> &'static str{
+    match op{
+        b'!' => "push(active, -1);",
+        b'^' => "BINARY(a ^ b);",
+        b'|' => "BINARY(a | b);",
+        b'&' => "BINARY(a & b);",
+        b'+' => "BINARY(WRAP(+));",
+        b'-' => "BINARY(WRAP(-));",
+        b'*' => "BINARY(WRAP(*));",
+        b'/' => "BINARY(b == 0 ? 0 : b == -1 ? (int64_t)(0 - (uint64_t)a) : a / b);",
+        b'$' => "active ^= 1;",
+        b'~' => "{ int64_t a = pop(active); int64_t b = pop(active ^ 1); push(active ^ 1, a); push(active, b); }",
+        b'=' => "{ int64_t a = pop(active); push(active, a); push(active, a); }",
+        b'?' => "{ int c = getchar(); push(active, c == EOF ? -1 : c); }",
+        b'.' => "putchar((unsigned char)pop(active));",
+        b'0' => "UNARY((int64_t)((uint64_t)a << 1));",
+        b'1' => "UNARY((int64_t)((uint64_t)a << 1 | 1));",
+        _ => unreachable!("'@' is emitted on its own"),
+    }
+}
+
+pub fn emit(program:&Program, strict:bool) -> String{
+    let code = &program.code;
+    let mut out = String::new();
+    let _ = writeln!(out, "// Stack Of Stacks program, {} instructions{}", code.len(), if strict {", strict mode"} else {""});
+    let _ = writeln!(out, "#define STRICT {}\n#define LEN {}u", i32::from(strict), code.len());
+    out.push_str(PRELUDE);
+
+    out.push_str("\nint main(void){\n    uint64_t cp;\n");
+    if code.is_empty(){
+        out.push_str("    (void)cp;\n    finish(0);\n}\n");
+        return out;
+    }
+
+    for (offset, op) in code.iter().enumerate(){
+        for label in program.labels.iter().filter(|l| l.offset == offset){
+            let _ = writeln!(out, "    // :{}", label.name);
+        }
+        let _ = write!(out, "op_{}: STEP(); /* {} */ ", offset, *op as char);
+
+        if *op != b'@'{
+            out.push_str(instruction(*op));
+            if offset+1 == code.len(){
+                out.push_str(if strict {" out_of_code(LEN);"} else {" goto op_0;"});
+            }
+            out.push('\n');
+            continue;
+        }
+
+        out.push_str("{\n        int64_t a = pop(active);\n        if(a == -1) finish(0);\n");
+        let values = match cfg::jump(code, offset){
+            Jump::Constant(value) => vec!(value),
+            Jump::Conditional(value) => vec!(0, value),
+            Jump::Dynamic => vec!(),
+        };
+        for value in values{
+            if let Target::Offset(target) = cfg::target(code.len(), offset, value, strict){
+                let _ = writeln!(out, "        if(a == {}) goto op_{};", literal(value), target);
+            }
+        }
+        let _ = writeln!(out, "        cp = {}u + (uint64_t)a + 1;\n        goto dispatch;\n    }}", offset);
+    }
+
+    // the fallback table, for jumps by an offset not known up front
+    out.push_str("\ndispatch:\n    if(cp >= LEN){\n        if(STRICT) out_of_code(cp);\n        cp = 0;\n    }\n    switch(cp){\n");
+    for offset in 0..code.len(){
+        let _ = writeln!(out, "        case {}: goto op_{};", offset, offset);
+    }
+    out.push_str("    }\n    return 0;\n}\n");
+    out
+}
+
+// value as a C literal, INT64_MIN has none
+fn literal(value:i64) -> String{
+    if value == i64::MIN {"INT64_MIN".to_owned()} else {format!("{}ll", value)}
+}
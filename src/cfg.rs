@@ -0,0 +1,219 @@
+// Control flow recovery for the pure script
+//
+// Jumps are relative and take their offset from the stack, so a target is only known statically
+// when the offset is pushed as a constant right in front of the '@' (a macro or something like
+// '!01110@'), or for the conditional 'K*@' idiom where a 0/1 test result is multiplied by a
+// constant K before jumping.
+
+use crate::TOKENS;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Jump{
+    Constant(i64),    // '@' fed by a constant, -1 halts
+    Conditional(i64), // 'K*@' after a 0/1 test, jumps by 0 (falls through) or K
+    Dynamic,          // anything else
+}
+
+// A constant push ('!' or '!!^' followed by binary digits) ending right before end: its start and value
+pub fn constant_before(code:&[u8], end:usize) -> Option<(usize, i64)>{
+    let digits = code[..end].iter().rev().take_while(|b| **b == b'0' || **b == b'1').count();
+    let start = end - digits;
+
+    let (start, mut value) = if code[..start].ends_with(b"!!^") {
+        (start-3, 0)
+    }else if code[..start].ends_with(b"!"){
+        (start-1, -1i64)
+    }else{
+        return None;
+    };
+
+    for digit in &code[end-digits..end]{
+        value = (value << 1) | i64::from(*digit == b'1');
+    }
+
+    Some((start, value))
+}
+
+// How the '@' at offset gets its offset
+pub fn jump(code:&[u8], offset:usize) -> Jump{
+    if let Some((_, value)) = constant_before(code, offset){
+        return Jump::Constant(value);
+    }
+    // without the test K gets multiplied by whatever was there, which could go anywhere
+    if guard(code, offset).is_some(){
+        if let Some((_, value)) = constant_before(code, offset-1){
+            return Jump::Conditional(value);
+        }
+    }
+    Jump::Dynamic
+}
+
+// Start of what makes the '@' at offset go where jump says: its constant, and the test in front for 'K*@'
+pub fn span(code:&[u8], offset:usize) -> Option<usize>{
+    match jump(code, offset){
+        Jump::Constant(_) => constant_before(code, offset).map(|(start, _)| start),
+        Jump::Conditional(_) => guard(code, offset).map(|test| constant_before(code, offset-1).unwrap().0 - test.len()),
+        Jump::Dynamic => None,
+    }
+}
+
+// How every '@' in code gets its offset, by offset (Dynamic for the other instructions). A jump
+// that another one lands in the middle of is Dynamic after all, entered there its constant or
+// test is not pushed whole and the '@' could go anywhere
+pub fn jumps(code:&[u8]) -> Vec<Jump>{
+    let mut jumps:Vec<Jump> = (0..code.len()).map(|o| if code[o] == b'@' {jump(code, o)} else {Jump::Dynamic}).collect();
+    // every jump given up on takes its targets along, so this settles
+    loop{
+        let mut targeted = vec![false; code.len()];
+        for offset in (0..code.len()).filter(|o| code[*o] == b'@'){
+            for (target, _) in successors(code, &jumps, offset, false).targets{
+                if let Target::Offset(target) = target{
+                    targeted[target] = true;
+                }
+            }
+        }
+
+        let mut changed = false;
+        for offset in 0..code.len(){
+            if jumps[offset] == Jump::Dynamic {continue}
+            let start = span(code, offset).unwrap();
+            if targeted[start+1..=offset].contains(&true){
+                jumps[offset] = Jump::Dynamic;
+                changed = true;
+            }
+        }
+        if !changed {return jumps}
+    }
+}
+
+// Instructions leaving 0 or 1 on the stack, whatever was there
+pub const TESTS:[&[u8]; 2] = [b"=/", b"!!^1&"];
+
+// The test in front of the 'K*@' at offset, the one thing that makes sure it only jumps by 0 or K
+pub fn guard(code:&[u8], offset:usize) -> Option<&'static [u8]>{
+    if offset == 0 || code[offset-1] != b'*' {return None}
+    let (start, _) = constant_before(code, offset-1)?;
+    TESTS.iter().find(|t| code[..start].ends_with(t)).copied()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Target{
+    Offset(usize),
+    Halt,
+    OutOfCode, // only in strict mode, otherwise execution wraps to 0
+}
+
+// Where CP ends up after the instruction at offset moved it by jump (0 for anything but '@')
+pub fn target(code_len:usize, offset:usize, jump:i64, strict:bool) -> Target{
+    if jump == -1 {return Target::Halt}
+
+    let cp = offset.wrapping_add(jump as usize).wrapping_add(1);
+    if cp < code_len{
+        Target::Offset(cp)
+    }else if strict{
+        Target::OutOfCode
+    }else{
+        Target::Offset(0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Edge{
+    Next,     // to the next instruction
+    Constant, // unconditional constant jump
+    Taken,    // conditional jump, taken
+    NotTaken, // conditional jump, falling through
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Successors{
+    pub targets:Vec<(Target, Edge)>,
+    pub dynamic:bool, // an '@' whose targets are unknown
+}
+
+// Where the instruction at offset can go, jumps as given by the function of that name
+pub fn successors(code:&[u8], jumps:&[Jump], offset:usize, strict:bool) -> Successors{
+    let mut targets = vec!();
+    let mut dynamic = false;
+
+    if code[offset] == b'@'{
+        match jumps[offset]{
+            Jump::Constant(value) => targets.push((target(code.len(), offset, value, strict), Edge::Constant)),
+            Jump::Conditional(value) => {
+                targets.push((target(code.len(), offset, 0, strict), Edge::NotTaken));
+                targets.push((target(code.len(), offset, value, strict), Edge::Taken));
+            },
+            Jump::Dynamic => dynamic = true,
+        }
+    }else{
+        debug_assert!(TOKENS.contains(&code[offset]));
+        targets.push((target(code.len(), offset, 0, strict), Edge::Next));
+    }
+
+    Successors{targets, dynamic}
+}
+
+
+// A straight run of instructions that is only entered at its start, end is exclusive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block{
+    pub start:usize,
+    pub end:usize,
+    pub successors:Successors, // of the last instruction
+}
+
+// Splits code into basic blocks: these start at offset 0, at every resolved jump target, after
+// every '@' and at every label offset given (so the graph can be read against the source)
+pub fn blocks(code:&[u8], labels:&[usize], strict:bool) -> Vec<Block>{
+    if code.is_empty() {return vec!()}
+
+    let jumps = jumps(code);
+    let mut leaders = vec![false; code.len()];
+    leaders[0] = true;
+    for offset in labels{
+        if *offset < code.len(){
+            leaders[*offset] = true;
+        }
+    }
+    for (offset, op) in code.iter().enumerate(){
+        if *op != b'@' {continue}
+        if offset+1 < code.len(){
+            leaders[offset+1] = true;
+        }
+        for (target, _) in successors(code, &jumps, offset, strict).targets{
+            if let Target::Offset(target) = target{
+                leaders[target] = true;
+            }
+        }
+    }
+
+    let mut blocks = vec!();
+    let mut start = 0;
+    for offset in 0..code.len(){
+        if offset+1 == code.len() || leaders[offset+1]{
+            blocks.push(Block{start, end: offset+1, successors: successors(code, &jumps, offset, strict)});
+            start = offset+1;
+        }
+    }
+    blocks
+}
+
+// Blocks that can be reached from the entry point following resolved edges only
+pub fn reachable(blocks:&[Block]) -> Vec<bool>{
+    let mut seen = vec![false; blocks.len()];
+    if blocks.is_empty() {return seen}
+
+    let mut worklist = vec!(0);
+    seen[0] = true;
+    while let Some(index) = worklist.pop(){
+        for (target, _) in &blocks[index].successors.targets{
+            let Target::Offset(target) = target else {continue};
+            let next = blocks.partition_point(|b| b.start <= *target) - 1;
+            if !seen[next]{
+                seen[next] = true;
+                worklist.push(next);
+            }
+        }
+    }
+    seen
+}
@@ -0,0 +1,160 @@
+// Code coverage: which instructions ran and which outcomes of every '@' were seen
+//
+// Every '@' whose offset is not a constant pushed right in front of it (like '!01110@' or '!@') is
+// a branch with two outcomes: taken (non zero offset) and not taken (offset 0, falling through).
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::assembler::Program;
+use crate::cfg::{self, Jump};
+use crate::vm::{Effect, Observer, Vm};
+
+const TOP:&str = "(top)";
+
+pub struct Coverage{
+    pub hits:Vec<u64>,
+    pub branches:BTreeMap<usize, [u64;2]>, // '@' offset -> [not taken, taken]
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Totals{
+    pub instructions:usize,
+    pub instructions_hit:usize,
+    pub branches:usize, // outcomes, so 2 per conditional '@'
+    pub branches_hit:usize,
+}
+
+fn percentage(hit:usize, total:usize) -> f64{
+    if total == 0 {100.0} else {hit as f64 * 100.0 / total as f64}
+}
+
+impl Coverage{
+    pub fn new(code:&[u8]) -> Coverage{
+        let jumps = cfg::jumps(code);
+        let branches = code.iter().enumerate()
+            .filter(|(offset, op)| **op == b'@' && !matches!(jumps[*offset], Jump::Constant(_)))
+            .map(|(offset, _)| (offset, [0, 0]))
+            .collect();
+        Coverage{hits: vec![0; code.len()], branches}
+    }
+
+    // Totals per label region in order of appearance, and for the whole program
+    pub fn totals<'a>(&self, program:&'a Program) -> (Vec<(&'a str, Totals)>, Totals){
+        let mut labels:Vec<(&str, Totals)> = vec!();
+        let mut all = Totals::default();
+
+        for (offset, hits) in self.hits.iter().enumerate(){
+            let name = program.label_at(offset).map_or(TOP, |(name, _)| name);
+            let index = match labels.iter().position(|(n, _)| *n == name){
+                Some(index) => index,
+                None => {labels.push((name, Totals::default())); labels.len()-1},
+            };
+
+            for totals in [&mut labels[index].1, &mut all]{
+                totals.instructions += 1;
+                totals.instructions_hit += usize::from(*hits > 0);
+                if let Some(outcomes) = self.branches.get(&offset){
+                    totals.branches += 2;
+                    totals.branches_hit += outcomes.iter().filter(|c| **c > 0).count();
+                }
+            }
+        }
+
+        (labels, all)
+    }
+
+    pub fn summary(&self, program:&Program) -> String{
+        let mut out = String::new();
+        let (labels, all) = self.totals(program);
+
+        let _ = writeln!(out, "Coverage: {}/{} instructions ({:.2}%), {}/{} branch outcomes ({:.2}%)",
+            all.instructions_hit, all.instructions, percentage(all.instructions_hit, all.instructions),
+            all.branches_hit, all.branches, percentage(all.branches_hit, all.branches));
+
+        let width = labels.iter().map(|(name, _)| name.len()).max().unwrap_or(0).max(5);
+        let _ = writeln!(out, "\n  {:<width$}  {:>19}  {:>17}", "label", "instructions", "branches", width = width);
+        for (name, totals) in &labels{
+            let _ = writeln!(out, "  {:<width$}  {:>11} {:>6.2}%  {:>9} {:>6.2}%", name,
+                format!("{}/{}", totals.instructions_hit, totals.instructions), percentage(totals.instructions_hit, totals.instructions),
+                format!("{}/{}", totals.branches_hit, totals.branches), percentage(totals.branches_hit, totals.branches),
+                width = width);
+        }
+
+        let partial:Vec<_> = self.branches.iter().filter(|(_, outcomes)| outcomes.contains(&0)).collect();
+        if !partial.is_empty(){
+            let _ = writeln!(out, "\nIncomplete branches:");
+            for (offset, [not_taken, taken]) in partial{
+                let missing = match (*not_taken, *taken){
+                    (0, 0) => "never executed",
+                    (0, _) => "never fell through",
+                    _ => "never taken",
+                };
+                let location = program.location(*offset).map_or("-".to_owned(), |l| format!("{}:{}", l.line, l.column));
+                let _ = writeln!(out, "  {:>9}  {:#018X}  {:<24} {}", location, offset, program.symbolise(*offset), missing);
+            }
+        }
+
+        out
+    }
+
+    // lcov tracefile, labels are reported as functions
+    pub fn lcov(&self, program:&Program, filename:&str) -> String{
+        let mut out = String::new();
+        let _ = writeln!(out, "TN:");
+        let _ = writeln!(out, "SF:{}", filename);
+
+        let line = |offset:usize| program.location(offset).map_or(offset+1, |l| l.line); // no source: one line per instruction
+
+        let mut functions_hit = 0;
+        for label in &program.labels{
+            let _ = writeln!(out, "FN:{},{}", line(label.offset), label.name);
+        }
+        for label in &program.labels{
+            let hits = self.hits.get(label.offset).copied().unwrap_or(0);
+            functions_hit += usize::from(hits > 0);
+            let _ = writeln!(out, "FNDA:{},{}", hits, label.name);
+        }
+        let _ = writeln!(out, "FNF:{}", program.labels.len());
+        let _ = writeln!(out, "FNH:{}", functions_hit);
+
+        let mut branches_hit = 0;
+        for (offset, outcomes) in &self.branches{
+            let executed = self.hits[*offset] > 0;
+            for (branch, count) in outcomes.iter().enumerate(){
+                branches_hit += usize::from(*count > 0);
+                let taken = if executed {count.to_string()} else {"-".to_owned()};
+                let _ = writeln!(out, "BRDA:{},{},{},{}", line(*offset), offset, branch, taken);
+            }
+        }
+        let _ = writeln!(out, "BRF:{}", self.branches.len()*2);
+        let _ = writeln!(out, "BRH:{}", branches_hit);
+
+        // a line counts as often as its most executed instruction
+        let mut lines:BTreeMap<usize, u64> = BTreeMap::new();
+        for (offset, hits) in self.hits.iter().enumerate(){
+            let entry = lines.entry(line(offset)).or_insert(0);
+            *entry = (*entry).max(*hits);
+        }
+        for (line, hits) in &lines{
+            let _ = writeln!(out, "DA:{},{}", line, hits);
+        }
+        let _ = writeln!(out, "LF:{}", lines.len());
+        let _ = writeln!(out, "LH:{}", lines.values().filter(|h| **h > 0).count());
+        let _ = writeln!(out, "end_of_record");
+
+        out
+    }
+}
+
+impl Observer for Coverage{
+    fn before(&mut self, vm:&Vm){
+        self.hits[vm.cp] += 1;
+    }
+
+    fn after(&mut self, _vm:&Vm, cp:usize, effect:&Effect){
+        if let (Effect::Jump(offset), Some(outcomes)) = (effect, self.branches.get_mut(&cp)){
+            outcomes[usize::from(*offset != 0)] += 1;
+        }
+    }
+}
@@ -0,0 +1,199 @@
+// Random program exploration
+//
+// Any byte string is valid bytecode, so random bytes make random programs. Each one is run from
+// an empty input under a step limit and classified by how it ends. Programs that behave the same
+// (same ending, output and step count) are counted together, the first of each behaviour is kept.
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::Read;
+
+use crate::loops::Detector;
+use crate::vm::{BufferIo, Effect, Fault, Observer, Vm};
+
+// Smallest final height counted as unbounded growth
+const GROWTH_HEIGHT:usize = 16;
+// Output kept per program
+const OUTPUT_LIMIT:usize = 256;
+
+// xorshift64*, plenty for picking bytes
+pub struct Rng(u64);
+
+impl Rng{
+    pub fn new(seed:u64) -> Rng{
+        Rng((seed ^ 0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64{
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    pub fn fill(&mut self, bytes:&mut [u8]){
+        for b in bytes{
+            *b = (self.next_u64() >> 56) as u8;
+        }
+    }
+}
+
+// Where programs come from
+pub enum Source<'a>{
+    Random(Rng),
+    Reader(&'a mut dyn Read), // consecutive chunks of the given size
+}
+
+impl Source<'_>{
+    // A program of at most size bytes, None when the reader ran dry
+    pub fn program(&mut self, size:usize) -> Option<Vec<u8>>{
+        match self{
+            Source::Random(rng) => {
+                let mut program = vec![0; 1 + (rng.next_u64() % size as u64) as usize];
+                rng.fill(&mut program);
+                Some(program)
+            },
+            Source::Reader(reader) => {
+                let mut program = vec!();
+                reader.take(size as u64).read_to_end(&mut program).ok()?;
+                if program.is_empty() {None} else {Some(program)}
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Ending{
+    Halted,
+    Fault,                  // strict mode only
+    Loop{period:u64},       // came back to an earlier state exactly, so it runs forever
+    Growing,                // still running at the limit with the stacks getting higher
+    Running,                // still running at the limit, nothing more known
+}
+
+impl Ending{
+    pub fn name(&self) -> &'static str{
+        match self{
+            Ending::Halted => "halt",
+            Ending::Fault => "fault",
+            Ending::Loop{..} => "loop",
+            Ending::Growing => "growth",
+            Ending::Running => "running",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Behaviour{
+    pub ending:Ending,
+    pub output:Vec<u8>, // the first OUTPUT_LIMIT bytes
+    pub steps:u64,      // until halt or fault, or until the loop was noticed
+}
+
+impl Behaviour{
+    pub fn signature(&self) -> u64{
+        let mut hasher = DefaultHasher::new();
+        self.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    // Worth keeping: writes text, runs long before halting or loops over more than a few instructions
+    pub fn interesting(&self) -> bool{
+        let text = self.output.len() >= 2 && self.output.iter().all(|b| b.is_ascii_graphic() || b.is_ascii_whitespace());
+        text
+            || (self.ending == Ending::Halted && self.steps >= 100)
+            || matches!(self.ending, Ending::Loop{period} if period >= 64)
+            || (self.ending == Ending::Growing && self.output.is_empty())
+    }
+}
+
+fn height(vm:&Vm) -> usize{
+    vm.stacks[0].len() + vm.stacks[1].len()
+}
+
+// Runs code with no input for at most max_steps steps
+pub fn run(code:Vec<u8>, strict:bool, max_steps:u64) -> Behaviour{
+    let mut detector = Detector::new(code.len());
+    let mut vm = Vm::new(code, strict);
+    let mut io = BufferIo::default();
+    let mut half_height = 0;
+
+    let ending = loop{
+        if vm.steps >= max_steps{
+            break if height(&vm) >= GROWTH_HEIGHT && height(&vm) > half_height {Ending::Growing} else {Ending::Running};
+        }
+        if vm.steps == max_steps / 2{
+            half_height = height(&vm);
+        }
+
+        let cp = vm.cp;
+        match vm.step(&mut io){
+            Ok(Effect::Halt) => break Ending::Halted,
+            Ok(effect) => detector.after(&vm, cp, &effect),
+            Err(Fault::StackDepleted | Fault::OutOfCode(_)) => break Ending::Fault,
+        }
+        if let Some(cycle) = &detector.cycle{
+            break Ending::Loop{period: cycle.period};
+        }
+    };
+
+    io.output.truncate(OUTPUT_LIMIT);
+    Behaviour{ending, output: io.output, steps: vm.steps}
+}
+
+// A distinct behaviour, the first program that showed it and how many did
+pub struct Specimen{
+    pub program:Vec<u8>,
+    pub behaviour:Behaviour,
+    pub count:u64,
+}
+
+#[derive(Default)]
+pub struct Exploration{
+    pub runs:u64,
+    pub specimens:Vec<Specimen>,
+    by_signature:HashMap<u64, usize>,
+}
+
+impl Exploration{
+    // Records a run, returns the specimen when its behaviour is new
+    pub fn add(&mut self, program:Vec<u8>, behaviour:Behaviour) -> Option<&Specimen>{
+        self.runs += 1;
+        let signature = behaviour.signature();
+        if let Some(index) = self.by_signature.get(&signature){
+            self.specimens[*index].count += 1;
+            return None;
+        }
+        self.by_signature.insert(signature, self.specimens.len());
+        self.specimens.push(Specimen{program, behaviour, count: 1});
+        self.specimens.last()
+    }
+
+    pub fn report(&self) -> String{
+        let mut out = String::new();
+        let mut by_ending:HashMap<&str, (u64, u64)> = HashMap::new();
+        let mut with_output = 0;
+        for specimen in &self.specimens{
+            let entry = by_ending.entry(specimen.behaviour.ending.name()).or_default();
+            entry.0 += specimen.count;
+            entry.1 += 1;
+            if !specimen.behaviour.output.is_empty(){
+                with_output += specimen.count;
+            }
+        }
+
+        let _ = writeln!(out, "{} programs, {} distinct behaviours", self.runs, self.specimens.len());
+        for name in ["halt", "fault", "loop", "growth", "running"]{
+            if let Some((programs, behaviours)) = by_ending.get(name){
+                let _ = writeln!(out, "  {:<8} {:>8} programs {:>7} behaviours  {:5.1}%", name, programs, behaviours, 100.0 * *programs as f64 / self.runs as f64);
+            }
+        }
+        let _ = writeln!(out, "  {:<8} {:>8} programs", "output", with_output);
+        out
+    }
+}
+
+pub fn hex(bytes:&[u8]) -> String{
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,214 @@
+// Pre-decoded interpreter, a faster tier for when nothing observes the run
+//
+// The plain interpreter matches on the ASCII token at CP every step. Here the code is decoded once
+// into an Op per offset, with superinstructions for common idioms. Jumps can land on any offset, so
+// every offset gets its own op and a superinstruction starting at one offset overlaps the ops that
+// follow it. A superinstruction only runs when it cannot fault halfway and fits in the step limit,
+// otherwise the single instruction at CP runs, so the machine ends up exactly as with vm::run.
+
+use crate::vm::{Fault, Io, Stop, Vm};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op{
+    High,
+    Xor,
+    Or,
+    And,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Switch,
+    Exchange,
+    Duplicate,
+    Jump,
+    Read,
+    Write,
+    Shl0,
+    Shl1,
+    Push{value:i64, len:u32},   // '!' or '!!^' followed by 0s and 1s
+    ZeroTest,                   // '==/', pushes 1 when the top is not 0, 0 when it is
+    MulJump,                    // '*@', jumps by the product (0 when either is 0)
+}
+
+impl Op{
+    pub fn single(token:u8) -> Op{
+        match token{
+            b'!' => Op::High,
+            b'^' => Op::Xor,
+            b'|' => Op::Or,
+            b'&' => Op::And,
+            b'+' => Op::Add,
+            b'-' => Op::Sub,
+            b'*' => Op::Mul,
+            b'/' => Op::Div,
+            b'$' => Op::Switch,
+            b'~' => Op::Exchange,
+            b'=' => Op::Duplicate,
+            b'@' => Op::Jump,
+            b'?' => Op::Read,
+            b'.' => Op::Write,
+            b'0' => Op::Shl0,
+            b'1' => Op::Shl1,
+            _ => panic!("INTERPRETER's FAULT: Invalid token!"),
+        }
+    }
+
+    // Instructions executed
+    pub fn steps(&self) -> u64{
+        match self{
+            Op::Push{len, ..} => *len as u64,
+            Op::ZeroTest => 3,
+            Op::MulJump => 2,
+            _ => 1,
+        }
+    }
+}
+
+fn push_at(code:&[u8], offset:usize) -> Option<Op>{
+    let (mut value, start) = match code.get(offset..offset+3){
+        Some(b"!!^") => (0, offset+3),
+        _ if code[offset] == b'!' => (-1, offset+1),
+        _ => return None,
+    };
+    let mut end = start;
+    while end < code.len() && matches!(code[end], b'0' | b'1') && end - offset < u32::MAX as usize{
+        value = (value << 1) | (code[end] - b'0') as i64;
+        end += 1;
+    }
+    (end > start || start == offset+3).then_some(Op::Push{value, len: (end - offset) as u32})
+}
+
+pub fn decode(code:&[u8]) -> Vec<Op>{
+    (0..code.len()).map(|offset| {
+        let rest = &code[offset..];
+        push_at(code, offset)
+            .or_else(|| rest.starts_with(b"==/").then_some(Op::ZeroTest))
+            .or_else(|| rest.starts_with(b"*@").then_some(Op::MulJump))
+            .unwrap_or_else(|| Op::single(code[offset]))
+    }).collect()
+}
+
+// Same as vm::run without observers
+pub fn run(vm:&mut Vm, io:&mut dyn Io) -> Result<Stop, Fault>{
+    if vm.code.is_empty(){return Ok(Stop::Halted)}
+
+    let ops = decode(&vm.code);
+    let mut stacks = std::mem::take(&mut vm.stacks);
+    let result = execute(vm, &ops, &mut stacks, io);
+    vm.stacks = stacks;
+    result
+}
+
+fn execute(vm:&mut Vm, ops:&[Op], stacks:&mut [Vec<i64>;2], io:&mut dyn Io) -> Result<Stop, Fault>{
+    let strict = vm.strict;
+    let len = ops.len();
+    let max_steps = vm.max_steps.unwrap_or(u64::MAX);
+    let mut cp = vm.cp;
+    let mut active = vm.active;
+    let mut steps = vm.steps;
+
+    // Writes the registers back on the way out
+    macro_rules! leave{
+        ($result:expr) => {{
+            vm.cp = cp;
+            vm.active = active;
+            vm.steps = steps;
+            return $result;
+        }}
+    }
+    macro_rules! pop{
+        ($stack:expr) => {
+            match $stack.pop(){
+                Some(v) => v,
+                None if strict => leave!(Err(Fault::StackDepleted)),
+                None => -1,
+            }
+        }
+    }
+    macro_rules! binary{
+        ($f:expr) => {{
+            let stack = &mut stacks[active];
+            let b = pop!(stack);
+            let a = pop!(stack);
+            stack.push($f(a, b));
+        }}
+    }
+
+    loop{
+        if steps >= max_steps {leave!(Ok(Stop::StepLimit))}
+
+        let mut op = ops[cp];
+        if steps + op.steps() > max_steps
+            || (op == Op::ZeroTest && stacks[active].is_empty())
+            || (op == Op::MulJump && stacks[active].len() < 2){
+            op = Op::single(vm.code[cp]);
+        }
+        steps += op.steps();
+        cp += op.steps() as usize - 1; // the last instruction of op, which jumps are relative to
+
+        match op{
+            Op::High => stacks[active].push(-1),
+            Op::Xor => binary!(|a, b| a ^ b),
+            Op::Or => binary!(|a, b| a | b),
+            Op::And => binary!(|a, b| a & b),
+            Op::Add => binary!(i64::wrapping_add),
+            Op::Sub => binary!(i64::wrapping_sub),
+            Op::Mul => binary!(i64::wrapping_mul),
+            Op::Div => binary!(|a:i64, b:i64| if b != 0 {a.wrapping_div(b)} else {0}),
+            Op::Switch => active ^= 1,
+            Op::Exchange => {
+                let [first, second] = stacks;
+                let (stack, other) = if active == 0 {(first, second)} else {(second, first)};
+                let a = pop!(stack);
+                let b = pop!(other);
+                other.push(a);
+                stack.push(b);
+            },
+            Op::Duplicate => {
+                let stack = &mut stacks[active];
+                let a = pop!(stack);
+                stack.push(a);
+                stack.push(a);
+            },
+            Op::Read => stacks[active].push(io.read()),
+            Op::Write => {
+                let a = pop!(stacks[active]);
+                io.write(a as u8);
+            },
+            Op::Shl0 => {
+                let stack = &mut stacks[active];
+                let a = pop!(stack);
+                stack.push(a << 1);
+            },
+            Op::Shl1 => {
+                let stack = &mut stacks[active];
+                let a = pop!(stack);
+                stack.push((a << 1) | 1);
+            },
+            Op::Push{value, ..} => stacks[active].push(value),
+            Op::ZeroTest => {
+                let stack = &mut stacks[active];
+                let a = *stack.last().unwrap();
+                stack.push(i64::from(a != 0));
+            },
+            Op::Jump | Op::MulJump => {
+                let stack = &mut stacks[active];
+                let a = if op == Op::MulJump{
+                    let b = pop!(stack);
+                    pop!(stack).wrapping_mul(b)
+                }else{
+                    pop!(stack)
+                };
+                if a == -1 {leave!(Ok(Stop::Halted))}
+                cp = cp.wrapping_add(a as usize);
+            },
+        }
+
+        cp = cp.wrapping_add(1);
+        if cp >= len{
+            if strict {leave!(Err(Fault::OutOfCode(cp)))}
+            cp = 0;
+        }
+    }
+}
@@ -0,0 +1,142 @@
+// Constant folding for the pure script
+//
+// Walks every straight run of instructions keeping track of the values it pushes that are known,
+// eg. '!!^1=+' leaves a 2. A run that only computes known values is replaced with plain constant
+// pushes of those values, padded to the length of the run, so no offset in the program moves and
+// labels, locations and jumps stay as they were. The peephole optimizer can then shorten the
+// pushes and move the jumps along. A jump whose offset is computed like this becomes a constant
+// jump cfg can follow. Runs stop at every jump target, and since a jump with an unknown target
+// could land in the middle of one, code with a reachable such jump left after folding is not
+// touched at all.
+
+use std::num::Wrapping;
+
+use crate::analysis;
+use crate::cfg::{self, Jump, Target};
+use crate::optimize::{push, shortest};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Folded{
+    pub code:Vec<u8>,           // as long as the original
+    pub folds:usize,            // runs replaced
+    pub resolved:Vec<usize>,    // jumps that only have a known target after folding
+    pub dynamic:Option<usize>,  // the reachable jump with an unknown target that stopped folding
+}
+
+// Applies op to the known values on top of the stack, false when it needs more than those or is not pure
+fn apply(values:&mut Vec<i64>, op:u8) -> bool{
+    let needed = match op{
+        b'!' => 0,
+        b'0' | b'1' | b'=' => 1,
+        b'^' | b'|' | b'&' | b'+' | b'-' | b'*' | b'/' => 2,
+        _ => return false,
+    };
+    if values.len() < needed {return false}
+
+    match op{
+        b'!' => values.push(-1),
+        b'=' => values.push(values[values.len()-1]),
+        b'0' | b'1' => {
+            let a = values.pop().unwrap();
+            values.push((a << 1) | i64::from(op == b'1'));
+        },
+        _ => {
+            let b = values.pop().unwrap();
+            let a = values.pop().unwrap();
+            values.push(match op{
+                b'^' => a ^ b,
+                b'|' => a | b,
+                b'&' => a & b,
+                b'+' => (Wrapping(a) + Wrapping(b)).0,
+                b'-' => (Wrapping(a) - Wrapping(b)).0,
+                b'*' => a.wrapping_mul(b),
+                _ => if b != 0 {a.wrapping_div(b)} else {0},
+            });
+        },
+    }
+    true
+}
+
+// Constant pushes of values exactly len long, if they fit
+fn pushes(values:&[i64], len:usize) -> Option<Vec<u8>>{
+    let rest:usize = values[1..].iter().map(|v| shortest(*v)).sum();
+    if shortest(values[0]) + rest > len {return None}
+
+    let mut out = push(values[0], len - rest);
+    for value in &values[1..]{
+        out.extend(push(*value, 0));
+    }
+    Some(out)
+}
+
+pub fn fold(code:&[u8], strict:bool) -> Folded{
+    let unchanged = |dynamic| Folded{code: code.to_vec(), folds: 0, resolved: vec!(), dynamic};
+
+    // offsets runs may not go past, grows with every target found inside a run until none are
+    let mut barriers = vec![false; code.len()];
+    loop{
+        let (folded, runs) = rewrite(code, &barriers);
+        let analysis = analysis::analyse(&folded, strict);
+
+        let mut inside = vec![false; code.len()];
+        for (start, end) in &runs{
+            inside[start+1..*end].fill(true);
+        }
+
+        let jumps = cfg::jumps(&folded);
+        let mut split = false;
+        for offset in (0..folded.len()).filter(|o| folded[*o] == b'@'){
+            for (target, _) in cfg::successors(&folded, &jumps, offset, strict).targets{
+                if let Target::Offset(target) = target{
+                    if inside[target]{
+                        barriers[target] = true;
+                        split = true;
+                    }
+                }
+            }
+        }
+        if split {continue}
+
+        if let Some(offset) = (0..folded.len()).find(|o| folded[*o] == b'@' && jumps[*o] == Jump::Dynamic && analysis.states[*o].is_some()){
+            return unchanged(Some(offset));
+        }
+
+        let before = cfg::jumps(code);
+        let resolved = (0..code.len()).filter(|o| code[*o] == b'@' && before[*o] == Jump::Dynamic && jumps[*o] != Jump::Dynamic).collect();
+        return Folded{code: folded, folds: runs.len(), resolved, dynamic: None};
+    }
+}
+
+// The code with every run of known values replaced, and those runs (end is exclusive)
+fn rewrite(code:&[u8], barriers:&[bool]) -> (Vec<u8>, Vec<(usize, usize)>){
+    let mut folded = code.to_vec();
+    let mut runs = vec!();
+    let mut flush = |start:usize, end:usize, values:&mut Vec<i64>| {
+        if values.is_empty() {return}
+        if let Some(replacement) = pushes(values, end - start){
+            if replacement[..] != code[start..end]{
+                folded[start..end].copy_from_slice(&replacement);
+                runs.push((start, end));
+            }
+        }
+        values.clear();
+    };
+
+    let mut values = vec!();
+    let mut start = 0;
+    for (offset, op) in code.iter().enumerate(){
+        if barriers[offset]{
+            flush(start, offset, &mut values);
+        }
+        if values.is_empty(){
+            start = offset;
+        }
+        // nothing pure takes the last known value away, so a run ends only here
+        if !apply(&mut values, *op){
+            flush(start, offset, &mut values);
+        }
+    }
+    flush(start, code.len(), &mut values);
+
+    (folded, runs)
+}
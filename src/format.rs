@@ -0,0 +1,234 @@
+// Source formatter
+//
+// Keeps the line structure and every comment, but re-lays out what is around them: indentation
+// uses spaces (a tab counts as 4), code on a line is separated by single spaces, labels always
+// stand apart, trailing comments of consecutive lines are aligned to one column and long runs of
+// 0/1 can be grouped. Whitespace outside macros means nothing to the assembler, which is what
+// makes this safe; format still checks that the pure script came out the same.
+
+use crate::assembler::{self, Error};
+use crate::library;
+
+const TAB_WIDTH:usize = 4;
+
+#[derive(Debug, Clone, Default)]
+pub struct Options{
+    pub group:Option<usize>, // split runs of 0/1 longer than this into groups of this size, from the right
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Element{
+    Code(Vec<u8>),  // instructions (and stray characters) without whitespace
+    Macro(Vec<u8>), // including the brackets, verbatim
+    Label(Vec<u8>), // ':' and the name, plus the character that ended it when that was not whitespace
+}
+
+#[derive(Debug, Default)]
+struct Line{
+    indent:usize,                  // in columns
+    elements:Vec<(Element, bool)>, // with whether whitespace came before it
+    comment:Option<Vec<u8>>,       // from the '#', trailing whitespace removed
+}
+
+// Splits source into lines the way tokenise reads it, a macro may span lines and stays in the line it starts in
+fn lines(source:&[u8]) -> Vec<Line>{
+    let mut lines = vec!();
+    let mut line = Line::default();
+    let mut space = false;
+    let mut at_start = true;
+
+    let mut bytes = source.iter().copied().peekable();
+    while let Some(b) = bytes.next(){
+        match b{
+            b'\n' => {
+                lines.push(std::mem::take(&mut line));
+                space = false;
+                at_start = true;
+                continue;
+            },
+            _ if b.is_ascii_whitespace() => {
+                if at_start{
+                    line.indent = if b == b'\t' {(line.indent / TAB_WIDTH + 1) * TAB_WIDTH} else {line.indent + 1};
+                }
+                space = true;
+                continue;
+            },
+            b'#' => {
+                let mut comment = vec!(b);
+                while let Some(c) = bytes.next_if(|c| *c != b'\n'){
+                    comment.push(c);
+                }
+                comment.truncate(comment.iter().rposition(|c| !c.is_ascii_whitespace()).map_or(0, |p| p+1));
+                line.comment = Some(comment);
+            },
+            b'[' => {
+                let mut text = vec!(b);
+                for c in bytes.by_ref(){
+                    text.push(c);
+                    if c == b']' {break}
+                }
+                line.elements.push((Element::Macro(text), space));
+            },
+            b':' => {
+                let mut text = vec!(b);
+                while let Some(c) = bytes.next_if(u8::is_ascii_lowercase){
+                    text.push(c);
+                }
+                // the character ending a label is dropped by tokenise, keep it unless it is whitespace
+                if let Some(c) = bytes.next_if(|c| !c.is_ascii_whitespace()){
+                    text.push(c);
+                }
+                line.elements.push((Element::Label(text), space));
+            },
+            _ => {
+                match line.elements.last_mut(){
+                    Some((Element::Code(code), _)) if !space => code.push(b),
+                    _ => line.elements.push((Element::Code(vec!(b)), space)),
+                }
+            },
+        }
+        space = false;
+        at_start = false;
+    }
+    if line.comment.is_some() || !line.elements.is_empty() || source.last().is_some_and(|b| *b != b'\n'){
+        lines.push(line);
+    }
+
+    lines
+}
+
+fn is_digit(b:&u8) -> bool{
+    *b == b'0' || *b == b'1'
+}
+
+// Inserts a space every size digits (counted from the right) in runs of 0/1 longer than size
+fn group_digits(code:&[u8], size:usize) -> Vec<u8>{
+    let mut out = vec!();
+    let mut start = 0;
+    while start < code.len(){
+        let run = code[start..].iter().take_while(|b| is_digit(b)).count();
+        if run == 0{
+            out.push(code[start]);
+            start += 1;
+            continue;
+        }
+        let digits = &code[start..start+run];
+        for (index, digit) in digits.iter().enumerate(){
+            if run > size && index > 0 && (run - index) % size == 0{
+                out.push(b' ');
+            }
+            out.push(*digit);
+        }
+        start += run;
+    }
+    out
+}
+
+// Code of a line without indentation
+fn code_text(line:&Line, options:&Options) -> Vec<u8>{
+    let mut elements:Vec<Element> = vec!();
+    let mut out:Vec<u8> = vec!();
+
+    for (index, (element, space)) in line.elements.iter().enumerate(){
+        let previous = elements.last();
+        let separate = index > 0 && (*space || matches!(element, Element::Label(_)) || matches!(previous, Some(Element::Label(_))));
+
+        // with grouping on, digits split over several words are one run, regrouped below
+        if let (Some(_), Some(Element::Code(before)), Element::Code(code)) = (options.group, elements.last_mut(), element){
+            if before.last().is_some_and(is_digit) && code.first().is_some_and(is_digit){
+                before.extend(code);
+                continue;
+            }
+        }
+        if separate{
+            elements.push(Element::Code(vec!())); // marks a space
+        }
+        elements.push(element.clone());
+    }
+
+    for element in elements{
+        match element{
+            Element::Code(code) if code.is_empty() => out.push(b' '),
+            Element::Code(code) => match options.group{
+                Some(size) if size > 0 => out.extend(group_digits(&code, size)),
+                _ => out.extend(code),
+            },
+            Element::Macro(text) | Element::Label(text) => out.extend(text),
+        }
+    }
+    out
+}
+
+// The code of source without that of the library it includes, errors in the library are this source's
+// but have no location in it
+fn assemble(source:&[u8]) -> Result<Vec<u8>, Error>{
+    let linked = library::link(source);
+    match assembler::assemble(linked.source.clone()){
+        Ok(mut program) => {
+            program.code.truncate(linked.library_code(&program));
+            Ok(program.code)
+        },
+        Err(e) => Err(Error{location: e.location.filter(|l| !linked.in_library(*l)), ..e}),
+    }
+}
+
+// Formats source, fails when it does not assemble
+pub fn format(source:&[u8], options:&Options) -> Result<Vec<u8>, Error>{
+    let before = assemble(source)?;
+
+    let lines = lines(source);
+    let texts:Vec<Vec<u8>> = lines.iter().map(|line| {
+        let mut text = vec![b' '; line.indent];
+        text.extend(code_text(line, options));
+        text
+    }).collect();
+
+    // trailing comments are aligned over runs of lines that all have code and a comment
+    let aligned = |index:usize| lines[index].comment.is_some() && !lines[index].elements.is_empty() && !texts[index].contains(&b'\n');
+    let mut columns:Vec<usize> = vec![0; lines.len()];
+    let mut index = 0;
+    while index < lines.len(){
+        if !aligned(index){
+            index += 1;
+            continue;
+        }
+        let start = index;
+        while index < lines.len() && aligned(index){
+            index += 1;
+        }
+        let widest = texts[start..index].iter().map(Vec::len).max().unwrap_or(0);
+        let column = (widest + 2).div_ceil(TAB_WIDTH) * TAB_WIDTH;
+        columns[start..index].fill(column);
+    }
+
+    let mut out = vec!();
+    for ((line, mut text), column) in lines.iter().zip(texts).zip(columns){
+        if let Some(comment) = &line.comment{
+            if line.elements.is_empty(){
+                // a comment line keeps its indentation
+            }else if column > 0{
+                text.resize(column, b' ');
+            }else{
+                text.push(b' ');
+            }
+            text.extend(comment);
+        }
+        if text.iter().all(|b| *b == b' '){
+            text.clear();
+        }
+        out.extend(text);
+        out.push(b'\n');
+    }
+
+    // tokenise drops the code before a comment that runs to the end of the file, adding the newline would bring it back
+    if source.last().is_some_and(|b| *b != b'\n'){
+        out.pop();
+    }
+
+    let after = assemble(&out)?;
+    if after != before{
+        return Err(Error{message: "Formatting changed the program, file left as is".to_owned(), location: None});
+    }
+
+    Ok(out)
+}
@@ -0,0 +1,419 @@
+// Golden output tests for .sos programs
+//
+// Expectations live in a sidecar file next to the program (branch.sos -> branch.expect) or in the
+// program itself on comment lines starting with '#|'. Both hold the same 'key: value' lines:
+//
+//   [case uneven]        starts a case, keys before the first case apply to every case
+//   stdin: a             input, with \n \t \\ and \xHH escapes
+//   stdout: U            expected output, same escapes
+//   stacks: [] [85]      expected stack 0 and 1 at the end, bottom first
+//   exit: halted         halted, step-limit, stack-depleted or out-of-code (halted if not given)
+//   steps: 10..200       bounds on the number of executed instructions, either side may be left out
+//   limit: 1000          stops the program after this many steps (default 10000000)
+//   strict: true         defaults to what the #! line of the program asks for
+//
+// Updating rewrites stdout, stacks and exit of every case with what the run produced.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::assembler::{self, Program};
+use crate::library;
+use crate::vm::{self, BufferIo, Fault, Stop, Vm};
+
+const DEFAULT_LIMIT:u64 = 10_000_000;
+const EMBEDDED_PREFIX:&str = "#|";
+const SIDECAR_EXTENSION:&str = "expect";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exit{
+    Halted,
+    StepLimit,
+    StackDepleted,
+    OutOfCode,
+}
+
+impl Exit{
+    pub fn from_name(name:&str) -> Option<Exit>{
+        match name{
+            "halted" => Some(Exit::Halted),
+            "step-limit" => Some(Exit::StepLimit),
+            "stack-depleted" => Some(Exit::StackDepleted),
+            "out-of-code" => Some(Exit::OutOfCode),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str{
+        match self{
+            Exit::Halted => "halted",
+            Exit::StepLimit => "step-limit",
+            Exit::StackDepleted => "stack-depleted",
+            Exit::OutOfCode => "out-of-code",
+        }
+    }
+}
+
+// Values are trimmed when read, so spaces at either end are escaped too
+pub fn escape(bytes:&[u8]) -> String{
+    let mut out = String::new();
+    for (index, b) in bytes.iter().enumerate(){
+        match b{
+            b' ' if index == 0 || index+1 == bytes.len() => out.push_str("\\x20"),
+            b'\n' => out.push_str("\\n"),
+            b'\t' => out.push_str("\\t"),
+            b'\\' => out.push_str("\\\\"),
+            0x20..=0x7E => out.push(*b as char),
+            _ => out.push_str(&format!("\\x{:02X}", b)),
+        }
+    }
+    out
+}
+
+pub fn unescape(text:&str) -> Result<Vec<u8>, String>{
+    let mut out = vec!();
+    let mut bytes = text.bytes();
+    while let Some(b) = bytes.next(){
+        if b != b'\\'{
+            out.push(b);
+            continue;
+        }
+        match bytes.next(){
+            Some(b'n') => out.push(b'\n'),
+            Some(b't') => out.push(b'\t'),
+            Some(b'\\') => out.push(b'\\'),
+            Some(b'x') => {
+                let hex:Vec<u8> = bytes.by_ref().take(2).collect();
+                let hex = std::str::from_utf8(&hex).ok().and_then(|h| u8::from_str_radix(h, 16).ok());
+                out.push(hex.ok_or(format!("Invalid \\x escape in '{}'", text))?);
+            },
+            _ => return Err(format!("Invalid escape in '{}'", text)),
+        }
+    }
+    Ok(out)
+}
+
+fn format_stacks(stacks:&[Vec<i64>;2]) -> String{
+    let stack = |s:&Vec<i64>| format!("[{}]", s.iter().map(i64::to_string).collect::<Vec<_>>().join(", "));
+    format!("{} {}", stack(&stacks[0]), stack(&stacks[1]))
+}
+
+fn parse_stacks(text:&str) -> Result<[Vec<i64>;2], String>{
+    let invalid = || format!("Invalid stacks '{}', expected something like '[1, 2] []'", text);
+    let mut stacks:Vec<Vec<i64>> = vec!();
+    let mut rest = text.trim();
+    while let Some(open) = rest.strip_prefix('['){
+        let (items, after) = open.split_once(']').ok_or_else(invalid)?;
+        let items = items.split(',').map(str::trim).filter(|i| !i.is_empty()).map(str::parse).collect::<Result<Vec<i64>, _>>().map_err(|_| invalid())?;
+        stacks.push(items);
+        rest = after.trim_start();
+    }
+    match <[Vec<i64>;2]>::try_from(stacks){
+        Ok(stacks) if rest.is_empty() => Ok(stacks),
+        _ => Err(invalid()),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Case{
+    pub name:String,
+    pub stdin:Vec<u8>,
+    pub stdout:Option<Vec<u8>>,
+    pub stacks:Option<[Vec<i64>;2]>,
+    pub exit:Exit,
+    pub steps:(Option<u64>, Option<u64>),
+    pub limit:u64,
+    pub strict:bool,
+    lines:(usize, usize), // in the expectation text, from the case header (or first line) to the last line belonging to it
+}
+
+// A program and its expectations
+#[derive(Debug, Clone)]
+pub struct Suite{
+    pub program:PathBuf,
+    pub expectations:PathBuf, // the program itself when embedded
+    pub cases:Vec<Case>,
+    text:String,
+}
+
+impl Suite{
+    fn embedded(&self) -> bool{
+        self.program == self.expectations
+    }
+}
+
+pub fn sidecar(program:&Path) -> PathBuf{
+    program.with_extension(SIDECAR_EXTENSION)
+}
+
+// Sets key of case from value
+fn apply(case:&mut Case, key:&str, value:&str) -> Result<(), String>{
+    match key{
+        "stdin" => case.stdin = unescape(value)?,
+        "stdout" => case.stdout = Some(unescape(value)?),
+        "stacks" => case.stacks = Some(parse_stacks(value)?),
+        "exit" => case.exit = Exit::from_name(value).ok_or(format!("Unknown exit '{}'", value))?,
+        "steps" => {
+            let (lo, hi) = value.split_once("..").ok_or(format!("Invalid steps '{}', expected LO..HI", value))?;
+            let bound = |s:&str| if s.trim().is_empty() {Ok(None)} else {s.trim().parse().map(Some).map_err(|_| format!("Invalid steps '{}'", value))};
+            case.steps = (bound(lo)?, bound(hi)?);
+        },
+        "limit" => case.limit = value.parse().map_err(|_| format!("Invalid limit '{}'", value))?,
+        "strict" => case.strict = match value {"true" => true, "false" => false, _ => return Err(format!("Invalid strict '{}'", value))},
+        _ => return Err(format!("Unknown key '{}'", key)),
+    }
+    Ok(())
+}
+
+// The expectation lines of text (line index and content), prefix is what they start with
+fn expectation_lines<'a>(text:&'a str, prefix:&'a str) -> impl Iterator<Item=(usize, &'a str)> + 'a{
+    text.lines().enumerate().filter_map(move |(index, line)| {
+        let line = line.strip_prefix(prefix)?.trim();
+        if line.is_empty() || (prefix.is_empty() && line.starts_with('#')) {None} else {Some((index, line))}
+    })
+}
+
+fn parse(text:&str, prefix:&str, name:&str, strict:bool) -> Result<Vec<Case>, String>{
+    let mut defaults = Case{
+        name: name.to_owned(), stdin: vec!(), stdout: None, stacks: None, exit: Exit::Halted,
+        steps: (None, None), limit: DEFAULT_LIMIT, strict, lines: (0, 0),
+    };
+    let mut cases:Vec<Case> = vec!();
+    let mut first = true;
+
+    for (index, line) in expectation_lines(text, prefix){
+        if first{
+            defaults.lines = (index, index);
+            first = false;
+        }
+        if let Some(header) = line.strip_prefix("[case").and_then(|h| h.strip_suffix(']')){
+            let mut case = defaults.clone();
+            case.name = header.trim().to_owned();
+            case.lines = (index, index);
+            cases.push(case);
+            continue;
+        }
+        let Some((key, value)) = line.split_once(':') else {
+            return Err(format!("line {}: expected 'key: value' or '[case NAME]'", index+1));
+        };
+        let case = cases.last_mut().unwrap_or(&mut defaults);
+        apply(case, key.trim(), value.trim()).map_err(|e| format!("line {}: {}", index+1, e))?;
+        case.lines.1 = index;
+    }
+
+    if cases.is_empty() && !first{
+        cases.push(defaults);
+    }
+    Ok(cases)
+}
+
+// Reads the expectations of a program, None when it has none
+pub fn load(program:&Path) -> Result<Option<Suite>, String>{
+    let source = fs::read_to_string(program).map_err(|e| format!("{}: {}", program.display(), e))?;
+    let strict = source.lines().next().is_some_and(|line| line.starts_with("#!") && line.contains("--strict"));
+    let name = program.file_stem().map_or("".to_owned(), |s| s.to_string_lossy().into_owned());
+
+    let sidecar = sidecar(program);
+    let (expectations, text, prefix) = if sidecar.is_file(){
+        let text = fs::read_to_string(&sidecar).map_err(|e| format!("{}: {}", sidecar.display(), e))?;
+        (sidecar, text, "")
+    }else{
+        (program.to_owned(), source, EMBEDDED_PREFIX)
+    };
+
+    let cases = parse(&text, prefix, &name, strict).map_err(|e| format!("{}: {}", expectations.display(), e))?;
+    if cases.is_empty(){
+        return Ok(None);
+    }
+    Ok(Some(Suite{program: program.to_owned(), expectations, cases, text}))
+}
+
+// Directories a search does not go into: hidden ones like .git, and cargo's build output
+fn skipped(dir:&Path) -> bool{
+    dir.file_name().and_then(|n| n.to_str()).is_some_and(|n| n.starts_with('.') || n == "target")
+}
+
+// The .sos files in paths, directories are searched recursively
+pub fn discover(paths:&[PathBuf]) -> Vec<PathBuf>{
+    let mut found = vec!();
+    for path in paths{
+        if path.is_dir(){
+            let Ok(entries) = fs::read_dir(path) else {continue};
+            let mut entries:Vec<PathBuf> = entries.flatten().map(|e| e.path()).filter(|p| !(p.is_dir() && skipped(p))).collect();
+            entries.sort();
+            found.extend(discover(&entries).into_iter().filter(|p| p.extension().is_some_and(|e| e == "sos")));
+        }else{
+            found.push(path.clone());
+        }
+    }
+    found
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome{
+    pub stdout:Vec<u8>,
+    pub stacks:[Vec<i64>;2],
+    pub exit:Exit,
+    pub steps:u64,
+}
+
+pub fn run(program:&Program, case:&Case) -> Outcome{
+    let mut vm = Vm::new(program.code.clone(), case.strict);
+    vm.max_steps = Some(case.limit);
+    let mut io = BufferIo::new(case.stdin.clone());
+
+    let exit = match vm::run(&mut vm, &mut io, &mut []){
+        Ok(Stop::Halted) => Exit::Halted,
+        Ok(Stop::StepLimit | Stop::Interrupted) => Exit::StepLimit, // no observers, so never interrupted
+        Err(Fault::StackDepleted) => Exit::StackDepleted,
+        Err(Fault::OutOfCode(_)) => Exit::OutOfCode,
+    };
+
+    Outcome{stdout: io.output, stacks: vm.stacks, exit, steps: vm.steps}
+}
+
+// Line diff of expected and actual, lines prefixed by ' ', '-' (expected only) or '+' (actual only)
+pub fn diff(expected:&str, actual:&str) -> Vec<String>{
+    let a:Vec<&str> = expected.split('\n').collect();
+    let b:Vec<&str> = actual.split('\n').collect();
+
+    // longest common subsequence lengths of the suffixes
+    let mut lcs = vec![vec![0usize; b.len()+1]; a.len()+1];
+    for i in (0..a.len()).rev(){
+        for j in (0..b.len()).rev(){
+            lcs[i][j] = if a[i] == b[j] {lcs[i+1][j+1] + 1} else {lcs[i+1][j].max(lcs[i][j+1])};
+        }
+    }
+
+    let mut out = vec!();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len(){
+        if i < a.len() && j < b.len() && a[i] == b[j]{
+            out.push(format!(" {}", a[i]));
+            i += 1;
+            j += 1;
+        }else if j < b.len() && (i == a.len() || lcs[i][j+1] >= lcs[i+1][j]){
+            out.push(format!("+{}", b[j]));
+            j += 1;
+        }else{
+            out.push(format!("-{}", a[i]));
+            i += 1;
+        }
+    }
+    out
+}
+
+// What is wrong with outcome, nothing when the case passes
+pub fn check(case:&Case, outcome:&Outcome) -> Vec<String>{
+    let mut failures = vec!();
+
+    if outcome.exit != case.exit{
+        failures.push(format!("exit: expected {}, got {}", case.exit.name(), outcome.exit.name()));
+    }
+    if let Some(stdout) = &case.stdout{
+        if *stdout != outcome.stdout{
+            let expected = String::from_utf8_lossy(stdout);
+            let actual = String::from_utf8_lossy(&outcome.stdout);
+            let mut message = "stdout differs (-expected +actual):".to_owned();
+            for line in diff(&expected, &actual){
+                message.push_str("\n    ");
+                message.push_str(&line);
+            }
+            if expected == actual{
+                message.push_str(&format!("\n    expected {}\n    got      {}", escape(stdout), escape(&outcome.stdout)));
+            }
+            failures.push(message);
+        }
+    }
+    if let Some(stacks) = &case.stacks{
+        if *stacks != outcome.stacks{
+            failures.push(format!("stacks: expected {}, got {}", format_stacks(stacks), format_stacks(&outcome.stacks)));
+        }
+    }
+    match case.steps{
+        (Some(lo), _) if outcome.steps < lo => failures.push(format!("steps: expected at least {}, took {}", lo, outcome.steps)),
+        (_, Some(hi)) if outcome.steps > hi => failures.push(format!("steps: expected at most {}, took {}", hi, outcome.steps)),
+        _ => {},
+    }
+
+    failures
+}
+
+// Text of the expectations with stdout, stacks and exit of every case replaced by its outcome
+pub fn update(suite:&Suite, outcomes:&[Outcome]) -> String{
+    let prefix = if suite.embedded() {format!("{} ", EMBEDDED_PREFIX)} else {"".to_owned()};
+    let mut lines:Vec<String> = suite.text.lines().map(str::to_owned).collect();
+    let key_of = |line:&str| -> Option<String>{
+        let line = if suite.embedded() {line.strip_prefix(EMBEDDED_PREFIX)?} else {line};
+        Some(line.split_once(':')?.0.trim().to_owned())
+    };
+
+    // from the last case up so line numbers of the earlier ones stay valid
+    for (case, outcome) in suite.cases.iter().zip(outcomes).rev(){
+        let values = [
+            ("stdout", escape(&outcome.stdout)),
+            ("stacks", format_stacks(&outcome.stacks)),
+            ("exit", outcome.exit.name().to_owned()),
+        ];
+        let mut missing = vec!();
+        for (key, value) in values{
+            let line = format!("{}{}: {}", prefix, key, value).trim_end().to_owned();
+            match (case.lines.0..=case.lines.1).find(|i| key_of(&lines[*i]).as_deref() == Some(key)){
+                Some(index) => lines[index] = line,
+                None => missing.push(line),
+            }
+        }
+        let at = (case.lines.1 + 1).min(lines.len());
+        lines.splice(at..at, missing);
+    }
+
+    let mut text = lines.join("\n");
+    if suite.text.ends_with('\n') || suite.text.is_empty(){
+        text.push('\n');
+    }
+    text
+}
+
+// Result of running all cases of one suite
+pub struct Report{
+    pub suite:Suite,
+    pub outcomes:Vec<Result<Outcome, String>>, // Err when the program does not assemble
+}
+
+pub fn run_suite(suite:Suite) -> Report{
+    let program = fs::read(&suite.program).map_err(|e| e.to_string()).and_then(|source| assembler::assemble(library::link(&source).source).map_err(|e| e.to_string()));
+    let outcomes = suite.cases.iter().map(|case| program.as_ref().map(|p| run(p, case)).map_err(Clone::clone)).collect();
+    Report{suite, outcomes}
+}
+
+// Runs every suite on jobs threads, reports come back in the order of suites
+pub fn run_all(suites:Vec<Suite>, jobs:usize) -> Vec<Report>{
+    use std::sync::Mutex;
+
+    let count = suites.len();
+    let queue = Mutex::new(suites.into_iter().enumerate());
+    let reports:Mutex<Vec<Option<Report>>> = Mutex::new((0..count).map(|_| None).collect());
+
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.max(1).min(count){
+            scope.spawn(|| loop{
+                let Some((index, suite)) = queue.lock().unwrap().next() else {break};
+                let report = run_suite(suite);
+                reports.lock().unwrap()[index] = Some(report);
+            });
+        }
+    });
+
+    reports.into_inner().unwrap().into_iter().flatten().collect()
+}
+
+// Writes the expectations of the report back with its outcomes, when it has any
+pub fn write_update(report:&Report) -> Result<bool, String>{
+    let Ok(outcomes) = report.outcomes.iter().cloned().collect::<Result<Vec<Outcome>, String>>() else {return Ok(false)};
+    let text = update(&report.suite, &outcomes);
+    if text == report.suite.text{
+        return Ok(false);
+    }
+    fs::write(&report.suite.expectations, text).map_err(|e| format!("{}: {}", report.suite.expectations.display(), e))?;
+    Ok(true)
+}
@@ -0,0 +1,163 @@
+// Control flow graph export to Graphviz DOT and Mermaid
+//
+// Nodes are the basic blocks from cfg, named after the label region they start in. Edges that
+// were resolved statically are solid, every unresolved jump gets a dashed edge to a '?' node.
+
+use std::fmt::Write;
+
+use crate::assembler::Program;
+use crate::cfg::{self, Block, Edge, Target};
+
+// Longest bit of code shown inside a node
+const CODE_WIDTH:usize = 24;
+
+// Name of the region before the first label
+const TOP:&str = "(top)";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format{
+    Dot,
+    Mermaid,
+}
+
+impl Format{
+    pub fn from_name(name:&str) -> Option<Format>{
+        match name{
+            "dot" => Some(Format::Dot),
+            "mermaid" => Some(Format::Mermaid),
+            _ => None,
+        }
+    }
+}
+
+fn edge_label(edge:Edge) -> &'static str{
+    match edge{
+        Edge::Next => "",
+        Edge::Constant => "const",
+        Edge::Taken => "taken",
+        Edge::NotTaken => "not taken",
+    }
+}
+
+// Title, offsets and (the start of) the code of a block, one per line
+fn block_text(program:&Program, block:&Block) -> Vec<String>{
+    let code = String::from_utf8_lossy(&program.code[block.start..block.end]);
+    let code = if code.len() > CODE_WIDTH {format!("{}...", &code[..CODE_WIDTH])} else {code.into_owned()};
+
+    let title = match (program.label_at(block.start), block.start){
+        (Some(_), _) => program.symbolise(block.start),
+        (None, 0) => TOP.to_owned(),
+        (None, start) => format!("{}+{}", TOP, start),
+    };
+    let mut lines = vec!(title, format!("{:#X}..{:#X}", block.start, block.end-1));
+    if let Some(location) = program.location(block.start){
+        lines[1].push_str(&format!(" (line {})", location.line));
+    }
+    lines.push(code);
+    lines
+}
+
+pub fn export(program:&Program, strict:bool, format:Format) -> String{
+    let labels:Vec<usize> = program.labels.iter().map(|l| l.offset).collect();
+    let blocks = cfg::blocks(&program.code, &labels, strict);
+    let reachable = cfg::reachable(&blocks);
+    let fully_resolved = !blocks.iter().zip(&reachable).any(|(b, r)| *r && b.successors.dynamic);
+
+    match format{
+        Format::Dot => dot(program, &blocks, &reachable, fully_resolved),
+        Format::Mermaid => mermaid(program, &blocks, &reachable, fully_resolved),
+    }
+}
+
+fn dot(program:&Program, blocks:&[Block], reachable:&[bool], fully_resolved:bool) -> String{
+    let escape = |s:&str| s.replace('\\', "\\\\").replace('"', "\\\"");
+    let mut out = String::new();
+
+    let _ = writeln!(out, "digraph sos {{");
+    let _ = writeln!(out, "    node [shape=box, fontname=\"monospace\"];");
+    let _ = writeln!(out, "    entry [shape=point];");
+    let _ = writeln!(out, "    halt [shape=doublecircle, label=\"halt\"];");
+    let _ = writeln!(out, "    entry -> b0;");
+
+    for (block, reached) in blocks.iter().zip(reachable){
+        let label:Vec<String> = block_text(program, block).iter().map(|l| escape(l)).collect();
+        let style = if !reached && fully_resolved {", style=dashed, color=gray"} else {""};
+        let _ = writeln!(out, "    b{} [label=\"{}\\l\"{}];", block.start, label.join("\\l"), style);
+    }
+
+    let mut dynamic = false;
+    let mut out_of_code = false;
+    for block in blocks{
+        for (target, edge) in &block.successors.targets{
+            let to = match target{
+                Target::Offset(offset) => format!("b{}", offset),
+                Target::Halt => "halt".to_owned(),
+                Target::OutOfCode => {out_of_code = true; "out_of_code".to_owned()},
+            };
+            match edge_label(*edge){
+                "" => {let _ = writeln!(out, "    b{} -> {};", block.start, to);},
+                label => {let _ = writeln!(out, "    b{} -> {} [label=\"{}\"];", block.start, to, label);},
+            }
+        }
+        if block.successors.dynamic{
+            dynamic = true;
+            let _ = writeln!(out, "    b{} -> dynamic [style=dashed, label=\"dynamic\"];", block.start);
+        }
+    }
+    if dynamic{
+        let _ = writeln!(out, "    dynamic [shape=diamond, style=dashed, label=\"?\"];");
+    }
+    if out_of_code{
+        let _ = writeln!(out, "    out_of_code [shape=octagon, label=\"out of code\"];");
+    }
+
+    let _ = writeln!(out, "}}");
+    out
+}
+
+fn mermaid(program:&Program, blocks:&[Block], reachable:&[bool], fully_resolved:bool) -> String{
+    // mermaid uses '#' for entity codes, so quotes and hashes need one
+    let escape = |s:&str| s.replace('#', "#35;").replace('"', "#quot;");
+    let mut out = String::new();
+
+    let _ = writeln!(out, "flowchart TD");
+    let _ = writeln!(out, "    entry(( )) --> b0");
+    let _ = writeln!(out, "    halt(((halt)))");
+
+    for (block, reached) in blocks.iter().zip(reachable){
+        let label:Vec<String> = block_text(program, block).iter().map(|l| escape(l)).collect();
+        let _ = writeln!(out, "    b{}[\"{}\"]", block.start, label.join("<br>"));
+        if !reached && fully_resolved{
+            let _ = writeln!(out, "    class b{} unreachable", block.start);
+        }
+    }
+
+    let mut dynamic = false;
+    let mut out_of_code = false;
+    for block in blocks{
+        for (target, edge) in &block.successors.targets{
+            let to = match target{
+                Target::Offset(offset) => format!("b{}", offset),
+                Target::Halt => "halt".to_owned(),
+                Target::OutOfCode => {out_of_code = true; "out_of_code".to_owned()},
+            };
+            match edge_label(*edge){
+                "" => {let _ = writeln!(out, "    b{} --> {}", block.start, to);},
+                label => {let _ = writeln!(out, "    b{} -->|{}| {}", block.start, label, to);},
+            }
+        }
+        if block.successors.dynamic{
+            dynamic = true;
+            let _ = writeln!(out, "    b{} -.->|dynamic| dynamic", block.start);
+        }
+    }
+    if dynamic{
+        let _ = writeln!(out, "    dynamic{{?}}");
+    }
+    if out_of_code{
+        let _ = writeln!(out, "    out_of_code{{{{out of code}}}}");
+    }
+    let _ = writeln!(out, "    classDef unreachable stroke-dasharray: 5 5, color:gray");
+
+    out
+}
@@ -0,0 +1,245 @@
+// Just enough JSON to read back what we write ourselves (traces and the like)
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value{
+    Null,
+    Bool(bool),
+    Number(f64),
+    Int(i64), // numbers without fraction or exponent, so 64 bit stack values survive a round trip
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value{
+    pub fn get(&self, key:&str) -> Option<&Value>{
+        match self{
+            Value::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64>{
+        match self{
+            Value::Int(i) => Some(*i),
+            Value::Number(n) if n.fract() == 0.0 => Some(*n as i64),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str>{
+        match self{
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&Vec<Value>>{
+        match self{
+            Value::Array(a) => Some(a),
+            _ => None,
+        }
+    }
+}
+
+pub fn escape(s:&str) -> String{
+    let mut out = String::with_capacity(s.len()+2);
+    out.push('"');
+    for c in s.chars(){
+        match c{
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+impl fmt::Display for Value{
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result{
+        match self{
+            Value::Null => write!(f, "null"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Int(i) => write!(f, "{}", i),
+            Value::String(s) => write!(f, "{}", escape(s)),
+            Value::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate(){
+                    if i > 0 {write!(f, ",")?}
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            },
+            Value::Object(fields) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in fields.iter().enumerate(){
+                    if i > 0 {write!(f, ",")?}
+                    write!(f, "{}:{}", escape(key), value)?;
+                }
+                write!(f, "}}")
+            },
+        }
+    }
+}
+
+
+pub fn parse(text:&str) -> Result<Value, String>{
+    let mut parser = Parser{bytes: text.as_bytes(), pos: 0};
+    let value = parser.value()?;
+    parser.whitespace();
+    if parser.pos != parser.bytes.len(){
+        return Err(format!("Trailing characters at {}", parser.pos));
+    }
+    Ok(value)
+}
+
+struct Parser<'a>{
+    bytes:&'a [u8],
+    pos:usize,
+}
+
+impl Parser<'_>{
+    fn whitespace(&mut self){
+        while self.pos < self.bytes.len() && self.bytes[self.pos].is_ascii_whitespace(){
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<u8>{
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn expect(&mut self, b:u8) -> Result<(), String>{
+        if self.peek() == Some(b){
+            self.pos += 1;
+            Ok(())
+        }else{
+            Err(format!("Expected '{}' at {}", b as char, self.pos))
+        }
+    }
+
+    fn literal(&mut self, word:&str, value:Value) -> Result<Value, String>{
+        if self.bytes[self.pos..].starts_with(word.as_bytes()){
+            self.pos += word.len();
+            Ok(value)
+        }else{
+            Err(format!("Unexpected character at {}", self.pos))
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String>{
+        self.whitespace();
+        match self.peek(){
+            None => Err("Unexpected end of input".to_owned()),
+            Some(b'n') => self.literal("null", Value::Null),
+            Some(b't') => self.literal("true", Value::Bool(true)),
+            Some(b'f') => self.literal("false", Value::Bool(false)),
+            Some(b'"') => Ok(Value::String(self.string()?)),
+            Some(b'[') => {
+                self.pos += 1;
+                let mut items = vec!();
+                self.whitespace();
+                if self.peek() == Some(b']'){
+                    self.pos += 1;
+                    return Ok(Value::Array(items));
+                }
+                loop{
+                    items.push(self.value()?);
+                    self.whitespace();
+                    match self.peek(){
+                        Some(b',') => self.pos += 1,
+                        Some(b']') => {self.pos += 1; return Ok(Value::Array(items))},
+                        _ => return Err(format!("Expected ',' or ']' at {}", self.pos)),
+                    }
+                }
+            },
+            Some(b'{') => {
+                self.pos += 1;
+                let mut fields = vec!();
+                self.whitespace();
+                if self.peek() == Some(b'}'){
+                    self.pos += 1;
+                    return Ok(Value::Object(fields));
+                }
+                loop{
+                    self.whitespace();
+                    let key = self.string()?;
+                    self.whitespace();
+                    self.expect(b':')?;
+                    fields.push((key, self.value()?));
+                    self.whitespace();
+                    match self.peek(){
+                        Some(b',') => self.pos += 1,
+                        Some(b'}') => {self.pos += 1; return Ok(Value::Object(fields))},
+                        _ => return Err(format!("Expected ',' or '}}' at {}", self.pos)),
+                    }
+                }
+            },
+            Some(_) => self.number(),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String>{
+        self.expect(b'"')?;
+        let mut out:Vec<u8> = vec!();
+        loop{
+            let Some(b) = self.peek() else {return Err("Unterminated string".to_owned())};
+            self.pos += 1;
+            match b{
+                b'"' => break,
+                b'\\' => {
+                    let Some(e) = self.peek() else {return Err("Unterminated string".to_owned())};
+                    self.pos += 1;
+                    match e{
+                        b'"' => out.push(b'"'),
+                        b'\\' => out.push(b'\\'),
+                        b'/' => out.push(b'/'),
+                        b'b' => out.push(0x08),
+                        b'f' => out.push(0x0C),
+                        b'n' => out.push(b'\n'),
+                        b'r' => out.push(b'\r'),
+                        b't' => out.push(b'\t'),
+                        b'u' => {
+                            let hex = self.bytes.get(self.pos..self.pos+4).ok_or("Truncated \\u escape")?;
+                            let code = u32::from_str_radix(std::str::from_utf8(hex).map_err(|e| e.to_string())?, 16).map_err(|e| e.to_string())?;
+                            self.pos += 4;
+                            let c = char::from_u32(code).unwrap_or('\u{FFFD}');
+                            let mut buffer = [0;4];
+                            out.extend(c.encode_utf8(&mut buffer).as_bytes());
+                        },
+                        _ => return Err(format!("Invalid escape at {}", self.pos)),
+                    }
+                },
+                b => out.push(b),
+            }
+        }
+        String::from_utf8(out).map_err(|e| e.to_string())
+    }
+
+    fn number(&mut self) -> Result<Value, String>{
+        let start = self.pos;
+        while let Some(b) = self.peek(){
+            if b.is_ascii_digit() || [b'-', b'+', b'.', b'e', b'E'].contains(&b){
+                self.pos += 1;
+            }else{
+                break;
+            }
+        }
+        let s = std::str::from_utf8(&self.bytes[start..self.pos]).unwrap();
+        if let Ok(i) = s.parse::<i64>(){
+            return Ok(Value::Int(i));
+        }
+        match s.parse::<f64>(){
+            Ok(n) => Ok(Value::Number(n)),
+            Err(_) => Err(format!("Invalid number at {}", start)),
+        }
+    }
+}
@@ -0,0 +1,50 @@
+pub mod analysis;
+pub mod assembler;
+pub mod brainfuck;
+pub mod bytecode;
+pub mod c;
+pub mod cfg;
+pub mod coverage;
+pub mod explore;
+pub mod fast;
+pub mod fold;
+pub mod format;
+pub mod golden;
+pub mod graph;
+pub mod json;
+pub mod library;
+pub mod lint;
+pub mod loops;
+pub mod lsp;
+#[cfg(feature = "cranelift")]
+pub mod native;
+pub mod optimize;
+pub mod profile;
+pub mod replay;
+pub mod snapshot;
+pub mod structured;
+pub mod trace;
+pub mod vm;
+pub mod wasm;
+
+pub const TOKENS:[u8;16] = [ //encoding 1 nibble pertoken, 2 per byte
+    b'!', // HIGH ALL write new value to stack with all bits set to 1 (-1)
+    b'^', // XOR
+    b'|', // OR
+    b'&', // AND
+
+    b'+', // ADD
+    b'-', // SUB
+    b'*', // MUL (returns 2 stack numbners)
+    b'/', // DIV (/0  = 0, can be used for test by performing x/x (0 when equal 1 when not equal))
+
+    b'$', // Switch stack
+    b'~', // Head stackA <-> head stackB
+    b'=', // Duplicate top value
+    b'@', // SKIP aka JUMP (how much to jump extra after CP increases)
+
+    b'?', // READ fs to stack
+    b'.', // WRITE write byte to fs
+    b'0', // SHL 1
+    b'1', // SHL 1; | 1
+];
@@ -0,0 +1,50 @@
+// The standard library of routines in library/std.sos, which also describes the calling convention
+//
+// A program asks for it with a line '#include std', a comment to everything that doesn't know about
+// it. The library is appended, so the code of the program and the locations in it stay the same.
+
+use crate::assembler::{Location, Program};
+
+pub const STD:&str = include_str!("../library/std.sos");
+
+const INCLUDE:&str = "#include std";
+
+// Whether a line of source is the include, maybe with a comment after it
+pub fn includes(source:&[u8]) -> bool{
+    source.split(|b| *b == b'\n').any(|line| {
+        line.trim_ascii_start().strip_prefix(INCLUDE.as_bytes()).is_some_and(|rest| rest.first().is_none_or(u8::is_ascii_whitespace))
+    })
+}
+
+// Source with the library appended, and where in it the library starts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Linked{
+    pub source:Vec<u8>,
+    pub library_start:Option<usize>, // None when the source does not include it
+}
+
+impl Linked{
+    // Whether location in the linked source is in the library rather than the program
+    pub fn in_library(&self, location:Location) -> bool{
+        self.library_start.is_some_and(|start| location >= Location{line: 1, column: 1}.advance(&self.source[..start]))
+    }
+
+    // Offset in the code of program, assembled from this source, where the code of the library starts
+    pub fn library_code(&self, program:&Program) -> usize{
+        if self.library_start.is_none() {return program.code.len()}
+        program.locations.partition_point(|l| !self.in_library(*l))
+    }
+}
+
+// Source with the library appended when it includes it, as it was otherwise
+pub fn link(source:&[u8]) -> Linked{
+    let mut linked = source.to_vec();
+    if !includes(source){
+        return Linked{source: linked, library_start: None};
+    }
+    // a comment on the last line would swallow the start of the library
+    linked.push(b'\n');
+    let library_start = linked.len();
+    linked.extend(STD.as_bytes());
+    Linked{source: linked, library_start: Some(library_start)}
+}
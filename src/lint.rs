@@ -0,0 +1,299 @@
+// Linter for mistakes the assembler and interpreter happily accept
+//
+// Source rules look at the text the same way tokenise does, program rules at the assembled code
+// and the stack analysis. A comment like '# lint: allow(unused-label, stray-character)' silences
+// those rules on its own line, or on the next line when the comment is all there is on its line.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::TOKENS;
+use crate::analysis::{self, Finding, Severity};
+use crate::assembler::{self, Location, Program};
+use crate::cfg::{self, Jump};
+use crate::library::{self, Linked};
+
+// Expanded size of a macro, see assembler
+const MACRO_SIZE:usize = 65;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Rule{
+    ParseError,
+    UnusedLabel,
+    DuplicateLabel,
+    MisalignedJump,
+    UnreachableHalt,
+    StrayCharacter,
+    SwallowedCharacter,
+    LineEnding,
+    StackUnderflow,
+    UnboundedStack,
+    UnreachableCode,
+    DynamicJump,
+    OutOfCode,
+    UnknownRule,
+}
+
+impl Rule{
+    pub const ALL:[Rule;14] = [
+        Rule::ParseError, Rule::UnusedLabel, Rule::DuplicateLabel, Rule::MisalignedJump, Rule::UnreachableHalt,
+        Rule::StrayCharacter, Rule::SwallowedCharacter, Rule::LineEnding, Rule::StackUnderflow,
+        Rule::UnboundedStack, Rule::UnreachableCode, Rule::DynamicJump, Rule::OutOfCode, Rule::UnknownRule,
+    ];
+
+    pub fn name(self) -> &'static str{
+        match self{
+            Rule::ParseError => "parse-error",
+            Rule::UnusedLabel => "unused-label",
+            Rule::DuplicateLabel => "duplicate-label",
+            Rule::MisalignedJump => "misaligned-jump",
+            Rule::UnreachableHalt => "unreachable-halt",
+            Rule::StrayCharacter => "stray-character",
+            Rule::SwallowedCharacter => "swallowed-character",
+            Rule::LineEnding => "line-ending",
+            Rule::StackUnderflow => "stack-underflow",
+            Rule::UnboundedStack => "unbounded-stack",
+            Rule::UnreachableCode => "unreachable-code",
+            Rule::DynamicJump => "dynamic-jump",
+            Rule::OutOfCode => "out-of-code",
+            Rule::UnknownRule => "unknown-rule",
+        }
+    }
+
+    pub fn from_name(name:&str) -> Option<Rule>{
+        Rule::ALL.iter().copied().find(|r| r.name() == name)
+    }
+}
+
+impl fmt::Display for Rule{
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result{
+        write!(f, "{}", self.name())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic{
+    pub rule:Rule,
+    pub severity:Severity,
+    pub location:Option<Location>,
+    pub message:String,
+}
+
+impl Diagnostic{
+    fn new(rule:Rule, severity:Severity, location:Option<Location>, message:String) -> Diagnostic{
+        Diagnostic{rule, severity, location, message}
+    }
+}
+
+// What the source scan finds besides diagnostics: the rules allowed per line
+struct Scan{
+    diagnostics:Vec<Diagnostic>,
+    allowed:HashMap<usize, HashSet<Rule>>,
+}
+
+// Rules named in a '# lint: allow(...)' comment, None when the comment is not one
+fn allow_list(comment:&str) -> Option<Vec<&str>>{
+    let rest = comment.trim().strip_prefix("lint:")?.trim().strip_prefix("allow(")?;
+    let list = rest.split_once(')')?.0;
+    Some(list.split(',').map(str::trim).filter(|n| !n.is_empty()).collect())
+}
+
+// Walks the source with the same states as tokenise
+fn scan(source:&[u8]) -> Scan{
+
+    #[derive(Copy, Clone, PartialEq, Eq)]
+    enum State{
+        Script,
+        Comment,
+        Macro,
+        Label,
+    }
+
+    let mut diagnostics = vec!();
+    let mut allowed:HashMap<usize, HashSet<Rule>> = HashMap::new();
+
+    let mut state = State::Script;
+    let mut location = Location{line: 1, column: 1};
+    let mut comment:(Location, Vec<u8>) = (location, vec!());
+    let mut line_has_code = false;
+    let mut stray:Option<(Location, Vec<u8>)> = None;
+
+    let mut end_comment = |comment:&(Location, Vec<u8>), line_has_code:bool, diagnostics:&mut Vec<Diagnostic>|{
+        let text = String::from_utf8_lossy(&comment.1);
+        let Some(names) = allow_list(&text) else {return};
+        let line = if line_has_code {comment.0.line} else {comment.0.line + 1};
+        for name in names{
+            match Rule::from_name(name){
+                Some(rule) => {allowed.entry(line).or_default().insert(rule);},
+                None => diagnostics.push(Diagnostic::new(Rule::UnknownRule, Severity::Warning, Some(comment.0), format!("unknown lint rule '{}'", name))),
+            }
+        }
+    };
+    let end_stray = |stray:&mut Option<(Location, Vec<u8>)>, diagnostics:&mut Vec<Diagnostic>|{
+        if let Some((start, text)) = stray.take(){
+            diagnostics.push(Diagnostic::new(Rule::StrayCharacter, Severity::Warning, Some(start),
+                format!("'{}' is not an instruction and is ignored, put comments after '#'", String::from_utf8_lossy(&text))));
+        }
+    };
+
+    for (index, b) in source.iter().enumerate(){
+        let b = *b;
+
+        if b == b'\r'{
+            if source.get(index+1) == Some(&b'\n'){
+                // only once per file, editors and git tend to do all lines or none
+                if !diagnostics.iter().any(|d:&Diagnostic| d.rule == Rule::LineEnding){
+                    diagnostics.push(Diagnostic::new(Rule::LineEnding, Severity::Note, Some(location), "CRLF line endings".to_owned()));
+                }
+            }else{
+                diagnostics.push(Diagnostic::new(Rule::LineEnding, Severity::Warning, Some(location),
+                    "carriage return without line feed, it does not start a new line so line numbers will be off".to_owned()));
+            }
+        }
+
+        match state{
+            State::Script => {
+                if TOKENS.contains(&b) || b == b'[' || b == b':' {
+                    line_has_code = true;
+                }
+                if TOKENS.contains(&b) || b.is_ascii_whitespace() || b == b'#' || b == b'[' || b == b':'{
+                    end_stray(&mut stray, &mut diagnostics);
+                }else{
+                    line_has_code = true;
+                    stray.get_or_insert((location, vec!())).1.push(b);
+                }
+                match b{
+                    b'#' => {state = State::Comment; comment = (location, vec!());},
+                    b'[' => state = State::Macro,
+                    b':' => state = State::Label,
+                    _ => {},
+                }
+            },
+            State::Comment => {
+                if b == b'\n'{
+                    end_comment(&comment, line_has_code, &mut diagnostics);
+                    state = State::Script;
+                }else{
+                    comment.1.push(b);
+                }
+            },
+            State::Macro => {
+                if b == b']'{
+                    state = State::Script;
+                }
+            },
+            State::Label => {
+                if !b.is_ascii_lowercase(){
+                    if !b.is_ascii_whitespace(){
+                        let what = match b{
+                            b'#' => "the '#' so the comment after it".to_owned(),
+                            b'[' => "the '[' that should start a macro".to_owned(),
+                            b':' => "the ':' that should start a label".to_owned(),
+                            _ if TOKENS.contains(&b) => format!("the instruction '{}'", b as char),
+                            _ => format!("'{}'", String::from_utf8_lossy(&[b])),
+                        };
+                        diagnostics.push(Diagnostic::new(Rule::SwallowedCharacter, Severity::Warning, Some(location),
+                            format!("a label ends at the first character that is not a lowercase letter, which is dropped: this drops {}", what)));
+                    }
+                    state = State::Script;
+                }
+            },
+        }
+
+        if b == b'\n'{
+            end_stray(&mut stray, &mut diagnostics);
+            line_has_code = false;
+        }
+        location = location.advance(&[b]);
+    }
+    end_stray(&mut stray, &mut diagnostics);
+    if state == State::Comment{
+        end_comment(&comment, line_has_code, &mut diagnostics);
+    }
+
+    Scan{diagnostics, allowed}
+}
+
+// Rules that need the assembled program, only about the part of it that does not come from the library
+fn check_program(program:&Program, linked:&Linked, strict:bool) -> Vec<Diagnostic>{
+    let mut diagnostics = vec!();
+    let code = &program.code;
+    let library = linked.library_code(program);
+
+    let referenced:HashSet<&str> = program.macros.iter().flat_map(|m| m.references.iter().map(|(name, _)| name.as_str())).collect();
+    for (index, label) in program.labels.iter().enumerate(){
+        if linked.in_library(label.location) {continue}
+        if let Some(later) = program.labels[index+1..].iter().find(|l| l.name == label.name){
+            diagnostics.push(Diagnostic::new(Rule::DuplicateLabel, Severity::Warning, Some(label.location),
+                format!("label '{}' is defined again at {}:{}, macros use the last definition", label.name, later.location.line, later.location.column)));
+        }else if !referenced.contains(label.name.as_str()){
+            diagnostics.push(Diagnostic::new(Rule::UnusedLabel, Severity::Warning, Some(label.location),
+                format!("label '{}' is never referenced", label.name)));
+        }
+    }
+
+    let analysis = analysis::analyse(code, strict);
+    let jumps = cfg::jumps(code);
+
+    for offset in (0..library).filter(|o| code[*o] == b'@'){
+        let location = program.location(offset);
+        let offsets = match jumps[offset]{
+            Jump::Constant(-1) => {
+                if !analysis.dynamic && analysis.states[offset].is_none(){
+                    diagnostics.push(Diagnostic::new(Rule::UnreachableHalt, Severity::Warning, location,
+                        "this halt can never be reached".to_owned()));
+                }
+                continue;
+            },
+            // only guarded 'K*@' is Conditional, the others could land anywhere and are Dynamic, as are
+            // jumps some other jump lands in the middle of
+            Jump::Constant(value) | Jump::Conditional(value) => value,
+            Jump::Dynamic => continue,
+        };
+
+        let target = (offset as i128) + (offsets as i128) + 1;
+        if target < 0 || target >= code.len() as i128{
+            // strict mode reports this as out-of-code below
+            if !strict{
+                diagnostics.push(Diagnostic::new(Rule::MisalignedJump, Severity::Warning, location,
+                    format!("jump by {} lands outside the code ({} instructions), execution continues at 0", offsets, code.len())));
+            }
+            continue;
+        }
+        let target = target as usize;
+        if let Some(m) = program.macros.iter().find(|m| m.offset < target && target < m.offset + MACRO_SIZE){
+            diagnostics.push(Diagnostic::new(Rule::MisalignedJump, Severity::Warning, location,
+                format!("jump by {} lands {} instructions into the expansion of macro [{}] at {}:{}",
+                    offsets, target - m.offset, m.text, m.location.line, m.location.column)));
+        }
+    }
+
+    for finding in analysis.findings.iter().filter(|f| f.offset() < library){
+        let rule = match finding{
+            Finding::Underflow{..} => Rule::StackUnderflow,
+            Finding::Unbounded{..} => Rule::UnboundedStack,
+            Finding::Unreachable{..} => Rule::UnreachableCode,
+            Finding::DynamicJump{..} => Rule::DynamicJump,
+            Finding::OutOfCode{..} => Rule::OutOfCode,
+        };
+        diagnostics.push(Diagnostic::new(rule, finding.severity(strict), program.location(finding.offset()), finding.to_string()));
+    }
+
+    diagnostics
+}
+
+// All diagnostics for source that are not allowed by a comment, in source order
+pub fn lint(source:&[u8], strict:bool) -> Vec<Diagnostic>{
+    let Scan{mut diagnostics, allowed} = scan(source);
+
+    let linked = library::link(source);
+    match assembler::assemble(linked.source.clone()){
+        Ok(program) => diagnostics.extend(check_program(&program, &linked, strict)),
+        // the library assembles fine on its own, so the error is this source's even when it shows further on
+        Err(e) => diagnostics.push(Diagnostic::new(Rule::ParseError, Severity::Error, e.location.filter(|l| !linked.in_library(*l)), e.message)),
+    }
+
+    diagnostics.retain(|d| !d.location.is_some_and(|l| allowed.get(&l.line).is_some_and(|rules| rules.contains(&d.rule))));
+    diagnostics.sort_by_key(|d| d.location);
+    diagnostics
+}
@@ -0,0 +1,194 @@
+// Infinite loop detection at run time
+//
+// Detector finds exact cycles: the machine state (CP, active stack and both stacks) is compared with
+// one saved at every power of two steps (Brent's algorithm), which finds any cycle once the power
+// exceeds its period. Reading input starts over. Only a hash of the saved state is kept, and states with more than
+// MAX_HEIGHT values on the stacks are not compared, so each step costs at most MAX_HEIGHT.
+//
+// Watchdog only guesses: it warns when CP wraps past the end of the code (usually a missing '!@')
+// and when the program keeps running the same instructions without any input or output.
+
+use std::fmt::Write as _;
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use crate::assembler::Program;
+use crate::vm::{Effect, Observer, Vm};
+
+// How hard to look for loops
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Detection{
+    Exact,  // stop on a repeated state
+    Warn,   // warn on suspicious behaviour, keep running
+}
+
+impl Detection{
+    pub fn from_name(name:&str) -> Option<Detection>{
+        match name{
+            "exact" => Some(Detection::Exact),
+            "warn" => Some(Detection::Warn),
+            _ => None,
+        }
+    }
+}
+
+// Largest state (values on both stacks) that is compared
+pub const MAX_HEIGHT:usize = 4096;
+// Steps without input or output after which the watchdog compares what ran
+const WINDOW:u64 = 1 << 24;
+
+fn height(vm:&Vm) -> usize{
+    vm.stacks[0].len() + vm.stacks[1].len()
+}
+
+fn state_hash(vm:&Vm) -> u64{
+    let mut hasher = DefaultHasher::new();
+    (vm.cp, vm.active, &vm.stacks).hash(&mut hasher);
+    hasher.finish()
+}
+
+// Consecutive offsets merged into ranges
+fn ranges(offsets:&[bool]) -> Vec<(usize, usize)>{
+    let mut ranges:Vec<(usize, usize)> = vec!();
+    for (offset, _) in offsets.iter().enumerate().filter(|(_, v)| **v){
+        match ranges.last_mut(){
+            Some((_, end)) if *end + 1 == offset => *end = offset,
+            _ => ranges.push((offset, offset)),
+        }
+    }
+    ranges
+}
+
+fn describe(program:&Program, offsets:&[bool]) -> String{
+    let mut out = String::new();
+    for (start, end) in ranges(offsets){
+        if start == end{
+            let _ = writeln!(out, "  {:#018X}                       {}", start, program.symbolise(start));
+        }else{
+            let _ = writeln!(out, "  {:#018X} - {:#018X}  {} - {}", start, end, program.symbolise(start), program.symbolise(end));
+        }
+    }
+    out
+}
+
+// A state that came back: the program runs the same period steps forever
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cycle{
+    pub period:u64,
+    pub steps:u64,          // when the repeat was noticed
+    pub offsets:Vec<bool>,  // per code offset, executed within the cycle
+}
+
+impl Cycle{
+    pub fn report(&self, program:&Program) -> String{
+        let mut out = String::new();
+        let _ = writeln!(out, "Infinite loop: the machine state repeats every {} steps (noticed after {} steps), running:", self.period, self.steps);
+        out += &describe(program, &self.offsets);
+        out
+    }
+}
+
+struct Saved{
+    cp:usize,
+    active:usize,
+    heights:[usize;2],
+    hash:u64,
+}
+
+pub struct Detector{
+    saved:Option<Saved>,
+    power:u64,
+    distance:u64,           // steps since saved
+    executed:Vec<bool>,     // per code offset, executed since saved
+    pub cycle:Option<Cycle>,
+}
+
+impl Detector{
+    pub fn new(code_len:usize) -> Detector{
+        Detector{saved: None, power: 1, distance: 0, executed: vec![false; code_len], cycle: None}
+    }
+
+    fn save(&mut self, vm:&Vm){
+        self.saved = (height(vm) <= MAX_HEIGHT).then(|| Saved{
+            cp: vm.cp,
+            active: vm.active,
+            heights: [vm.stacks[0].len(), vm.stacks[1].len()],
+            hash: state_hash(vm),
+        });
+        self.executed.fill(false);
+        self.distance = 0;
+    }
+
+    fn repeats(&self, vm:&Vm) -> bool{
+        self.saved.as_ref().is_some_and(|s| {
+            s.cp == vm.cp && s.active == vm.active && s.heights == [vm.stacks[0].len(), vm.stacks[1].len()] && s.hash == state_hash(vm)
+        })
+    }
+}
+
+impl Observer for Detector{
+    fn after(&mut self, vm:&Vm, cp:usize, effect:&Effect){
+        if self.cycle.is_some() {return}
+        self.executed[cp] = true;
+        self.distance += 1;
+
+        // input is not part of the state, so nothing before a read can count as a repeat (EOF always reads the same)
+        if matches!(effect, Effect::Read(value) if *value != -1){
+            self.power = 1;
+            self.save(vm);
+        }else if self.repeats(vm){
+            self.cycle = Some(Cycle{period: self.distance, steps: vm.steps, offsets: self.executed.clone()});
+        }else if self.distance == self.power{
+            self.power *= 2;
+            self.save(vm);
+        }
+    }
+
+    fn stop(&self) -> bool{
+        self.cycle.is_some()
+    }
+}
+
+pub struct Watchdog<'a>{
+    program:&'a Program,
+    wrapped:bool,           // warned about wrapping already
+    suspected:bool,         // warned about a loop already
+    quiet:u64,              // steps since the last input or output
+    window:Vec<bool>,       // per code offset, executed in the current window
+    previous:Vec<bool>,     // same for the window before
+}
+
+impl Watchdog<'_>{
+    pub fn new(program:&Program) -> Watchdog<'_>{
+        let len = program.code.len();
+        Watchdog{program, wrapped: false, suspected: false, quiet: 0, window: vec![false; len], previous: vec![false; len]}
+    }
+}
+
+impl Observer for Watchdog<'_>{
+    fn after(&mut self, vm:&Vm, cp:usize, effect:&Effect){
+        let jump = if let Effect::Jump(offset) = effect {*offset as usize} else {0};
+        if !self.wrapped && vm.cp == 0 && cp.wrapping_add(jump).wrapping_add(1) != 0{
+            self.wrapped = true;
+            eprintln!("Warning: CP wrapped past the end of the code after {} steps at {} (missing '!@'?)", vm.steps, self.program.symbolise(cp));
+        }
+
+        // what ran before is left in the windows, it only delays the warning by a window
+        if matches!(effect, Effect::Read(_) | Effect::Write(_)){
+            self.quiet = 0;
+            return;
+        }
+        if self.suspected {return}
+
+        self.window[cp] = true;
+        self.quiet += 1;
+        if self.quiet.is_multiple_of(WINDOW){
+            if self.quiet > WINDOW && self.window == self.previous{
+                self.suspected = true;
+                eprintln!("Warning: possibly not terminating, {} steps without input or output, running:", self.quiet);
+                eprint!("{}", describe(self.program, &self.window));
+            }
+            std::mem::swap(&mut self.window, &mut self.previous);
+            self.window.fill(false);
+        }
+    }
+}
@@ -0,0 +1,353 @@
+// Language server for .sos files, spoken over stdio
+//
+// Documents are kept in full (full text sync) and simply assembled again for every request, they
+// are small. Diagnostics come from lint, which includes the macro and label errors of the
+// assembler. Positions are 0 based with the character counted in bytes, which is the same as
+// UTF-16 for the ASCII the assembler accepts.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+use crate::analysis::{self, Severity};
+use crate::assembler::{self, Location, Program};
+use crate::json::{self, Value};
+use crate::library::{self, Linked};
+use crate::lint;
+
+// JSON-RPC error codes
+const METHOD_NOT_FOUND:i64 = -32601;
+const INVALID_REQUEST:i64 = -32600;
+const PARSE_ERROR:i64 = -32700;
+
+fn object(fields:Vec<(&str, Value)>) -> Value{
+    Value::Object(fields.into_iter().map(|(k, v)| (k.to_owned(), v)).collect())
+}
+
+fn string(s:&str) -> Value{
+    Value::String(s.to_owned())
+}
+
+fn error(id:Value, code:i64, message:String) -> Value{
+    object(vec!(
+        ("jsonrpc", string("2.0")),
+        ("id", id),
+        ("error", object(vec!(("code", Value::Int(code)), ("message", Value::String(message))))),
+    ))
+}
+
+fn position(location:Location) -> Value{
+    object(vec!(
+        ("line", Value::Int(location.line as i64 - 1)),
+        ("character", Value::Int(location.column as i64 - 1)),
+    ))
+}
+
+fn range(start:Location, end:Location) -> Value{
+    object(vec!(("start", position(start)), ("end", position(end))))
+}
+
+// The location of an LSP position
+fn location(position:&Value) -> Option<Location>{
+    Some(Location{
+        line: position.get("line")?.as_i64()? as usize + 1,
+        column: position.get("character")?.as_i64()? as usize + 1,
+    })
+}
+
+// A file can ask for strict mode in its #! line, just like it does when run
+fn strict(text:&str) -> bool{
+    text.lines().next().is_some_and(|line| line.starts_with("#!") && line.contains("--strict"))
+}
+
+// A label name in the source, either where it is defined or used in a macro
+struct Symbol<'a>{
+    name:&'a str,
+    start:Location,
+    end:Location,
+    definition:bool,
+    library:bool, // in the included library, which has no place in the document
+}
+
+fn symbols<'a>(program:&'a Program, linked:&Linked) -> Vec<Symbol<'a>>{
+    let mut symbols = vec!();
+    for label in &program.labels{
+        let end = label.location.advance(b":").advance(label.name.as_bytes());
+        symbols.push(Symbol{name: &label.name, start: label.location, end, definition: true, library: linked.in_library(label.location)});
+    }
+    for m in &program.macros{
+        for (name, start) in &m.references{
+            symbols.push(Symbol{name, start: *start, end: start.advance(name.as_bytes()), definition: false, library: linked.in_library(*start)});
+        }
+    }
+    symbols
+}
+
+// Within start..end, the end counts so a cursor right after a word still finds it
+fn contains(start:Location, end:Location, at:Location) -> bool{
+    start <= at && at <= end
+}
+
+pub struct Server{
+    documents:HashMap<String, String>,
+    shutdown:bool,
+    exit:Option<i32>,
+}
+
+impl Default for Server{
+    fn default() -> Server{
+        Server::new()
+    }
+}
+
+impl Server{
+    pub fn new() -> Server{
+        Server{documents: HashMap::new(), shutdown: false, exit: None}
+    }
+
+    // Exit code once the client sent 'exit'
+    pub fn exit(&self) -> Option<i32>{
+        self.exit
+    }
+
+    // Handles one message and returns the messages to send back
+    pub fn handle(&mut self, message:&Value) -> Vec<Value>{
+        let method = message.get("method").and_then(Value::as_str).unwrap_or("");
+        let params = message.get("params").cloned().unwrap_or(Value::Null);
+        let id = message.get("id").cloned();
+
+        let mut out = vec!();
+        let result = match method{
+            "initialize" => Ok(self.initialize()),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            },
+            "exit" => {
+                self.exit = Some(if self.shutdown {0} else {1});
+                return out;
+            },
+            "textDocument/didOpen" | "textDocument/didChange" => {
+                let Some(uri) = params.get("textDocument").and_then(|d| d.get("uri")).and_then(Value::as_str) else {return out};
+                let text = match params.get("textDocument").and_then(|d| d.get("text")).and_then(Value::as_str){
+                    Some(text) => Some(text),
+                    None => params.get("contentChanges").and_then(Value::as_array).and_then(|c| c.last()).and_then(|c| c.get("text")).and_then(Value::as_str),
+                };
+                if let Some(text) = text{
+                    self.documents.insert(uri.to_owned(), text.to_owned());
+                    out.push(self.diagnostics(uri));
+                }
+                return out;
+            },
+            "textDocument/didClose" => {
+                if let Some(uri) = params.get("textDocument").and_then(|d| d.get("uri")).and_then(Value::as_str){
+                    self.documents.remove(uri);
+                    out.push(notification("textDocument/publishDiagnostics", object(vec!(("uri", string(uri)), ("diagnostics", Value::Array(vec!()))))));
+                }
+                return out;
+            },
+            "textDocument/definition" => Ok(self.with_program(&params, definition)),
+            "textDocument/references" => {
+                let declaration = params.get("context").and_then(|c| c.get("includeDeclaration")) == Some(&Value::Bool(true));
+                Ok(self.with_program(&params, |uri, program, linked, at| references(uri, program, linked, at, declaration)))
+            },
+            "textDocument/hover" => Ok(self.with_program(&params, |_, program, linked, at| hover(program, linked, at))),
+            "textDocument/inlayHint" => Ok(self.inlay_hints(&params)),
+            _ => Err((METHOD_NOT_FOUND, format!("Unknown method '{}'", method))),
+        };
+
+        // notifications (no id) never get an answer
+        let Some(id) = id else {return out};
+        let result = if self.shutdown && method != "shutdown" {Err((INVALID_REQUEST, "Server is shut down".to_owned()))} else {result};
+        out.push(match result{
+            Ok(result) => object(vec!(("jsonrpc", string("2.0")), ("id", id), ("result", result))),
+            Err((code, message)) => error(id, code, message),
+        });
+        out
+    }
+
+    fn initialize(&self) -> Value{
+        object(vec!(
+            ("capabilities", object(vec!(
+                ("textDocumentSync", Value::Int(1)), // full
+                ("definitionProvider", Value::Bool(true)),
+                ("referencesProvider", Value::Bool(true)),
+                ("hoverProvider", Value::Bool(true)),
+                ("inlayHintProvider", Value::Bool(true)),
+            ))),
+            ("serverInfo", object(vec!(("name", string("sos-lsp")), ("version", string(env!("CARGO_PKG_VERSION")))))),
+        ))
+    }
+
+    fn diagnostics(&self, uri:&str) -> Value{
+        let text = &self.documents[uri];
+        let diagnostics = lint::lint(text.as_bytes(), strict(text)).into_iter().map(|d| {
+            let start = d.location.unwrap_or(Location{line: 1, column: 1});
+            object(vec!(
+                ("range", range(start, start.advance(b" "))),
+                ("severity", Value::Int(match d.severity {Severity::Error => 1, Severity::Warning => 2, Severity::Note => 3})),
+                ("code", string(d.rule.name())),
+                ("source", string("sos")),
+                ("message", Value::String(d.message)),
+            ))
+        }).collect();
+        notification("textDocument/publishDiagnostics", object(vec!(("uri", string(uri)), ("diagnostics", Value::Array(diagnostics)))))
+    }
+
+    // Runs f on the assembled document and position of a text document position request, null when either is missing
+    fn with_program(&self, params:&Value, f:impl Fn(&str, &Program, &Linked, Location) -> Value) -> Value{
+        let Some(uri) = params.get("textDocument").and_then(|d| d.get("uri")).and_then(Value::as_str) else {return Value::Null};
+        let Some(text) = self.documents.get(uri) else {return Value::Null};
+        let Some(at) = params.get("position").and_then(location) else {return Value::Null};
+        let linked = library::link(text.as_bytes());
+        match assembler::assemble(linked.source.clone()){
+            Ok(program) => f(uri, &program, &linked, at),
+            Err(_) => Value::Null,
+        }
+    }
+
+    // Offset and stack heights at the end of every line that has code
+    fn inlay_hints(&self, params:&Value) -> Value{
+        let Some(uri) = params.get("textDocument").and_then(|d| d.get("uri")).and_then(Value::as_str) else {return Value::Null};
+        let Some(text) = self.documents.get(uri) else {return Value::Null};
+        let linked = library::link(text.as_bytes());
+        let Ok(program) = assembler::assemble(linked.source.clone()) else {return Value::Array(vec!())};
+        let analysis = analysis::analyse(&program.code, strict(text));
+
+        let first = params.get("range").and_then(|r| r.get("start")).and_then(location).map_or(1, |l| l.line);
+        let last = params.get("range").and_then(|r| r.get("end")).and_then(location).map_or(usize::MAX, |l| l.line);
+
+        // first instruction on every line, the code of an included library has no place in this document
+        let mut starts:Vec<(usize, usize)> = vec!();
+        for (offset, location) in program.locations[..linked.library_code(&program)].iter().enumerate(){
+            if starts.last().is_none_or(|(line, _)| *line != location.line){
+                starts.push((location.line, offset));
+            }
+        }
+
+        let lines:Vec<&str> = text.split('\n').collect();
+        let hints = starts.into_iter().filter(|(line, _)| first <= *line && *line <= last).map(|(line, offset)| {
+            let width = lines.get(line-1).map_or(0, |l| l.trim_end_matches('\r').len());
+            let label = match analysis.heights(offset){
+                [Some(a), Some(b)] => format!("{:#X}  {} | {}", offset, a, b),
+                _ => format!("{:#X}  unreached", offset),
+            };
+            object(vec!(
+                ("position", position(Location{line, column: width+1})),
+                ("label", Value::String(label)),
+                ("paddingLeft", Value::Bool(true)),
+                ("tooltip", Value::String(format!("offset {} and the heights of stack 0 | 1 before it", offset))),
+            ))
+        }).collect();
+        Value::Array(hints)
+    }
+}
+
+fn notification(method:&str, params:Value) -> Value{
+    object(vec!(("jsonrpc", string("2.0")), ("method", string(method)), ("params", params)))
+}
+
+fn lsp_location(uri:&str, start:Location, end:Location) -> Value{
+    object(vec!(("uri", string(uri)), ("range", range(start, end))))
+}
+
+// The definition macros use for a name, which is the last one
+fn defined<'a>(symbols:&'a [Symbol], name:&str) -> Option<&'a Symbol<'a>>{
+    symbols.iter().rfind(|s| s.definition && s.name == name)
+}
+
+fn definition(uri:&str, program:&Program, linked:&Linked, at:Location) -> Value{
+    let symbols = symbols(program, linked);
+    let Some(symbol) = symbols.iter().find(|s| !s.library && contains(s.start, s.end, at)) else {return Value::Null};
+    match defined(&symbols, symbol.name){
+        // the library is not a file the client could open
+        Some(d) if !d.library => lsp_location(uri, d.start, d.end),
+        _ => Value::Null,
+    }
+}
+
+fn references(uri:&str, program:&Program, linked:&Linked, at:Location, declaration:bool) -> Value{
+    let symbols = symbols(program, linked);
+    let Some(symbol) = symbols.iter().find(|s| !s.library && contains(s.start, s.end, at)) else {return Value::Null};
+    Value::Array(symbols.iter()
+        .filter(|s| !s.library && s.name == symbol.name && (declaration || !s.definition))
+        .map(|s| lsp_location(uri, s.start, s.end))
+        .collect())
+}
+
+fn hover(program:&Program, linked:&Linked, at:Location) -> Value{
+    let symbols = symbols(program, linked);
+    let (text, start, end) = if let Some(symbol) = symbols.iter().find(|s| !s.library && contains(s.start, s.end, at)){
+        let Some(label) = program.labels.iter().rfind(|l| l.name == symbol.name) else {return Value::Null};
+        (format!("label `{}` at offset {} ({:#X})", label.name, label.offset, label.offset), symbol.start, symbol.end)
+    }else if let Some(m) = program.macros.iter().find(|m| !linked.in_library(m.location) && contains(m.location, m.location.advance(format!("[{}]", m.text).as_bytes()), at)){
+        (format!("`[{}]` = {} ({:#X}), pushed at offset {} ({:#X})", m.text, m.value, m.value, m.offset, m.offset),
+            m.location, m.location.advance(format!("[{}]", m.text).as_bytes()))
+    }else{
+        return Value::Null;
+    };
+    object(vec!(
+        ("contents", object(vec!(("kind", string("markdown")), ("value", Value::String(text))))),
+        ("range", range(start, end)),
+    ))
+}
+
+// Reads one message with its Content-Length header, None at the end of input
+// The body of the next message, None at the end of input
+fn read_body(input:&mut impl BufRead) -> io::Result<Option<Vec<u8>>>{
+    let mut length:Option<usize> = None;
+    loop{
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0{
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty(){
+            if length.is_some() {break}
+            continue;
+        }
+        if let Some((name, value)) = header.split_once(':'){
+            if name.eq_ignore_ascii_case("content-length"){
+                length = value.trim().parse().ok();
+            }
+        }
+    }
+
+    let mut body = vec![0; length.unwrap_or(0)];
+    input.read_exact(&mut body)?;
+    Ok(Some(body))
+}
+
+fn parse_body(body:Vec<u8>) -> Result<Value, String>{
+    let body = String::from_utf8(body).map_err(|e| e.to_string())?;
+    json::parse(&body)
+}
+
+pub fn read_message(input:&mut impl BufRead) -> io::Result<Option<Value>>{
+    let Some(body) = read_body(input)? else {return Ok(None)};
+    parse_body(body).map(Some).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub fn write_message(output:&mut impl Write, message:&Value) -> io::Result<()>{
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+// Serves until the client exits, returns the exit code
+pub fn serve(mut input:impl BufRead, mut output:impl Write) -> io::Result<i32>{
+    let mut server = Server::new();
+    while let Some(body) = read_body(&mut input)?{
+        // the length was read fine so the next message is where it should be, only this one is lost
+        let replies = match parse_body(body){
+            Ok(message) => server.handle(&message),
+            Err(e) => vec!(error(Value::Null, PARSE_ERROR, format!("Parse error: {}", e))),
+        };
+        for reply in replies{
+            write_message(&mut output, &reply)?;
+        }
+        if let Some(code) = server.exit(){
+            return Ok(code);
+        }
+    }
+    Ok(1)
+}
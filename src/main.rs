@@ -1,52 +1,131 @@
 use std::fs::File;
 use std::env;
-use std::assert;
 use std::process::exit;
-use std::num::Wrapping;
-use std::arch::asm;
-use std::io::{Read, Write, stdin, stdout, stderr};
-use std::collections::HashMap;
-
-
-const TOKENS:[u8;16] = [ //encoding 1 nibble pertoken, 2 per byte
-    b'!', // HIGH ALL write new value to stack with all bits set to 1 (-1)
-    b'^', // XOR
-    b'|', // OR
-    b'&', // AND
-
-    b'+', // ADD
-    b'-', // SUB
-    b'*', // MUL (returns 2 stack numbners)
-    b'/', // DIV (/0  = 0, can be used for test by performing x/x (0 when equal 1 when not equal))
-
-    b'$', // Switch stack
-    b'~', // Head stackA <-> head stackB 
-    b'=', // Duplicate top value
-    b'@', // SKIP aka JUMP (how much to jump extra after CP increases)
-
-    b'?', // READ fs to stack
-    b'.', // WRITE write byte to fs
-    b'0', // SHL 1
-    b'1', // SHL 1; | 1
-];
+use std::io::{Read, Write, stdout};
+
+use stackofstacks::analysis::{self, Severity};
+use stackofstacks::assembler::{Program, assemble, disassemble};
+use stackofstacks::bytecode::{compile, bytecode};
+use stackofstacks::brainfuck;
+use stackofstacks::c;
+use stackofstacks::coverage::Coverage;
+use stackofstacks::explore::{self, Exploration, Rng, Source};
+use stackofstacks::fast;
+use stackofstacks::fold;
+use stackofstacks::format;
+use stackofstacks::golden;
+use stackofstacks::graph;
+use stackofstacks::library;
+use stackofstacks::lint::{self, Rule};
+#[cfg(feature = "cranelift")]
+use stackofstacks::native;
+use stackofstacks::optimize;
+use stackofstacks::loops::{Detection, Detector, Watchdog};
+use stackofstacks::profile::Profiler;
+use stackofstacks::replay::{self, Recorder, ReplayCheck, Replayer};
+use stackofstacks::snapshot::{Checkpoint, Snapshot};
+use stackofstacks::structured;
+use stackofstacks::trace::{self, Tracer};
+use stackofstacks::vm::{self, Vm, Io, StdIo, Stop, Fault, Debugger, Observer};
+use stackofstacks::wasm;
+
+
+// How to run the program, as given on the command line
+struct Options{
+    debug:bool,
+    strict:bool,
+    max_steps:Option<u64>,
+    tracer:Option<Tracer>,
+    profile:bool,
+    profile_listing:Option<String>,
+    profile_collapsed:Option<String>,
+    coverage:bool,
+    coverage_lcov:Option<String>,
+    loops:Option<Detection>,
+    optimize:bool,
+    jit:bool,
+    brainfuck:bool,
+    structured:bool,
+    target:Option<String>,
+    snapshot:Option<String>,
+    snapshot_every:Option<u64>,
+    resume:Option<String>,
+    recorder:Option<Recorder>,
+    replay:Option<String>,
+    seed:Option<u64>,
+    explore_size:usize,
+    explore_save:Option<String>,
+}
+
+// Value of a --name=VALUE option
+fn value<'a>(param:&'a str, name:&str) -> Option<&'a str>{
+    param.strip_prefix(name)?.strip_prefix('=')
+}
 
 fn main() {
 
     let mut args = env::args();
     args.next();
 
-    let mut debug = false;
-    let mut strict = false;
+    let mut options = Options{
+        debug: false,
+        strict: false,
+        max_steps: None,
+        tracer: None,
+        profile: false,
+        profile_listing: None,
+        profile_collapsed: None,
+        coverage: false,
+        coverage_lcov: None,
+        loops: None,
+        optimize: false,
+        jit: false,
+        brainfuck: false,
+        structured: false,
+        target: None,
+        snapshot: None,
+        snapshot_every: None,
+        resume: None,
+        recorder: None,
+        replay: None,
+        seed: None,
+        explore_size: 16,
+        explore_save: None,
+    };
 
     let params:Vec<String> = args.collect();
+
+    if params.first().map(String::as_str) == Some("lint"){
+        lint_files(&params[1..]);
+        return;
+    }
+    if params.first().map(String::as_str) == Some("fmt"){
+        format_files(&params[1..]);
+        return;
+    }
+    if params.first().map(String::as_str) == Some("test"){
+        test_files(&params[1..]);
+        return;
+    }
     let mut filename = "".to_owned();
 
-    let mut mode = Mode::RUN;
+    let mut mode = Mode::Run;
     enum Mode{
-        RUN,
-        COMPILE,
-        BYTECODE,
-        DUMP,
+        Run,
+        Compile,
+        EmitC,
+        EmitWasm,
+        EmitWat,
+        EmitObject,
+        EmitSos,
+        EmitBrainfuck,
+        Bytecode,
+        Dump,
+        Disassemble,
+        ReadTrace,
+        Analyse,
+        Cfg(graph::Format),
+        Explore(Option<u64>),
     }
 
 
@@ -54,52 +133,306 @@ fn main() {
         let param = p.as_str();
 
         if param.starts_with("--"){
-            match(param){
+            match param{
                 "--help" => {
                     eprintln!("Usage stackofstacks [--debug, --compile, --bytecode] FILENAME");
+                    eprintln!("      stackofstacks lint [--strict, --allow=RULE, --deny=RULE] FILENAME...");
+                    eprintln!("      stackofstacks fmt [--check, --stdout, --group=N] FILENAME...");
+                    eprintln!("      stackofstacks test [--update, --jobs=N] [PATH...]");
+                    eprintln!("  A line '#include std' appends the standard library, see library/std.sos");
                     eprintln!("  --debug     Shows debug / trace on STDERR while runniogn program");
                     eprintln!("  --dump      Dumps the raw (macro expanded) code");
                     eprintln!("  --strict    Aborts when popping from empty stack or accessing uninitialised ram");
                     eprintln!("  --compile   Compiles program to bytecode (emitted on STDOUT)");
                     eprintln!("  --bytecode  Runs compiled bytecode instead of text");
-                },                
+                    eprintln!("  --emit-c    Translates program to a C file (emitted on STDOUT), compile it with any cc,");
+                    eprintln!("              add -DSOS_MAX_STEPS=N for a step limit and -DSOS_STACKS to see the stacks at the end");
+                    eprintln!("  --emit-wasm Translates program to a WebAssembly module (emitted on STDOUT) importing");
+                    eprintln!("              sos.read_byte and sos.write_byte and exporting run(max_steps)");
+                    eprintln!("  --emit-wat  Same as --emit-wasm in the WebAssembly text format");
+                    eprintln!("  --emit-object");
+                    eprintln!("              Compiles program with Cranelift to an object file (emitted on STDOUT) defining");
+                    eprintln!("              sos_run, see src/native.rs for how to call it");
+                    eprintln!("  --target=TRIPLE");
+                    eprintln!("              Architecture for --emit-object, like aarch64-unknown-linux-gnu (default: this one)");
+                    eprintln!("  --brainfuck Reads FILENAME as Brainfuck, translated to Stack Of Stacks (run it without --strict)");
+                    eprintln!("  --structured Reads FILENAME as the structured language, compiled to Stack Of Stacks");
+                    eprintln!("  --emit-sos  Writes the source of the program (emitted on STDOUT), the translation with --brainfuck");
+                    eprintln!("  --emit-bf   Translates program to Brainfuck (emitted on STDOUT) with 64 bit values as bits,");
+                    eprintln!("              it needs a tape endless both ways and reads 0 bytes as EOF");
+                    eprintln!("  --jit       Compiles program to native code with Cranelift before running it, unless");
+                    eprintln!("              it is being observed (--debug, --profile, --trace and the like)");
+                    eprintln!("  --disassemble");
+                    eprintln!("              Prints compiled bytecode as source (emitted on STDOUT)");
+                    eprintln!("  --analyse   Checks stack heights statically, lists them per line on STDOUT and");
+                    eprintln!("              reports underflows, unbounded growth and unreachable code on STDERR");
+                    eprintln!("  --cfg[=FORMAT]");
+                    eprintln!("              Prints the control flow graph on STDOUT, FORMAT is 'dot' (default) or 'mermaid'");
+                    eprintln!("  --explore[=COUNT]");
+                    eprintln!("              Runs COUNT (default: until interrupted) random bytecode programs, or programs");
+                    eprintln!("              read from FILENAME in chunks, and reports how they end on STDERR. Every new");
+                    eprintln!("              interesting behaviour is printed on STDOUT. Step limit is 10000 unless given");
+                    eprintln!("  --seed=N    Seed for --explore");
+                    eprintln!("  --explore-size=N");
+                    eprintln!("              Largest program --explore makes in bytes (default 16)");
+                    eprintln!("  --explore-save=DIR");
+                    eprintln!("              Saves interesting programs found by --explore as bytecode in DIR");
+                    eprintln!("  --max-steps=N");
+                    eprintln!("              Stops the program after executing N instructions");
+                    eprintln!("  --trace=FORMAT:FILE");
+                    eprintln!("              Writes an execution trace to FILE, FORMAT is 'jsonl' or 'bin'");
+                    eprintln!("  --read-trace");
+                    eprintln!("              Prints the trace FILENAME (either format) as JSON lines on STDOUT");
+                    eprintln!("  --profile   Prints hot instructions, labels and jumps on STDERR after running");
+                    eprintln!("  --profile-listing=FILE");
+                    eprintln!("              Writes the source annotated with execution counts to FILE");
+                    eprintln!("  --profile-collapsed=FILE");
+                    eprintln!("              Writes collapsed stacks (flamegraph input) to FILE");
+                    eprintln!("  --coverage  Prints instruction and branch coverage per label on STDERR after running");
+                    eprintln!("  --coverage-lcov=FILE");
+                    eprintln!("              Writes coverage as an lcov tracefile to FILE");
+                    eprintln!("  --detect-loops[=MODE]");
+                    eprintln!("              MODE 'exact' (default) stops when the machine comes back to an earlier state");
+                    eprintln!("              and lists the instructions of the loop, 'warn' only warns on STDERR when CP");
+                    eprintln!("              wraps past the end of the code or no input or output happens for a long time");
+                    eprintln!("  --optimize  Folds constants and rewrites wasteful instruction sequences before running,");
+                    eprintln!("              compiling or dumping, with --analyse and --cfg only folds (offsets stay the same)");
+                    eprintln!("  --snapshot=FILE");
+                    eprintln!("              Saves the machine state to FILE when the program stops without halting");
+                    eprintln!("              (step limit, loop detected)");
+                    eprintln!("  --snapshot-every=N");
+                    eprintln!("              Also saves the state every N steps");
+                    eprintln!("  --resume=FILE");
+                    eprintln!("              Continues from the state saved in FILE, skipping the input read before.");
+                    eprintln!("              Steps count on from the snapshot, so --max-steps is the total");
+                    eprintln!("  --record=FILE");
+                    eprintln!("              Writes every value '?' reads (EOF included) with its step number to FILE");
+                    eprintln!("  --replay=FILE");
+                    eprintln!("              Reads input from a recording instead of STDIN, fails when the program");
+                    eprintln!("              reads at other steps than recorded");
+                    eprintln!();
+                    eprintln!("lint checks for common mistakes, RULE is one of:");
+                    eprintln!("  {}", Rule::ALL.map(Rule::name).join(", "));
+                    eprintln!("  --allow=RULE  Does not report RULE");
+                    eprintln!("  --deny=RULE   Reports RULE as an error");
+                    eprintln!("  A comment '# lint: allow(RULE, ...)' does the same as --allow for its line,");
+                    eprintln!("  or for the next line when the comment is on a line of its own");
+                    eprintln!();
+                    eprintln!("fmt rewrites files with consistent spacing and aligned comments");
+                    eprintln!("  --check       Only lists the files that are not formatted, exits 1 if there are any");
+                    eprintln!("  --stdout      Prints the formatted files instead of rewriting them");
+                    eprintln!("  --group=N     Splits runs of 0/1 longer than N into groups of N");
+                    eprintln!();
+                    eprintln!("test runs the programs in PATH (default '.') that have expectations, either in a");
+                    eprintln!("sidecar NAME.expect or on '#|' comment lines, and compares their output");
+                    eprintln!("  --update      Rewrites the expected stdout, stacks and exit with the actual ones");
+                    eprintln!("  --jobs=N      Runs N programs at the same time (default: number of CPUs)");
+                },
                 "--debug" => {
-                    debug = true;
+                    options.debug = true;
                 },
                 "--dump" => {
-                    mode = Mode::DUMP;
-                },                
+                    mode = Mode::Dump;
+                },
                 "--strict" => {
-                    strict = true;
+                    options.strict = true;
                 },
                 "--compile" => {
-                    mode = Mode::COMPILE;
+                    mode = Mode::Compile;
+                },
+                "--emit-c" => {
+                    mode = Mode::EmitC;
+                },
+                "--emit-wasm" => {
+                    mode = Mode::EmitWasm;
+                },
+                "--emit-wat" => {
+                    mode = Mode::EmitWat;
+                },
+                "--emit-object" => {
+                    mode = Mode::EmitObject;
+                },
+                "--emit-sos" => {
+                    mode = Mode::EmitSos;
+                },
+                "--emit-bf" => {
+                    mode = Mode::EmitBrainfuck;
+                },
+                "--brainfuck" => {
+                    options.brainfuck = true;
+                },
+                "--structured" => {
+                    options.structured = true;
+                },
+                "--jit" => {
+                    options.jit = true;
                 },
                 "--bytecode" => {
-                    mode = Mode::BYTECODE;
+                    mode = Mode::Bytecode;
+                },
+                "--disassemble" => {
+                    mode = Mode::Disassemble;
+                },
+                "--read-trace" => {
+                    mode = Mode::ReadTrace;
+                },
+                "--analyse" => {
+                    mode = Mode::Analyse;
+                },
+                "--cfg" => {
+                    mode = Mode::Cfg(graph::Format::Dot);
+                },
+                "--explore" => {
+                    mode = Mode::Explore(None);
+                },
+                "--profile" => {
+                    options.profile = true;
+                },
+                "--coverage" => {
+                    options.coverage = true;
+                },
+                "--optimize" => {
+                    options.optimize = true;
+                },
+                "--detect-loops" => {
+                    options.loops = Some(Detection::Exact);
+                },
+                _ if let Some(steps) = value(param, "--max-steps") => {
+                    options.max_steps = match steps.parse(){
+                        Ok(n) => Some(n),
+                        Err(_) => {
+                            eprintln!("Invalid number of steps '{}'", steps);
+                            exit(1);
+                        }
+                    };
+                },
+                _ if let Some(count) = value(param, "--explore") => {
+                    mode = match count.parse(){
+                        Ok(n) => Mode::Explore(Some(n)),
+                        Err(_) => {
+                            eprintln!("Invalid number of programs '{}'", count);
+                            exit(1);
+                        }
+                    };
+                },
+                _ if let Some(seed) = value(param, "--seed") => {
+                    options.seed = match seed.parse(){
+                        Ok(n) => Some(n),
+                        Err(_) => {
+                            eprintln!("Invalid seed '{}'", seed);
+                            exit(1);
+                        }
+                    };
+                },
+                _ if let Some(size) = value(param, "--explore-size") => {
+                    options.explore_size = match size.parse(){
+                        Ok(n) if n > 0 => n,
+                        _ => {
+                            eprintln!("Invalid program size '{}'", size);
+                            exit(1);
+                        }
+                    };
+                },
+                _ if let Some(dir) = value(param, "--explore-save") => {
+                    options.explore_save = Some(dir.to_owned());
+                },
+                _ if let Some(format_name) = value(param, "--cfg") => {
+                    let Some(format) = graph::Format::from_name(format_name) else {
+                        eprintln!("Unknown graph format '{}' (use 'dot' or 'mermaid')", format_name);
+                        exit(1);
+                    };
+                    mode = Mode::Cfg(format);
+                },
+                _ if let Some(mode_name) = value(param, "--detect-loops") => {
+                    let Some(detection) = Detection::from_name(mode_name) else {
+                        eprintln!("Unknown loop detection '{}' (use 'exact' or 'warn')", mode_name);
+                        exit(1);
+                    };
+                    options.loops = Some(detection);
+                },
+                _ if let Some(triple) = value(param, "--target") => {
+                    options.target = Some(triple.to_owned());
+                },
+                _ if let Some(file) = value(param, "--snapshot") => {
+                    options.snapshot = Some(file.to_owned());
+                },
+                _ if let Some(steps) = value(param, "--snapshot-every") => {
+                    options.snapshot_every = match steps.parse(){
+                        Ok(n) if n > 0 => Some(n),
+                        _ => {
+                            eprintln!("Invalid number of steps '{}'", steps);
+                            exit(1);
+                        }
+                    };
+                },
+                _ if let Some(file) = value(param, "--resume") => {
+                    options.resume = Some(file.to_owned());
+                },
+                _ if let Some(record_filename) = value(param, "--record") => {
+                    options.recorder = match Recorder::create(record_filename){
+                        Ok(r) => Some(r),
+                        Err(e) => {
+                            eprintln!("Error creating recording: {}", e);
+                            exit(1);
+                        }
+                    };
+                },
+                _ if let Some(file) = value(param, "--replay") => {
+                    options.replay = Some(file.to_owned());
+                },
+                _ if let Some(spec) = value(param, "--trace") => {
+                    let Some((format_name, trace_filename)) = spec.split_once(':') else {
+                        eprintln!("Invalid trace specification '{}', expected --trace=FORMAT:FILE", spec);
+                        exit(1);
+                    };
+                    let Some(format) = trace::Format::from_name(format_name) else {
+                        eprintln!("Unknown trace format '{}' (use 'jsonl' or 'bin')", format_name);
+                        exit(1);
+                    };
+                    options.tracer = match Tracer::create(format, trace_filename){
+                        Ok(t) => Some(t),
+                        Err(e) => {
+                            eprintln!("Error creating trace file: {}", e);
+                            exit(1);
+                        }
+                    };
+                },
+                _ if let Some(file) = value(param, "--profile-listing") => {
+                    options.profile_listing = Some(file.to_owned());
+                },
+                _ if let Some(file) = value(param, "--profile-collapsed") => {
+                    options.profile_collapsed = Some(file.to_owned());
+                },
+                _ if let Some(file) = value(param, "--coverage-lcov") => {
+                    options.coverage_lcov = Some(file.to_owned());
                 },
                 _ => {
                     eprintln!("Unknown option '{}' !", param);
                     exit(1);
                 }
-            }   
+            }
         }else{
-            if filename != ""{
+            if !filename.is_empty(){
                 eprintln!("Only one filename allowed!");
-                exit(1);                    
+                exit(1);
             }
-            filename = p;         
+            filename = p;
         }
 
 
     }
 
-    if filename == ""{
+    if let Mode::Explore(count) = mode{
+        explore_programs(&filename, count, options);
+        return;
+    }
+
+    if filename.is_empty(){
         eprintln!("No filename specified!");
         exit(1);
     }
 
-    let mut file = match File::open(filename)
+    let mut file = match File::open(&filename)
     {
         Ok(f) => f,
         Err(_) => {
@@ -108,603 +441,641 @@ fn main() {
         }
     };
 
-    let size:usize = match file.metadata()
-    {
-        Ok(l) => l,
-        Err(_) => {
-            eprintln!("File has no filesize");
-            exit(1);
-        }
-    }.len().try_into().unwrap();
-    
-    let mut script_bytes:Vec<u8> = Vec::with_capacity(size.try_into().unwrap());
-    script_bytes.resize(size, 0);
-    file.read(&mut script_bytes);
-
-    match(mode){
-        Mode::RUN => { 
-            // println!("{:?}", &tokenise(script_bytes));
-            // println!("{}", std::str::from_utf8(&parse(&tokenise(script_bytes))).unwrap());
-            run(&parse(&tokenise(script_bytes)), debug, strict);
-        },
-        Mode::COMPILE => { 
-            let mut out = stdout().lock();
-            out.write( &compile(&parse(&tokenise(script_bytes))) ); 
-        },
-        Mode::BYTECODE => { 
-            run(&bytecode(&parse(&tokenise(script_bytes))), debug, strict); 
-        },
-        Mode::DUMP => { 
-            let pure_script = parse(&tokenise(script_bytes));
-
-             //input is already screened to not contain non-ascii characters by the tokenise funtion
-            let string = std::str::from_utf8(&pure_script).expect("INTERPRETER's FAULT: The function 'tokenise' should have checked for non ascii characters!");
-            let mut index = 0;
-
-            for token in string.chars(){
-                eprintln!("0x{:#018X}:  {}", index, token);
-                index += 1;
-            }
-
-        },        
+    if matches!(mode, Mode::ReadTrace){
+        read_trace(file);
+        return;
     }
 
-}
-
-fn expand(input:&Vec<u8>, labels:&HashMap<Vec<u8>,usize>, index:&usize)->Vec<u8>{
-
-    //input is already screened to not contain non-ascii characters by the tokenise funtion
-    let string = std::str::from_utf8(input).expect("INTERPRETER's FAULT: The function 'tokenise' should have checked for non ascii characters!");
-
-    #[derive(Debug)]
-    enum Token{
-        Number(Vec<u8>),
-        Operator(u8),
+    let mut script_bytes:Vec<u8> = vec!();
+    if file.read_to_end(&mut script_bytes).is_err(){
+        eprintln!("Error reading file");
+        exit(1);
     }
 
-    let mut tokens:Vec<Token> = vec!();
-    let mut buffer:Vec<u8> = vec!();
-
-    for c in input{
-
-        if [b'+',b'-'].contains(c){
-            if buffer.len() > 0 {
-                tokens.push(Token::Number(buffer));
-                buffer = vec!();
+    if options.brainfuck{
+        script_bytes = match brainfuck::transpile(&script_bytes){
+            Ok(source) => source.into_bytes(),
+            Err(e) => {
+                eprintln!("Brainfuck error: {}", e);
+                exit(1);
             }
-
-            tokens.push(Token::Operator(*c));            
-        }else{
-            buffer.push(*c);
-        }
-
-    }
-    if buffer.len() > 0 {
-        tokens.push(Token::Number(buffer));
-        // buffer = vec!();
+        };
     }
 
-    //check well formed ness
-    //check for empty macro
-    if tokens.len() < 1{
-        eprintln!("Macro parsing error: Empty macro!");
-        exit(1);        
+    if options.structured{
+        script_bytes = match structured::compile(&script_bytes){
+            Ok(source) => source.into_bytes(),
+            Err(e) => {
+                eprintln!("Compile error: {}", e);
+                exit(1);
+            }
+        };
     }
 
-    //if start with operator eg: -1 the  prepend 0 so -4 becomes 0-4
-    if matches!(tokens[0], Token::Operator(_)){
-        tokens = {let mut x = vec!(Token::Number(vec!(b'0'))); x.extend(tokens); x};
-    }
+    match mode{
+        Mode::Run => {
+            execute(&prepare(script_bytes, &options), &filename, options);
+        },
+        Mode::Compile => {
+            let mut out = stdout().lock();
+            let _ = out.write_all( &compile(&prepare(script_bytes, &options).code) );
+        },
+        Mode::EmitC => {
+            let _ = stdout().lock().write_all(c::emit(&prepare(script_bytes, &options), options.strict).as_bytes());
+        },
+        Mode::EmitWasm => {
+            let _ = stdout().lock().write_all(&wasm::wasm(&prepare(script_bytes, &options).code, options.strict));
+        },
+        Mode::EmitWat => {
+            let _ = stdout().lock().write_all(wasm::wat(&prepare(script_bytes, &options).code, options.strict).as_bytes());
+        },
+        Mode::EmitObject => {
+            let object = emit_object(&prepare(script_bytes, &options).code, &options);
+            let _ = stdout().lock().write_all(&object);
+        },
+        Mode::EmitSos => {
+            let _ = stdout().lock().write_all(&script_bytes);
+        },
+        Mode::EmitBrainfuck => {
+            let _ = stdout().lock().write_all(brainfuck::export(&prepare(script_bytes, &options).code, options.strict).as_bytes());
+        },
+        Mode::Bytecode => {
+            let code = bytecode(&assemble_script(script_bytes).code);
+            execute(&prepare_program(Program::from_code(code), &options), &filename, options);
+        },
+        Mode::Dump => {
+            let pure_script = prepare(script_bytes, &options).code;
+
+            for (index, token) in pure_script.iter().enumerate(){
+                eprintln!("0x{:#018X}:  {}", index, *token as char);
+            }
 
-    //check if macro ends with Number
-    if !matches!(tokens[tokens.len()-1], Token::Number(_)){
-        eprintln!("Macro parsing error: Macro cannot end with operator: [{}]", std::str::from_utf8(input).unwrap());
-        exit(1);    
+        },
+        Mode::Disassemble => {
+            let _ = stdout().lock().write_all(&disassemble(&bytecode(&script_bytes)));
+        },
+        Mode::Analyse => {
+            analyse(&fold_program(assemble_source(script_bytes), &options), &filename, options.strict);
+        },
+        Mode::Cfg(format) => {
+            let program = fold_program(assemble_source(script_bytes), &options);
+            let _ = stdout().lock().write_all(graph::export(&program, options.strict, format).as_bytes());
+        },
+        Mode::ReadTrace | Mode::Explore(_) => {},
     }
 
-    //chekc if macro has format of: number (operator number)*
-    let mut should_be_number = true;
-    for token in &tokens{
+}
 
-        match token{
-            Token::Number(v) => {
-                if !should_be_number{
-                    eprintln!("Macro parsing error: Unexpected (extra) Number in macro: [{}]", std::str::from_utf8(input).unwrap());
-                    exit(1);        
-                }
-            },
-            Token::Operator(b) => {
-                if should_be_number{
-                    eprintln!("Macro parsing error: Unexpected (extra) Operator in macro: [{}]", std::str::from_utf8(input).unwrap());
-                    exit(1);        
-                }
-            },
+// Assembles the source, reporting errors the way the interpreter always did
+fn assemble_script(script_bytes:Vec<u8>) -> Program{
+    match assemble(script_bytes){
+        Ok(program) => program,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
         }
-
-        should_be_number = !should_be_number;
-    }
-
-    #[derive(Debug)]
-    enum T2Token{
-        Int(i64),
-        Add,
-        Sub,
     }
+}
 
-    //Validate all tokens individually, and return a list of T2 tokens
-    fn resolve_tokens(tokens:Vec<Token>, labels:&HashMap<Vec<u8>,usize>) -> Vec<T2Token>{
-        let mut out = vec!();
-
-        for token in &tokens{
-
-            let new_token = match token{
-                Token::Number(v) => {
-
-                    let s = std::str::from_utf8(v).unwrap();
-
-                    let int = match s.parse(){
-                        Ok(int) => int, //int parsed
-                        Err(_) => {
-
-                            let int;
-                            // You want to use from_str_radix. It's implemented on the integer types.
-                            if v.len()==3 && v[0] == b'\'' && v[2] == b'\'' { //matches 'X' notation
-                                int = i64::from(v[1]);
-
-                            }else if v[0] == b'0' && (v[1] == b'b' || v[1] == b'x' || v[1] == b'o' || v[1] == b'd'){
-                                let number_string = &s[2..];
-                                let radix = match v[1]{
-                                    b'b' => 2,
-                                    b'o' => 8,
-                                    b'd' => 10, //Just to be complete
-                                    b'x' => 16,
-                                    _ => {panic!();},
-                                };
-
-                                int = match i64::from_str_radix(number_string, radix){
-                                    Ok(i) => i,
-                                    Err(_) => {
-                                        eprintln!("Macro parsing error: '{}' is an invalid number representation", s);
-                                        exit(1);    
-                                    },
-                                }
-                            }else{
-                                int = match labels.get(v){
-                                    Some(u) => *u as i64,
-                                    None => {
-                                        eprintln!("Macro parsing error: Label '{}' not found in labels", s);
-                                        exit(1);                                  
-                                    }
-                                };                                
-                            }
-
-                            int
-                        },
-                    };
-
-
-                    T2Token::Int(int)
-                }
-                Token::Operator(c) => {
-                    match c{
-                        b'+' => T2Token::Add,
-                        b'-' => T2Token::Sub,
-                        _ => {panic!("INTERPRETER's FAULT: Operator token should only cointain +/- !");}
-                    }
-                }
-            };
+// Assembles the source with the library it includes, bytecode never includes anything
+fn assemble_source(script_bytes:Vec<u8>) -> Program{
+    assemble_script(library::link(&script_bytes).source)
+}
 
-            out.push(new_token);
-        }
+// Assembles the source and optimizes it when asked to
+fn prepare(script_bytes:Vec<u8>, options:&Options) -> Program{
+    prepare_program(assemble_source(script_bytes), options)
+}
 
-        out
+fn prepare_program(program:Program, options:&Options) -> Program{
+    if !options.optimize {return program}
 
+    let program = fold_program(program, options);
+    let optimized = optimize::optimize(&program.code, options.strict);
+    if let Some(offset) = optimized.dynamic{
+        let location = program.location(offset).map_or("".to_owned(), |l| format!(" at {}:{}", l.line, l.column));
+        eprintln!("Not optimized: the jump{} ({}) has a target that is not known", location, program.symbolise(offset));
     }
+    optimized.program(&program)
+}
 
-    let t2tokens = resolve_tokens(tokens,labels);
-
-    // println!("{:?}", t2tokens);
+// Folds constants when optimizing, which keeps every offset so labels and locations still fit
+fn fold_program(program:Program, options:&Options) -> Program{
+    if !options.optimize {return program}
 
-    let mut it = t2tokens.iter();
+    Program{code: fold::fold(&program.code, options.strict).code, ..program}
+}
 
-    let T2Token::Int(mut acc) = it.next().unwrap() else {panic!()};
+fn write_file(filename:&str, contents:&str){
+    write_bytes(filename, contents.as_bytes());
+}
 
-    loop{
-        let Some(op_token) = it.next() else {break};
-        let T2Token::Int(num) = it.next().unwrap() else {panic!()};
+fn write_bytes(filename:&str, contents:&[u8]){
+    if let Err(e) = File::create(filename).and_then(|mut f| f.write_all(contents)){
+        eprintln!("Error writing '{}': {}", filename, e);
+    }
+}
 
-        match op_token{
-            T2Token::Add => {
-                acc += num;
-            },
-            T2Token::Sub => {
-                acc -= num;
-            },
-            T2Token::Int(_) => {panic!();}
+#[cfg(feature = "cranelift")]
+fn run_native(vm:&mut Vm, io:&mut dyn Io) -> Result<Stop, Fault>{
+    match native::Jit::new(&vm.code, vm.strict){
+        Ok(jit) => jit.run(vm, io),
+        Err(e) => {
+            eprintln!("Error compiling to native code: {}", e);
+            exit(1);
         }
     }
+}
 
-
-    let ret = format!("!{:064b}", acc);
-    assert!(ret.len() == 65); //Must be 65 characters wide
-
-
-    for token in ret.bytes(){
-        if !TOKENS.contains(&token){
-            panic!("INTERPRETER's FAULT: Invalid tokens in macro output!");
+#[cfg(feature = "cranelift")]
+fn emit_object(code:&[u8], options:&Options) -> Vec<u8>{
+    match native::object(code, options.strict, options.target.as_deref()){
+        Ok(object) => object,
+        Err(e) => {
+            eprintln!("Error compiling to native code: {}", e);
+            exit(1);
         }
     }
-    return Vec::from(ret);
-
-
+}
 
+#[cfg(not(feature = "cranelift"))]
+fn run_native(_vm:&mut Vm, _io:&mut dyn Io) -> Result<Stop, Fault>{
+    eprintln!("Built without native code generation (the cranelift feature)");
+    exit(1);
 }
 
-#[derive(Debug)]
-enum Token{
-    Script(Vec<u8>),
-    Macro(Vec<u8>),
-    Label(Vec<u8>),
+#[cfg(not(feature = "cranelift"))]
+fn emit_object(_code:&[u8], _options:&Options) -> Vec<u8>{
+    eprintln!("Built without native code generation (the cranelift feature)");
+    exit(1);
 }
 
+fn execute(program:&Program, filename:&str, mut options:Options){
+    let mut vm = Vm::new(program.code.clone(), options.strict);
+    vm.max_steps = options.max_steps;
 
-fn tokenise(script_bytes:Vec<u8>) -> Vec<Token>{
+    let mut recording = match &options.replay{
+        Some(replay) => match replay::load(replay){
+            Ok(r) => Some(r),
+            Err(e) => {
+                eprintln!("Error reading recording '{}': {}", replay, e);
+                exit(1);
+            }
+        },
+        None => None,
+    };
 
-    #[derive(Copy, Clone)]
-    enum State{
-        Script,
-        Comment,
-        Macro,
-        Label,
+    let mut input = 0;
+    if let Some(resume) = &options.resume{
+        let restored = Snapshot::load(resume).and_then(|snapshot| snapshot.restore(&mut vm).map(|_| snapshot.input));
+        input = match restored{
+            Ok(input) => input,
+            Err(e) => {
+                eprintln!("Error resuming from '{}': {}", resume, e);
+                exit(1);
+            }
+        };
+        match recording.as_mut(){
+            Some(r) => r.retain(|(step, _)| *step > vm.steps),
+            None => for _ in 0..input{
+                StdIo.read();
+            },
+        }
     }
 
-    // let mut pure_script:Vec<u8> = vec!();
+    let mut replay_check = recording.as_ref().map(ReplayCheck::new);
+    let mut replayer = recording.map(|r| Replayer::new(r, StdIo));
+    let mut std_io = StdIo;
+    let io:&mut dyn Io = match replayer.as_mut(){
+        Some(r) => r,
+        None => &mut std_io,
+    };
+
+    let mut debugger = Debugger;
+    let profiling = options.profile || options.profile_listing.is_some() || options.profile_collapsed.is_some();
+    let mut profiler = Profiler::new(program.code.len());
+    let covering = options.coverage || options.coverage_lcov.is_some();
+    let mut coverage = Coverage::new(&program.code);
+    let mut detector = Detector::new(program.code.len());
+    let mut watchdog = Watchdog::new(program);
+    let mut checkpoint = options.snapshot.as_ref().map(|filename| Checkpoint::new(filename, options.snapshot_every, input));
+
+    let mut observers:Vec<&mut dyn Observer> = vec!();
+    if options.debug{
+        observers.push(&mut debugger);
+    }
+    if let Some(t) = options.tracer.as_mut(){
+        observers.push(t);
+    }
+    if profiling{
+        observers.push(&mut profiler);
+    }
+    if covering{
+        observers.push(&mut coverage);
+    }
+    match options.loops{
+        Some(Detection::Exact) => observers.push(&mut detector),
+        Some(Detection::Warn) => observers.push(&mut watchdog),
+        None => {},
+    }
+    if let Some(c) = checkpoint.as_mut(){
+        observers.push(c);
+    }
+    if let Some(r) = options.recorder.as_mut(){
+        observers.push(r);
+    }
+    if let Some(c) = replay_check.as_mut(){
+        observers.push(c);
+    }
 
-    let mut tokenised_script:Vec<Token>  = vec!();
-    let mut buffer:Vec<u8> = vec!();
+    // the pre-decoded interpreter and native code are faster but cannot be observed
+    let result = if !observers.is_empty(){
+        vm::run(&mut vm, io, &mut observers)
+    }else if options.jit{
+        run_native(&mut vm, io)
+    }else{
+        fast::run(&mut vm, io)
+    };
+    let _ = stdout().flush();
 
-    let mut state = State::Script;
-    let mut line_count = 1;
-    let mut char_count = 1;
+    if let Some(e) = options.tracer.as_ref().and_then(Tracer::error){
+        eprintln!("Error writing trace: {}", e);
+    }
+    if let Some(e) = checkpoint.as_ref().and_then(|c| c.error.as_ref()){
+        eprintln!("Error writing snapshot: {}", e);
+    }
+    if let Some(e) = options.recorder.as_ref().and_then(Recorder::error){
+        eprintln!("Error writing recording: {}", e);
+    }
 
-    for token in script_bytes{
+    if options.profile{
+        eprint!("{}", profiler.report(program));
+    }
+    if let Some(listing) = &options.profile_listing{
+        write_file(listing, &profiler.listing(program));
+    }
+    if let Some(collapsed) = &options.profile_collapsed{
+        write_file(collapsed, &profiler.collapsed(program, filename));
+    }
+    if options.coverage{
+        eprint!("{}", coverage.summary(program));
+    }
+    if let Some(lcov) = &options.coverage_lcov{
+        write_file(lcov, &coverage.lcov(program, filename));
+    }
 
-        if token >= 0x80 {
-            eprintln!("Parsing error: Illegal (Non ASCII) character found at {}:{}", line_count, char_count);
+    if let Some(check) = &replay_check{
+        if let Some(divergence) = check.divergence{
+            eprintln!("{}", divergence);
             exit(1);
         }
-        if token != 0x0D{ //CR not counted
-            char_count += 1;
-        }
-        if token == 0x0A{
-            char_count = 1;
-            line_count += 1;
-        }
-
-        
-        match state{
-            State::Script =>{
-                // println!("{:}: {:?}", "Source", std::str::from_utf8(&buffer).unwrap());
-
-                if TOKENS.contains(&token){
-                    buffer.push(token);
-                }else{
-                    match token{
-                        b'#' => {
-                            state = State::Comment;
-                        },
-                        b'[' => {
-                            tokenised_script.push(Token::Script(buffer));
-                            buffer = vec!();
-
-                            state = State::Macro;
-                        },
-                        b':' => {
-                            tokenised_script.push(Token::Script(buffer));
-                            buffer = vec!();
-
-                            state = State::Label;
-                        },                        
-                        _ => (), //preceived as comment
-                    }
-                }
-            },
-            State::Comment =>{
-                // println!("{:}: {:?}", "Comment", std::str::from_utf8(&buffer).unwrap());
-
-                match token{
-                    b'\n' => {
-                        state = State::Script;
-                    },
-                    _ => (), //preceived as comment
-                }                
-            },
-            State::Macro =>{
-                // println!("{:}: {:?}", "Macro", std::str::from_utf8(&buffer).unwrap());
-                match token{
-                    b']' => {
-
-                        tokenised_script.push(Token::Macro(buffer));
-                        buffer = vec!();
-
-                        state = State::Script;
-                    },
-                    b => {
-                        buffer.push(b);
-                    }
-                }                
-            }
-            State::Label =>{ //97=122
-                // println!("{:}: {:?}", "Label", std::str::from_utf8(&buffer).unwrap());
-                if (97 <= token) && (token <= 122){
-                    buffer.push(token);
-                }else{
-
-                    tokenised_script.push(Token::Label(buffer));
-                    buffer = vec!();
-
-                    state = State::Script;
-                }   
-            }
+        if result == Ok(Stop::Halted) && check.unread() > 0{
+            eprintln!("Replay diverged: halted with {} recorded reads left", check.unread());
+            exit(1);
         }
-
-
     }
 
-    match state{
-        State::Script =>{
-            tokenised_script.push(Token::Script(buffer));
-        },
-        State::Comment =>{
-            //not needed here, its just discarded while interwoven with Source state
+    match result{
+        Ok(Stop::Halted) => {},
+        Ok(Stop::StepLimit) => {
+            eprintln!("Step limit reached after {} steps", vm.steps);
         },
-        State::Macro =>{
-            eprintln!("Macro violation: Macro is not closed by EOF.");
+        Ok(Stop::Interrupted) => {
+            if let Some(cycle) = &detector.cycle{
+                eprint!("{}", cycle.report(program));
+            }
             exit(1);
         },
-        State::Label =>{
-            tokenised_script.push(Token::Label(buffer));
+        Err(fault) => {
+            eprintln!("{}", fault);
+            exit(1);
         },
     }
-
-    tokenised_script
 }
 
-//parse to pure_script
-fn parse(tokens:&Vec<Token>) -> Vec<u8>{
+fn analyse(program:&Program, filename:&str, strict:bool){
+    let analysis = analysis::analyse(&program.code, strict);
 
-    let mut labels:HashMap<Vec<u8>, usize> = HashMap::new();
-    let mut index = 0;
-    let mut pure_script:Vec<u8> = vec!();
-
-    for token in tokens{
-        match token{
-            Token::Script(v) => {
-                index += v.len();
-            },
-            Token::Macro(v) => {
-                //And heres the crux, we need to know NOW how long expanded macro size will be, and macro needs the label offset chicken and egg story
-                //For now macro's have output size fixed at 65 !+binary number
-                index += 65;
-            },
-            Token::Label(v) => {
-                labels.insert(v.to_vec(), index);
-            },
+    let mut out = std::io::BufWriter::new(stdout().lock());
+    let mut offset = 0;
+    for (index, line) in String::from_utf8_lossy(&program.source).lines().enumerate(){
+        let starts_here = offset < program.code.len() && program.locations[offset].line == index+1;
+        let heights = if !starts_here{
+            "".to_owned()
+        }else{
+            match analysis.heights(offset){
+                [Some(a), Some(b)] => format!("{} | {}", a, b),
+                _ => "unreached".to_owned(),
+            }
+        };
+        let _ = writeln!(out, "{:>20} | {}", heights, line);
+        while offset < program.code.len() && program.locations[offset].line == index+1{
+            offset += 1;
         }
     }
-
-    for token in tokens{
-        match token{
-            Token::Script(v) => {
-                // index += len(v);
-                pure_script.extend(v);
-            },
-            Token::Macro(v) => {
-                let expanded_v = expand(&v, &labels, &index);
-                index += expanded_v.len();
-                pure_script.extend(expanded_v);
-            },
-            Token::Label(v) => {},
-        }
+    let _ = out.flush();
+
+    let mut errors = false;
+    for finding in &analysis.findings{
+        let severity = finding.severity(strict);
+        errors |= severity == Severity::Error;
+        let location = program.location(finding.offset()).map_or("".to_owned(), |l| format!("{}:{}:", l.line, l.column));
+        eprintln!("{}:{} {}: {} ({})", filename, location, format!("{:?}", severity).to_lowercase(), finding, program.symbolise(finding.offset()));
     }
 
-    // println!("{}", std::str::from_utf8(&pure_script).unwrap());
-    return pure_script;
+    if errors{
+        exit(1);
+    }
 }
 
-fn compile(code:&Vec<u8>) -> Vec<u8>{
-    let mut opcodes:HashMap<u8, u8> = HashMap::new();
-
-    let mut high_bits = true;
-    let mut out:Vec<u8> = vec!();
+// Runs random programs, or the ones in filename when given
+fn explore_programs(filename:&str, count:Option<u64>, options:Options){
+    let seed = options.seed.unwrap_or_else(|| {
+        std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64)
+    });
+    let max_steps = options.max_steps.unwrap_or(10_000);
+
+    let mut file;
+    let mut source = if filename.is_empty(){
+        eprintln!("Exploring with seed {}", seed);
+        Source::Random(Rng::new(seed))
+    }else{
+        file = match File::open(filename){
+            Ok(f) => std::io::BufReader::new(f),
+            Err(_) => {
+                eprintln!("Error opening file");
+                exit(1);
+            }
+        };
+        Source::Reader(&mut file)
+    };
 
-    for (index, token) in TOKENS.iter().enumerate(){
-        opcodes.insert(*token, index as u8);
+    if let Some(dir) = &options.explore_save{
+        if let Err(e) = std::fs::create_dir_all(dir){
+            eprintln!("Error creating '{}': {}", dir, e);
+            exit(1);
+        }
     }
 
-    //Since opcode 0 (PUSH(-1)) always works and is benign on itself, we dont care is lasat nibble contains just that.
-    for token in code{
-        if high_bits{
-            out.push( *opcodes.get(token).unwrap() << 4 );
-        }else{
-            let i:usize = out.len()-1;
-            out[i] |= *opcodes.get(token).unwrap();
+    let mut exploration = Exploration::default();
+    let mut out = stdout().lock();
+    while count.is_none_or(|count| exploration.runs < count){
+        let Some(program) = source.program(options.explore_size) else {break};
+        let behaviour = explore::run(bytecode(&program), options.strict, max_steps);
+        let Some(specimen) = exploration.add(program, behaviour) else {continue};
+        let behaviour = &specimen.behaviour;
+        if !behaviour.interesting() {continue}
+
+        let name = format!("{}-{:016x}", behaviour.ending.name(), behaviour.signature());
+        let output = &behaviour.output[..behaviour.output.len().min(32)];
+        let _ = writeln!(out, "{:<7} steps={:<6} {:<40} output=\"{}\"", behaviour.ending.name(), behaviour.steps,
+            explore::hex(&specimen.program), golden::escape(output));
+        if let Some(dir) = &options.explore_save{
+            write_bytes(&format!("{}/{}.bin", dir, name), &specimen.program);
         }
-        high_bits = !high_bits;
     }
+    let _ = out.flush();
 
-    out
+    eprint!("{}", exploration.report());
 }
 
+// The lint subcommand: checks every file given, exits 1 when any error was reported
+fn lint_files(params:&[String]){
+    let mut strict = false;
+    let mut allowed:Vec<Rule> = vec!();
+    let mut denied:Vec<Rule> = vec!();
+    let mut filenames:Vec<&str> = vec!();
+
+    let rule = |name:&str| match Rule::from_name(name){
+        Some(rule) => rule,
+        None => {
+            eprintln!("Unknown lint rule '{}'", name);
+            exit(1);
+        }
+    };
 
-fn bytecode(bytecode:&Vec<u8>) -> Vec<u8>{
-    let mut code:Vec<u8> = vec!();
-
-    for byte in bytecode{
-        code.push(TOKENS[usize::from(byte>>4)]);
-        code.push(TOKENS[usize::from(byte&0b1111)]);
+    for param in params{
+        let param = param.as_str();
+        match param{
+            "--strict" => strict = true,
+            _ if let Some(name) = value(param, "--allow") => allowed.push(rule(name)),
+            _ if let Some(name) = value(param, "--deny") => denied.push(rule(name)),
+            _ if param.starts_with("--") => {
+                eprintln!("Unknown option '{}' !", param);
+                exit(1);
+            },
+            _ => filenames.push(param),
+        }
     }
 
-    code
-}
-
-
-trait Oos{
-    fn oos(self:&Self, infinite_stack:&bool)->i64;
-}
-
-impl Oos for Option<i64>{
-    fn oos(self:&Self, strict:&bool) -> i64{
-        match(self){
+    if filenames.is_empty(){
+        eprintln!("No filename specified!");
+        exit(1);
+    }
 
-            Some(v) => *v,
-            None => {
-                if *strict{
-                    eprintln!("Strict mode violation: Stack depleted");
-                    exit(1);
-                }else{
-                    -1
-                }
+    let mut errors = false;
+    for filename in filenames{
+        let source = match std::fs::read(filename){
+            Ok(source) => source,
+            Err(e) => {
+                eprintln!("Error reading '{}': {}", filename, e);
+                errors = true;
+                continue;
             }
+        };
 
+        for mut diagnostic in lint::lint(&source, strict){
+            if allowed.contains(&diagnostic.rule) {continue}
+            if denied.contains(&diagnostic.rule){
+                diagnostic.severity = Severity::Error;
+            }
+            errors |= diagnostic.severity == Severity::Error;
+            let location = diagnostic.location.map_or("".to_owned(), |l| format!("{}:{}:", l.line, l.column));
+            println!("{}:{} {}[{}]: {}", filename, location, format!("{:?}", diagnostic.severity).to_lowercase(), diagnostic.rule, diagnostic.message);
         }
+    }
 
-    }    
+    if errors{
+        exit(1);
+    }
 }
 
-fn run(code:&Vec<u8>, debug:bool, strict:bool){
+// The fmt subcommand: formats every file given in place
+fn format_files(params:&[String]){
+    let mut check = false;
+    let mut to_stdout = false;
+    let mut options = format::Options::default();
+    let mut filenames:Vec<&str> = vec!();
+
+    for param in params{
+        let param = param.as_str();
+        match param{
+            "--check" => check = true,
+            "--stdout" => to_stdout = true,
+            _ if let Some(size) = value(param, "--group") => {
+                options.group = match size.parse(){
+                    Ok(n) if n > 0 => Some(n),
+                    _ => {
+                        eprintln!("Invalid group size '{}'", size);
+                        exit(1);
+                    }
+                };
+            },
+            _ if param.starts_with("--") => {
+                eprintln!("Unknown option '{}' !", param);
+                exit(1);
+            },
+            _ => filenames.push(param),
+        }
+    }
 
-    if code.len() == 0{return}
+    if filenames.is_empty(){
+        eprintln!("No filename specified!");
+        exit(1);
+    }
 
-    let mut index = 0;
-    let mut ram:HashMap<i64, i64> = HashMap::new();
+    let mut failed = false;
+    for filename in filenames{
+        let source = match std::fs::read(filename){
+            Ok(source) => source,
+            Err(e) => {
+                eprintln!("Error reading '{}': {}", filename, e);
+                failed = true;
+                continue;
+            }
+        };
+
+        let formatted = match format::format(&source, &options){
+            Ok(formatted) => formatted,
+            Err(e) => {
+                let location = e.location.map_or("".to_owned(), |l| format!("{}:{}:", l.line, l.column));
+                eprintln!("{}:{} {}", filename, location, e);
+                failed = true;
+                continue;
+            }
+        };
 
-    let mut stacks: [Vec<i64>;2] = [vec!(),vec!()];
-    let mut stack: &mut Vec<i64>;
-    let mut stack_index = 0;
-    stack = &mut stacks[stack_index];
+        if check{
+            if formatted != source{
+                println!("{}", filename);
+                failed = true;
+            }
+        }else if to_stdout{
+            let _ = stdout().lock().write_all(&formatted);
+        }else if formatted != source{
+            if let Err(e) = std::fs::write(filename, &formatted){
+                eprintln!("Error writing '{}': {}", filename, e);
+                failed = true;
+            }
+        }
+    }
 
-    loop{
+    if failed{
+        exit(1);
+    }
+}
 
-        if debug{
-            eprintln!("{:?}", &stack);
-            stack = &mut stacks[!stack_index&1];
-            eprintln!("{:?}", &stack);
-            stack = &mut stacks[stack_index&1];
-            eprintln!("{:#018X}: {}", index, code[index] as char);
+// The test subcommand: runs golden output tests, exits 1 when any failed
+fn test_files(params:&[String]){
+    let mut update = false;
+    let mut jobs = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let mut paths:Vec<std::path::PathBuf> = vec!();
+
+    for param in params{
+        let param = param.as_str();
+        match param{
+            "--update" => update = true,
+            _ if let Some(n) = value(param, "--jobs") => {
+                jobs = match n.parse(){
+                    Ok(n) if n > 0 => n,
+                    _ => {
+                        eprintln!("Invalid number of jobs '{}'", n);
+                        exit(1);
+                    }
+                };
+            },
+            _ if param.starts_with("--") => {
+                eprintln!("Unknown option '{}' !", param);
+                exit(1);
+            },
+            _ => paths.push(param.into()),
         }
+    }
+    if paths.is_empty(){
+        paths.push(".".into());
+    }
 
-        match(code[index]){
-            b'$' => {//Switch stack
-                stack_index = !stack_index&1;
-                stack = &mut stacks[stack_index];
-            }
-            b'~' => {//Xchange stack heads
-                let a = stack.pop().oos(&strict);
-                stack = &mut stacks[!stack_index&1];
-                let b = stack.pop().oos(&strict);
-                
-                stack.push(a);
-                stack = &mut stacks[stack_index];
-                stack.push(b);
-            }
-            b'=' => {// Duplicate top value
-                let a = stack.pop().oos(&strict);
-                stack.push(a);
-                stack.push(a);
-            }            
-            b'+' => {
-                let b = stack.pop().oos(&strict);
-                let a = stack.pop().oos(&strict);
-                stack.push( (Wrapping(a)+Wrapping(b)).0 );
+    let mut failed = false;
+    let mut suites = vec!();
+    for path in golden::discover(&paths){
+        match golden::load(&path){
+            Ok(Some(suite)) => suites.push(suite),
+            Ok(None) => {},
+            Err(e) => {
+                eprintln!("{}", e);
+                failed = true;
             }
-            b'-' => {
-                let b = stack.pop().oos(&strict);
-                let a = stack.pop().oos(&strict);
-                stack.push( (Wrapping(a)-Wrapping(b)).0 );
-            }
-            b'*' => { // MULTIPLY stack:[a,b] -> [low, high]
-                let b = stack.pop().oos(&strict);
-                let a = stack.pop().oos(&strict);
-                let x = i128::from(a)*i128::from(b);
-                stack.push(x as i64);
-                // stack.push((x >> 64) as i64); //just lose the eccess
-            }
-            b'/' => {
-                let b = stack.pop().oos(&strict);
-                let a = stack.pop().oos(&strict);
-                if b != 0{
-                    stack.push(a/b);
-                }else{
-                    stack.push(0); //div by 0 is 0 by design (can replace test)
+        }
+    }
+
+    let (mut passed, mut failures) = (0, 0);
+    for report in golden::run_all(suites, jobs){
+        let program = report.suite.program.display();
+        for (case, outcome) in report.suite.cases.iter().zip(&report.outcomes){
+            let problems = match outcome{
+                Ok(outcome) => golden::check(case, outcome),
+                Err(e) => vec!(e.clone()),
+            };
+            if problems.is_empty(){
+                passed += 1;
+                println!("ok    {}: {}", program, case.name);
+            }else{
+                failures += 1;
+                println!("FAIL  {}: {}", program, case.name);
+                for problem in problems{
+                    println!("    {}", problem);
                 }
             }
-            b'|' => {
-                let b = stack.pop().oos(&strict);
-                let a = stack.pop().oos(&strict);
-                stack.push( a | b );
-            }
-            b'&' => {
-                let b = stack.pop().oos(&strict);
-                let a = stack.pop().oos(&strict);
-                stack.push( a & b );
-            }
-            b'^' => {
-                let b = stack.pop().oos(&strict);
-                let a = stack.pop().oos(&strict);
-                stack.push( a ^ b );
-            }
-            b'!' => {
-                stack.push( -1 );
-            }
-            b'1' => {
-                let a = stack.pop().oos(&strict);
-                stack.push((a << 1) | 0b1);
-            }
-            b'0' => {
-                let a = stack.pop().oos(&strict);
-                stack.push((a << 1));
-            }
-            b'@' => {
-                let a = stack.pop().oos(&strict);
-                if a == -1 {break} // This would lead to perpetual spinlock basically haling ,execution, so better make it an exit strategy
-                index = (Wrapping(index)+Wrapping(a as usize)).0;
-            }
-            b'?' => {
-                let mut buffer:[u8;1] = [0];
-                let stack_value = match stdin().lock().read_exact(&mut buffer){
-                    Ok(_) => buffer[0] as i64,
-                    Err(_) => -1,   
-                };
-                stack.push(stack_value);
-            }
-            b'.' => {
-                let ch = stack.pop().oos(&strict);
-
-                let buffer:[u8;1] = [ch as u8];
-                let _ = stdout().lock().write(&buffer);
-            }            
-            _ => {
-                panic!("INTERPRETER's FAULT: Invalid token!")
+        }
+        if update{
+            match golden::write_update(&report){
+                Ok(true) => println!("updated {}", report.suite.expectations.display()),
+                Ok(false) => {},
+                Err(e) => {
+                    eprintln!("Error updating {}", e);
+                    failed = true;
+                }
             }
         }
+    }
 
-        index = (Wrapping(index)+Wrapping(1usize)).0;
-        if (index<0) | (index >= code.len()) {
-            if strict{
-                eprintln!("Strict mode violation: Outside of code memmory (offset: 0x{:#018X})", index);
-                exit(1);
-            }else{
+    println!("{} passed, {} failed", passed, failures);
+    if failed || (failures > 0 && !update){
+        exit(1);
+    }
+}
 
-            }   index = 0;
+fn read_trace(file:File){
+    let reader = match trace::read(file){
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("Error reading trace: {}", e);
+            exit(1);
         }
-    }
+    };
 
-    if debug{
-        eprintln!("{:?}", &stack);
-        stack = &mut stacks[!stack_index&1];
-        eprintln!("{:?}", &stack);
+    let mut out = std::io::BufWriter::new(stdout().lock());
+    for record in reader{
+        match record{
+            Ok(record) => {
+                if writeln!(out, "{}", record.to_json()).is_err() {exit(1)}
+            },
+            Err(e) => {
+                let _ = out.flush();
+                eprintln!("Error reading trace: {}", e);
+                exit(1);
+            }
+        }
     }
-    
-
+    let _ = out.flush();
 }
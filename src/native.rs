@@ -0,0 +1,528 @@
+// Native code generation with Cranelift, in memory (JIT) or as an object file
+//
+// Lowers the code to one function, sos_run, that does what vm::run does on any architecture
+// Cranelift supports; x86_64 and aarch64 are built in. Every offset gets a block since jumps can
+// land anywhere. Jumps are resolved like the C backend does: the targets cfg knows become a
+// direct branch guarded by a check of the offset popped, anything else goes through a jump table
+// on CP. The function takes a pointer to a Context and returns one of the STATUS_ constants. The
+// stacks are Rust vectors taken apart, calling back into grow when one is full, and input and
+// output go through the read and write callbacks. Object files need a host that fills in the
+// Context and calls sos_run itself, with this layout:
+//
+//   struct sos_stack{ int64_t *items; uint64_t len, cap; };
+//   struct sos_context{
+//       struct sos_stack stacks[2];
+//       uint64_t steps, max_steps, cp, active;
+//       void *io;
+//       int64_t (*read)(void *io);                  // -1 on EOF
+//       void (*write)(void *io, int64_t byte);
+//       void (*grow)(struct sos_stack *stack);      // makes room for at least one more item
+//   };
+//   int32_t sos_run(struct sos_context *context);
+
+use std::ffi::c_void;
+use std::mem::ManuallyDrop;
+
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::entity::EntityRef;
+use cranelift_codegen::ir::{types, AbiParam, Block, InstBuilder, JumpTableData, MemFlags, SigRef, Signature, Value};
+use cranelift_codegen::isa::{self, OwnedTargetIsa};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Linkage, Module};
+use cranelift_object::{ObjectBuilder, ObjectModule};
+use target_lexicon::Triple;
+
+use crate::cfg::{self, Jump, Target};
+use crate::vm::{Fault, Io, Stop, Vm};
+
+pub const STATUS_HALTED:i32 = 0;
+pub const STATUS_STEP_LIMIT:i32 = 1;
+pub const STATUS_STACK_DEPLETED:i32 = 2;
+pub const STATUS_OUT_OF_CODE:i32 = 3;
+
+pub const ENTRY:&str = "sos_run";
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct RawStack{
+    pub items:*mut i64,
+    pub len:u64,
+    pub cap:u64,
+}
+
+#[repr(C)]
+pub struct Context{
+    pub stacks:[RawStack; 2],
+    pub steps:u64,
+    pub max_steps:u64,
+    pub cp:u64,
+    pub active:u64,
+    pub io:*mut c_void,
+    pub read:extern "C" fn(*mut c_void) -> i64,
+    pub write:extern "C" fn(*mut c_void, i64),
+    pub grow:extern "C" fn(*mut RawStack),
+}
+
+// Field offsets in Context and RawStack
+const STACK_SIZE:i64 = 24;
+const STACK_ITEMS:i32 = 0;
+const STACK_LEN:i32 = 8;
+const STACK_CAP:i32 = 16;
+const STEPS:i32 = 48;
+const MAX_STEPS:i32 = 56;
+const CP:i32 = 64;
+const ACTIVE:i32 = 72;
+const IO:i32 = 80;
+const READ:i32 = 88;
+const WRITE:i32 = 96;
+const GROW:i32 = 104;
+
+fn raw(stack:Vec<i64>) -> RawStack{
+    let mut stack = ManuallyDrop::new(stack);
+    RawStack{items: stack.as_mut_ptr(), len: stack.len() as u64, cap: stack.capacity() as u64}
+}
+
+// Safety: stack has to come from raw
+unsafe fn cooked(stack:&RawStack) -> Vec<i64>{
+    Vec::from_raw_parts(stack.items, stack.len as usize, stack.cap as usize)
+}
+
+extern "C" fn grow(stack:*mut RawStack){
+    // Safety: generated code only passes the stacks of the Context run made
+    unsafe{
+        let mut items = cooked(&*stack);
+        items.reserve(1);
+        *stack = raw(items);
+    }
+}
+
+extern "C" fn read(io:*mut c_void) -> i64{
+    // Safety: io is the &mut dyn Io run was given
+    unsafe {(*(io as *mut &mut dyn Io)).read()}
+}
+
+extern "C" fn write(io:*mut c_void, byte:i64){
+    unsafe {(*(io as *mut &mut dyn Io)).write(byte as u8)}
+}
+
+// Cranelift for the host, or for target (a triple like aarch64-unknown-linux-gnu)
+pub fn target(target:Option<&str>, pic:bool) -> Result<OwnedTargetIsa, String>{
+    let mut flags = settings::builder();
+    let set = |flags:&mut settings::Builder, name, value| flags.set(name, value).map_err(|e| e.to_string());
+    set(&mut flags, "opt_level", "speed")?;
+    set(&mut flags, "is_pic", if pic {"true"} else {"false"})?;
+    let builder = match target{
+        Some(name) => {
+            let triple = name.parse::<Triple>().map_err(|e| format!("Invalid target '{}': {}", name, e))?;
+            isa::lookup(triple).map_err(|e| format!("Unsupported target '{}': {}", name, e))?
+        },
+        None => cranelift_native::builder().map_err(str::to_owned)?,
+    };
+    builder.finish(settings::Flags::new(flags)).map_err(|e| e.to_string())
+}
+
+// Builds sos_run for code in module
+fn define(module:&mut dyn Module, code:&[u8], strict:bool) -> Result<cranelift_module::FuncId, String>{
+    let pointer = module.target_config().pointer_type();
+    let mut context = module.make_context();
+    context.func.signature.params.push(AbiParam::new(pointer));
+    context.func.signature.returns.push(AbiParam::new(types::I32));
+    let id = module.declare_function(ENTRY, Linkage::Export, &context.func.signature).map_err(|e| e.to_string())?;
+
+    let call_conv = module.target_config().default_call_conv;
+    let signature = |params:&[types::Type], returns:&[types::Type]| {
+        let mut signature = Signature::new(call_conv);
+        signature.params.extend(params.iter().map(|t| AbiParam::new(*t)));
+        signature.returns.extend(returns.iter().map(|t| AbiParam::new(*t)));
+        signature
+    };
+
+    let mut builder_context = FunctionBuilderContext::new();
+    let mut builder = FunctionBuilder::new(&mut context.func, &mut builder_context);
+    let signatures = Signatures{
+        read: builder.import_signature(signature(&[pointer], &[types::I64])),
+        write: builder.import_signature(signature(&[pointer, types::I64], &[])),
+        grow: builder.import_signature(signature(&[pointer], &[])),
+    };
+    Lowering::new(builder, pointer, signatures, code, strict).lower();
+
+    module.define_function(id, &mut context).map_err(|e| format!("{:?}", e))?;
+    Ok(id)
+}
+
+struct Signatures{
+    read:SigRef,
+    write:SigRef,
+    grow:SigRef,
+}
+
+struct Lowering<'a>{
+    builder:FunctionBuilder<'a>,
+    pointer:types::Type,
+    signatures:Signatures,
+    code:&'a [u8],
+    strict:bool,
+    context:Value,
+    active:Variable,
+    steps:Variable,
+    max_steps:Variable,
+    cp:Variable,
+    ops:Vec<Block>,
+    dispatch:Block,
+    exit:Block, // takes the status and the offset to leave in CP
+}
+
+impl<'a> Lowering<'a>{
+    fn new(mut builder:FunctionBuilder<'a>, pointer:types::Type, signatures:Signatures, code:&'a [u8], strict:bool) -> Lowering<'a>{
+        let entry = builder.create_block();
+        builder.append_block_params_for_function_params(entry);
+        builder.switch_to_block(entry);
+        let context = builder.block_params(entry)[0];
+
+        let [active, steps, max_steps, cp] = [0, 1, 2, 3].map(Variable::new);
+        for variable in [active, steps, max_steps, cp]{
+            builder.declare_var(variable, types::I64);
+        }
+        let ops = code.iter().map(|_| builder.create_block()).collect();
+        let dispatch = builder.create_block();
+        let exit = builder.create_block();
+        builder.append_block_param(exit, types::I32);
+        builder.append_block_param(exit, types::I64);
+
+        Lowering{builder, pointer, signatures, code, strict, context, active, steps, max_steps, cp, ops, dispatch, exit}
+    }
+
+    fn load(&mut self, base:Value, offset:i32) -> Value{
+        let ty = if offset >= IO {self.pointer} else {types::I64};
+        self.builder.ins().load(ty, MemFlags::trusted(), base, offset)
+    }
+
+    fn store(&mut self, value:Value, base:Value, offset:i32){
+        self.builder.ins().store(MemFlags::trusted(), value, base, offset);
+    }
+
+    fn leave(&mut self, status:i32, cp:Value){
+        let status = self.builder.ins().iconst(types::I32, i64::from(status));
+        self.builder.ins().jump(self.exit, &[status, cp]);
+    }
+
+    // Address of the active stack, or the other one
+    fn stack(&mut self, other:bool) -> Value{
+        let mut index = self.builder.use_var(self.active);
+        if other{
+            index = self.builder.ins().bxor_imm(index, 1);
+        }
+        let offset = self.builder.ins().imul_imm(index, STACK_SIZE);
+        let offset = if self.pointer == types::I64 {offset} else {self.builder.ins().ireduce(self.pointer, offset)};
+        self.builder.ins().iadd(self.context, offset)
+    }
+
+    fn item(&mut self, stack:Value, index:Value) -> Value{
+        let items = self.builder.ins().load(self.pointer, MemFlags::trusted(), stack, STACK_ITEMS);
+        let offset = self.builder.ins().imul_imm(index, 8);
+        let offset = if self.pointer == types::I64 {offset} else {self.builder.ins().ireduce(self.pointer, offset)};
+        self.builder.ins().iadd(items, offset)
+    }
+
+    fn pop(&mut self, offset:usize, other:bool) -> Value{
+        let stack = self.stack(other);
+        let len = self.load(stack, STACK_LEN);
+        let empty = self.builder.create_block();
+        let full = self.builder.create_block();
+        let join = self.builder.create_block();
+        self.builder.append_block_param(join, types::I64);
+        self.builder.ins().brif(len, full, &[], empty, &[]);
+
+        self.builder.switch_to_block(empty);
+        if self.strict{
+            let cp = self.builder.ins().iconst(types::I64, offset as i64);
+            self.leave(STATUS_STACK_DEPLETED, cp);
+        }else{
+            let value = self.builder.ins().iconst(types::I64, -1);
+            self.builder.ins().jump(join, &[value]);
+        }
+
+        self.builder.switch_to_block(full);
+        let len = self.builder.ins().iadd_imm(len, -1);
+        self.store(len, stack, STACK_LEN);
+        let address = self.item(stack, len);
+        let value = self.builder.ins().load(types::I64, MemFlags::trusted(), address, 0);
+        self.builder.ins().jump(join, &[value]);
+
+        self.builder.switch_to_block(join);
+        self.builder.block_params(join)[0]
+    }
+
+    fn push(&mut self, value:Value, other:bool){
+        let stack = self.stack(other);
+        let len = self.load(stack, STACK_LEN);
+        let cap = self.load(stack, STACK_CAP);
+        let grow = self.builder.create_block();
+        let room = self.builder.create_block();
+        let full = self.builder.ins().icmp(IntCC::Equal, len, cap);
+        self.builder.ins().brif(full, grow, &[], room, &[]);
+
+        self.builder.switch_to_block(grow);
+        let callback = self.load(self.context, GROW);
+        self.builder.ins().call_indirect(self.signatures.grow, callback, &[stack]);
+        self.builder.ins().jump(room, &[]);
+
+        self.builder.switch_to_block(room);
+        let address = self.item(stack, len);
+        self.builder.ins().store(MemFlags::trusted(), value, address, 0);
+        let len = self.builder.ins().iadd_imm(len, 1);
+        self.store(len, stack, STACK_LEN);
+    }
+
+    fn binary(&mut self, offset:usize) -> (Value, Value){
+        let b = self.pop(offset, false);
+        let a = self.pop(offset, false);
+        (a, b)
+    }
+
+    fn lower(mut self){
+        let len = self.code.len() as i64;
+        let context = self.context;
+        for (variable, field) in [(self.active, ACTIVE), (self.steps, STEPS), (self.max_steps, MAX_STEPS), (self.cp, CP)]{
+            let value = self.load(context, field);
+            self.builder.def_var(variable, value);
+        }
+        self.builder.ins().jump(self.dispatch, &[]);
+
+        for (offset, op) in self.code.iter().enumerate(){
+            let block = self.ops[offset];
+            self.builder.switch_to_block(block);
+
+            let steps = self.builder.use_var(self.steps);
+            let max_steps = self.builder.use_var(self.max_steps);
+            let limit = self.builder.create_block();
+            let go = self.builder.create_block();
+            let reached = self.builder.ins().icmp(IntCC::UnsignedGreaterThanOrEqual, steps, max_steps);
+            self.builder.ins().brif(reached, limit, &[], go, &[]);
+            self.builder.switch_to_block(limit);
+            let cp = self.builder.ins().iconst(types::I64, offset as i64);
+            self.leave(STATUS_STEP_LIMIT, cp);
+            self.builder.switch_to_block(go);
+            let steps = self.builder.ins().iadd_imm(steps, 1);
+            self.builder.def_var(self.steps, steps);
+
+            let result = match op{
+                b'!' => Some(self.builder.ins().iconst(types::I64, -1)),
+                b'^' => {let (a, b) = self.binary(offset); Some(self.builder.ins().bxor(a, b))},
+                b'|' => {let (a, b) = self.binary(offset); Some(self.builder.ins().bor(a, b))},
+                b'&' => {let (a, b) = self.binary(offset); Some(self.builder.ins().band(a, b))},
+                b'+' => {let (a, b) = self.binary(offset); Some(self.builder.ins().iadd(a, b))},
+                b'-' => {let (a, b) = self.binary(offset); Some(self.builder.ins().isub(a, b))},
+                b'*' => {let (a, b) = self.binary(offset); Some(self.builder.ins().imul(a, b))},
+                b'/' => {
+                    // dividing by 1 where b is 0 or -1, which trap, then picking 0 or -a for those
+                    let (a, b) = self.binary(offset);
+                    let zero = self.builder.ins().icmp_imm(IntCC::Equal, b, 0);
+                    let minus_one = self.builder.ins().icmp_imm(IntCC::Equal, b, -1);
+                    let special = self.builder.ins().bor(zero, minus_one);
+                    let one = self.builder.ins().iconst(types::I64, 1);
+                    let divisor = self.builder.ins().select(special, one, b);
+                    let quotient = self.builder.ins().sdiv(a, divisor);
+                    let negated = self.builder.ins().ineg(a);
+                    let quotient = self.builder.ins().select(minus_one, negated, quotient);
+                    let nothing = self.builder.ins().iconst(types::I64, 0);
+                    Some(self.builder.ins().select(zero, nothing, quotient))
+                },
+                b'$' => {
+                    let active = self.builder.use_var(self.active);
+                    let active = self.builder.ins().bxor_imm(active, 1);
+                    self.builder.def_var(self.active, active);
+                    None
+                },
+                b'~' => {
+                    let a = self.pop(offset, false);
+                    let b = self.pop(offset, true);
+                    self.push(a, true);
+                    Some(b)
+                },
+                b'=' => {
+                    let a = self.pop(offset, false);
+                    self.push(a, false);
+                    Some(a)
+                },
+                b'?' => {
+                    let io = self.load(context, IO);
+                    let callback = self.load(context, READ);
+                    let call = self.builder.ins().call_indirect(self.signatures.read, callback, &[io]);
+                    Some(self.builder.inst_results(call)[0])
+                },
+                b'.' => {
+                    let a = self.pop(offset, false);
+                    let io = self.load(context, IO);
+                    let callback = self.load(context, WRITE);
+                    self.builder.ins().call_indirect(self.signatures.write, callback, &[io, a]);
+                    None
+                },
+                b'0' | b'1' => {
+                    let a = self.pop(offset, false);
+                    let shifted = self.builder.ins().ishl_imm(a, 1);
+                    Some(if *op == b'1' {self.builder.ins().bor_imm(shifted, 1)} else {shifted})
+                },
+                _ => { // '@'
+                    self.jump(offset);
+                    continue;
+                },
+            };
+            if let Some(value) = result{
+                self.push(value, false);
+            }
+
+            if offset+1 < self.code.len(){
+                self.builder.ins().jump(self.ops[offset+1], &[]);
+            }else if self.strict{
+                let cp = self.builder.ins().iconst(types::I64, len);
+                self.leave(STATUS_OUT_OF_CODE, cp);
+            }else{
+                self.builder.ins().jump(self.ops[0], &[]);
+            }
+        }
+
+        // the jump table, for jumps by an offset not known up front
+        self.builder.switch_to_block(self.dispatch);
+        let cp = self.builder.use_var(self.cp);
+        if self.code.is_empty(){
+            // nothing to run, like fast::run
+            self.leave(STATUS_HALTED, cp);
+            return self.finish();
+        }
+        let outside = self.builder.create_block();
+        let inside = self.builder.create_block();
+        let past = self.builder.ins().icmp_imm(IntCC::UnsignedGreaterThanOrEqual, cp, len);
+        self.builder.ins().brif(past, outside, &[], inside, &[]);
+
+        self.builder.switch_to_block(outside);
+        if self.strict{
+            self.leave(STATUS_OUT_OF_CODE, cp);
+        }else{
+            self.builder.ins().jump(self.ops[0], &[]);
+        }
+
+        self.builder.switch_to_block(inside);
+        let index = self.builder.ins().ireduce(types::I32, cp);
+        let default = self.builder.func.dfg.block_call(self.ops[0], &[]);
+        let table:Vec<_> = self.ops.iter().map(|block| self.builder.func.dfg.block_call(*block, &[])).collect();
+        let table = self.builder.create_jump_table(JumpTableData::new(default, &table));
+        self.builder.ins().br_table(index, table);
+        self.finish();
+    }
+
+    fn finish(mut self){
+        let context = self.context;
+        self.builder.switch_to_block(self.exit);
+        let [status, cp] = [self.builder.block_params(self.exit)[0], self.builder.block_params(self.exit)[1]];
+        for (variable, field) in [(self.active, ACTIVE), (self.steps, STEPS)]{
+            let value = self.builder.use_var(variable);
+            self.store(value, context, field);
+        }
+        self.store(cp, context, CP);
+        self.builder.ins().return_(&[status]);
+
+        self.builder.seal_all_blocks();
+        self.builder.finalize();
+    }
+
+    fn jump(&mut self, offset:usize){
+        let a = self.pop(offset, false);
+        let halt = self.builder.create_block();
+        let go = self.builder.create_block();
+        let halts = self.builder.ins().icmp_imm(IntCC::Equal, a, -1);
+        self.builder.ins().brif(halts, halt, &[], go, &[]);
+        self.builder.switch_to_block(halt);
+        let cp = self.builder.ins().iconst(types::I64, offset as i64);
+        self.leave(STATUS_HALTED, cp);
+        self.builder.switch_to_block(go);
+
+        let values = match cfg::jump(self.code, offset){
+            Jump::Constant(value) => vec!(value),
+            Jump::Conditional(value) => vec!(0, value),
+            Jump::Dynamic => vec!(),
+        };
+        for value in values{
+            if let Target::Offset(target) = cfg::target(self.code.len(), offset, value, self.strict){
+                let next = self.builder.create_block();
+                let known = self.builder.ins().icmp_imm(IntCC::Equal, a, value);
+                self.builder.ins().brif(known, self.ops[target], &[], next, &[]);
+                self.builder.switch_to_block(next);
+            }
+        }
+
+        let cp = self.builder.ins().iadd_imm(a, offset as i64 + 1);
+        self.builder.def_var(self.cp, cp);
+        self.builder.ins().jump(self.dispatch, &[]);
+    }
+}
+
+// Code compiled to native code in memory
+pub struct Jit{
+    module:Option<JITModule>,
+    function:extern "C" fn(*mut Context) -> i32,
+    len:usize,
+}
+
+impl Jit{
+    pub fn new(code:&[u8], strict:bool) -> Result<Jit, String>{
+        let mut module = JITModule::new(JITBuilder::with_isa(target(None, false)?, default_libcall_names()));
+        let id = define(&mut module, code, strict)?;
+        module.finalize_definitions().map_err(|e| e.to_string())?;
+        // Safety: sos_run was just defined with this signature
+        let function = unsafe {std::mem::transmute::<*const u8, extern "C" fn(*mut Context) -> i32>(module.get_finalized_function(id))};
+        Ok(Jit{module: Some(module), function, len: code.len()})
+    }
+
+    // Runs the machine, which has to have the code this was compiled from, like fast::run does
+    pub fn run(&self, vm:&mut Vm, io:&mut dyn Io) -> Result<Stop, Fault>{
+        assert_eq!(vm.code.len(), self.len, "machine has different code than was compiled");
+        if vm.code.is_empty() {return Ok(Stop::Halted)}
+
+        let mut io = io;
+        let [first, second] = std::mem::take(&mut vm.stacks);
+        let mut context = Context{
+            stacks: [raw(first), raw(second)],
+            steps: vm.steps,
+            max_steps: vm.max_steps.unwrap_or(u64::MAX),
+            cp: vm.cp as u64,
+            active: vm.active as u64,
+            io: &mut io as *mut &mut dyn Io as *mut c_void,
+            read,
+            write,
+            grow,
+        };
+        let status = (self.function)(&mut context);
+
+        // Safety: the stacks are still the vectors taken apart above, grown by grow only
+        vm.stacks = unsafe {[cooked(&context.stacks[0]), cooked(&context.stacks[1])]};
+        vm.steps = context.steps;
+        vm.active = context.active as usize;
+        vm.cp = context.cp as usize;
+        match status{
+            STATUS_HALTED => Ok(Stop::Halted),
+            STATUS_STEP_LIMIT => Ok(Stop::StepLimit),
+            STATUS_STACK_DEPLETED => Err(Fault::StackDepleted),
+            _ => Err(Fault::OutOfCode(vm.cp)),
+        }
+    }
+}
+
+impl Drop for Jit{
+    fn drop(&mut self){
+        if let Some(module) = self.module.take(){
+            // Safety: function cannot be called anymore
+            unsafe {module.free_memory()};
+        }
+    }
+}
+
+// An object file for target (the host when None) defining sos_run
+pub fn object(code:&[u8], strict:bool, target_name:Option<&str>) -> Result<Vec<u8>, String>{
+    let builder = ObjectBuilder::new(target(target_name, true)?, "sos", default_libcall_names()).map_err(|e| e.to_string())?;
+    let mut module = ObjectModule::new(builder);
+    define(&mut module, code, strict)?;
+    module.finish().emit().map_err(|e| e.to_string())
+}
@@ -0,0 +1,287 @@
+// Peephole optimizer for the pure script
+//
+// Rewrites short instruction sequences into cheaper ones that leave the machine in the same state:
+//  - constant pushes ('!' or '!!^' and binary digits, eg. from macros) get their shortest form
+//  - '$$' and '~~' go, as do adding, subtracting, or'ing and xor'ing 0 ('!!^+') and and'ing -1 ('!&')
+//  - '=' followed by a drop ('!|&' or '!!^&|') goes
+// Rewrites that behave differently on an empty stack are only made where analysis shows the stacks
+// are high enough, and unreachable code is left as it is. Jumps are relative, so every jump cfg
+// resolves (a constant or 'K*@' right before the '@') gets its offset recomputed for the new layout
+// and no rewrite spans a jump target. 'K*@' only jumps to where cfg says when what K multiplies is
+// 0 or 1, so it has to come right after a test known to give that ('=/' or '!!^1&'). A jump with
+// an unknown target could land anywhere, so code with a reachable one is not touched at all.
+
+use crate::analysis::{self, State};
+use crate::assembler::{Label, Program};
+use crate::cfg::{self, Jump, Target};
+
+// Rounds of rewriting, each can make new patterns meet
+const PASSES:usize = 16;
+
+// Pattern, replacement and the heights the (active, other) stack need for it to be the same
+const RULES:[(&[u8], &[u8], usize, usize); 9] = [
+    (b"=!!^&|", b"", 1, 0),
+    (b"=!|&", b"", 1, 0),
+    (b"!!^+", b"", 1, 0),
+    (b"!!^-", b"", 1, 0),
+    (b"!!^|", b"", 1, 0),
+    (b"!!^^", b"", 1, 0),
+    (b"!&", b"", 1, 0),
+    (b"~~", b"", 1, 1),
+    (b"$$", b"", 0, 0),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Optimized{
+    pub code:Vec<u8>,
+    pub origins:Vec<usize>,     // per new offset, the old offset it came from
+    pub offsets:Vec<usize>,     // per old offset and one past the end, where it went
+    pub rewrites:usize,
+    pub dynamic:Option<usize>,  // the reachable jump with an unknown target that stopped the optimizer
+}
+
+impl Optimized{
+    fn unchanged(code:&[u8]) -> Optimized{
+        Optimized{code: code.to_vec(), origins: (0..code.len()).collect(), offsets: (0..=code.len()).collect(), rewrites: 0, dynamic: None}
+    }
+
+    // The program with the new code, labels and locations moved along
+    pub fn program(&self, program:&Program) -> Program{
+        Program{
+            code: self.code.clone(),
+            source: program.source.clone(),
+            labels: program.labels.iter().map(|l| Label{offset: self.offsets[l.offset.min(program.code.len())], ..l.clone()}).collect(),
+            macros: program.macros.clone(),
+            locations: if program.locations.is_empty() {vec!()} else {self.origins.iter().map(|o| program.locations[*o]).collect()},
+        }
+    }
+}
+
+// Digits after '!' (negative values) or '!!^' that push value
+fn digits(value:i64) -> Vec<u8>{
+    let fill = if value < 0 {-1} else {0};
+    let count = (0..64).find(|n| value >> n == fill).unwrap_or(64);
+    (0..count).rev().map(|n| if (value >> n) & 1 == 1 {b'1'} else {b'0'}).collect()
+}
+
+// Constant push of value padded to len with digits that change nothing (leading 1s after '!', 0s after '!!^')
+pub(crate) fn push(value:i64, len:usize) -> Vec<u8>{
+    let (mut out, pad) = if value < 0 {(b"!".to_vec(), b'1')} else {(b"!!^".to_vec(), b'0')};
+    let digits = digits(value);
+    out.resize(len.saturating_sub(digits.len()).max(out.len()), pad);
+    out.extend(digits);
+    out
+}
+
+pub(crate) fn shortest(value:i64) -> usize{
+    push(value, 0).len()
+}
+
+// Lowest height of the (active, other) stack in any state
+fn lows(state:&State) -> (usize, usize){
+    let mut lows = (usize::MAX, usize::MAX);
+    for (active, heights) in state.by_active.iter().enumerate(){
+        if let Some(heights) = heights{
+            lows = (lows.0.min(heights[active].lo), lows.1.min(heights[!active&1].lo));
+        }
+    }
+    lows
+}
+
+// A resolved jump: the test, constant and '*' in front of it (the latter two for 'K*@') and the '@' at end
+struct Patch{
+    start:usize,
+    end:usize,
+    target:Target,
+    test:Option<&'static [u8]>,
+}
+
+impl Patch{
+    // Length besides the constant
+    fn extra(&self) -> usize{
+        self.test.map_or(1, |test| test.len() + 2)
+    }
+}
+
+enum Segment{
+    Code(Vec<u8>, Vec<usize>),  // bytes and where each came from
+    Patch(usize),               // index in patches
+}
+
+pub fn optimize(code:&[u8], strict:bool) -> Optimized{
+    let mut optimized = Optimized::unchanged(code);
+    for _ in 0..PASSES{
+        let next = pass(&optimized.code, strict);
+        if next.dynamic.is_some(){
+            optimized.dynamic = next.dynamic;
+            break;
+        }
+        // empty code halts right away, which no non-empty program does without running '@'
+        if next.code == optimized.code || next.code.is_empty() {break}
+
+        optimized = Optimized{
+            origins: next.origins.iter().map(|o| optimized.origins[*o]).collect(),
+            offsets: optimized.offsets.iter().map(|o| next.offsets[*o]).collect(),
+            code: next.code,
+            rewrites: optimized.rewrites + next.rewrites,
+            dynamic: None,
+        };
+    }
+    optimized
+}
+
+fn pass(code:&[u8], strict:bool) -> Optimized{
+    let analysis = analysis::analyse(code, strict);
+    let mut patches = vec!();
+    let mut patched = vec![None; code.len()];
+    let mut targets = vec![false; code.len()+1];
+    let jumps = cfg::jumps(code);
+    for offset in (0..code.len()).filter(|o| code[*o] == b'@'){
+        let (value, test) = match jumps[offset]{
+            Jump::Constant(value) => (value, None),
+            Jump::Conditional(value) => (value, cfg::guard(code, offset)),
+            Jump::Dynamic => {
+                if analysis.states[offset].is_some(){
+                    return Optimized{dynamic: Some(offset), ..Optimized::unchanged(code)};
+                }
+                continue;
+            },
+        };
+        let start = cfg::span(code, offset).unwrap();
+
+        let target = cfg::target(code.len(), offset, value, strict);
+        if let Target::Offset(t) = target{
+            targets[t] = true;
+        }
+        patched[start..=offset].fill(Some(patches.len()));
+        patches.push(Patch{start, end: offset, target, test});
+    }
+
+    // a window of code that can be rewritten as a whole
+    let free = |start:usize, end:usize| {
+        end <= code.len() && patched[start..end].iter().all(Option::is_none) && !targets[start+1..end].contains(&true)
+    };
+
+    let mut segments = vec!();
+    let mut places = vec!((0, 0); code.len()+1); // segment and position within, per old offset
+    let mut bytes = vec!();
+    let mut origins = vec!();
+    let mut rewrites = 0;
+    let mut offset = 0;
+    while offset < code.len(){
+        if let Some(index) = patched[offset]{
+            segments.push(Segment::Code(std::mem::take(&mut bytes), std::mem::take(&mut origins)));
+            places[offset..=patches[index].end].fill((segments.len(), 0));
+            segments.push(Segment::Patch(index));
+            offset = patches[index].end + 1;
+            continue;
+        }
+
+        let here = (segments.len(), bytes.len());
+        let mut rewrite:Option<(usize, Vec<u8>)> = None; // length replaced and replacement
+        if let Some(state) = &analysis.states[offset]{
+            let (active, other) = lows(state);
+            for (pattern, replacement, need_active, need_other) in RULES{
+                if code[offset..].starts_with(pattern) && free(offset, offset + pattern.len()) && active >= need_active && other >= need_other{
+                    rewrite = Some((pattern.len(), replacement.to_vec()));
+                    break;
+                }
+            }
+            if rewrite.is_none() && code[offset] == b'!'{
+                let start = if code[offset..].starts_with(b"!!^") && free(offset, offset+3) {offset+3} else {offset+1};
+                let mut value = if start == offset+3 {0} else {-1};
+                let mut end = start;
+                while end < code.len() && matches!(code[end], b'0' | b'1') && free(offset, end+1){
+                    value = (value << 1) | i64::from(code[end] == b'1');
+                    end += 1;
+                }
+                if shortest(value) < end - offset{
+                    rewrite = Some((end - offset, push(value, 0)));
+                }
+            }
+        }
+
+        match rewrite{
+            Some((len, replacement)) => {
+                places[offset..offset+len].fill(here);
+                origins.extend(std::iter::repeat_n(offset, replacement.len()));
+                bytes.extend(replacement);
+                rewrites += 1;
+                offset += len;
+            },
+            None => {
+                places[offset] = here;
+                bytes.push(code[offset]);
+                origins.push(offset);
+                offset += 1;
+            },
+        }
+    }
+    segments.push(Segment::Code(bytes, origins));
+    places[code.len()] = (segments.len()-1, match segments.last() {Some(Segment::Code(b, _)) => b.len(), _ => 0});
+
+    // Jump constants only ever grow from their shortest form, so this settles
+    let mut lens:Vec<usize> = patches.iter().map(|p| {
+        let value = match jumps[p.end] {Jump::Constant(v) | Jump::Conditional(v) => v, Jump::Dynamic => 0};
+        shortest(value) + p.extra()
+    }).collect();
+    loop{
+        let (starts, values) = layout(&segments, &patches, &lens, &places);
+        let mut grown = false;
+        for (index, patch) in patches.iter().enumerate(){
+            let needed = shortest(values[index]) + patch.extra();
+            if needed > lens[index]{
+                lens[index] = needed;
+                grown = true;
+            }
+        }
+        if grown {continue}
+
+        let mut optimized = Optimized{code: vec!(), origins: vec!(), offsets: vec!(), rewrites, dynamic: None};
+        for segment in &segments{
+            match segment{
+                Segment::Code(bytes, origins) => {
+                    optimized.code.extend(bytes);
+                    optimized.origins.extend(origins);
+                },
+                Segment::Patch(index) => {
+                    let patch = &patches[*index];
+                    let mut encoded = patch.test.unwrap_or_default().to_vec();
+                    encoded.extend(push(values[*index], lens[*index] - patch.extra()));
+                    if patch.test.is_some() {encoded.push(b'*')}
+                    encoded.push(b'@');
+                    optimized.origins.extend(std::iter::repeat_n(patch.start, encoded.len()-1));
+                    optimized.origins.push(patch.end);
+                    optimized.code.extend(encoded);
+                },
+            }
+        }
+        optimized.offsets = places.iter().map(|(segment, position)| starts[*segment] + position).collect();
+        return optimized;
+    }
+}
+
+// Start of every segment and the value every jump needs for its target
+fn layout(segments:&[Segment], patches:&[Patch], lens:&[usize], places:&[(usize, usize)]) -> (Vec<usize>, Vec<i64>){
+    let mut starts = vec!();
+    let mut position = 0;
+    for segment in segments{
+        starts.push(position);
+        position += match segment{
+            Segment::Code(bytes, _) => bytes.len(),
+            Segment::Patch(index) => lens[*index],
+        };
+    }
+    starts.push(position);
+
+    let new = |old:usize| starts[places[old].0] + places[old].1;
+    let values = segments.iter().enumerate().filter_map(|(index, segment)| match segment{
+        Segment::Patch(patch) => Some((starts[index] + lens[*patch] - 1, &patches[*patch])),
+        Segment::Code(..) => None,
+    }).map(|(at, patch)| match patch.target{
+        Target::Halt => -1,
+        Target::Offset(target) => new(target) as i64 - at as i64 - 1,
+        Target::OutOfCode => position as i64 - at as i64 - 1,
+    }).collect();
+    (starts, values)
+}
@@ -0,0 +1,145 @@
+// Execution profiler: counts how often every code offset is executed and which '@' jumps are taken
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use crate::assembler::Program;
+use crate::vm::{Effect, Observer, Vm};
+
+const HOT_INSTRUCTIONS:usize = 20;
+const HOT_EDGES:usize = 20;
+
+// Name of the region before the first label
+const TOP:&str = "(top)";
+
+pub struct Profiler{
+    pub counts:Vec<u64>,                  // executions per code offset
+    pub edges:HashMap<(usize, usize), u64>, // '@' from offset -> to offset (the next instruction executed)
+}
+
+impl Profiler{
+    pub fn new(code_len:usize) -> Profiler{
+        Profiler{counts: vec![0; code_len], edges: HashMap::new()}
+    }
+
+    pub fn total(&self) -> u64{
+        self.counts.iter().sum()
+    }
+
+    fn percentage(&self, count:u64) -> f64{
+        let total = self.total();
+        if total == 0 {0.0} else {count as f64 * 100.0 / total as f64}
+    }
+
+    fn region(program:&Program, offset:usize) -> &str{
+        program.label_at(offset).map_or(TOP, |(name, _)| name)
+    }
+
+    fn location(program:&Program, offset:usize) -> String{
+        program.location(offset).map_or("-".to_owned(), |l| format!("{}:{}", l.line, l.column))
+    }
+
+    // Executions summed per label region, hottest first
+    pub fn per_label<'a>(&self, program:&'a Program) -> Vec<(&'a str, u64, usize)>{
+        let mut labels:Vec<(&str, u64, usize)> = vec!(); // name, executions, instructions in region
+        let mut index:HashMap<&str, usize> = HashMap::new();
+        for (offset, count) in self.counts.iter().enumerate(){
+            let name = Profiler::region(program, offset);
+            let entry = *index.entry(name).or_insert_with(|| {labels.push((name, 0, 0)); labels.len()-1});
+            labels[entry].1 += count;
+            labels[entry].2 += 1;
+        }
+        labels.sort_by_key(|(_, count, _)| std::cmp::Reverse(*count));
+        labels
+    }
+
+    pub fn report(&self, program:&Program) -> String{
+        let mut out = String::new();
+        let _ = writeln!(out, "Profile: {} instructions executed", self.total());
+
+        let mut hot:Vec<(usize, u64)> = self.counts.iter().copied().enumerate().filter(|(_, c)| *c > 0).collect();
+        hot.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        let _ = writeln!(out, "\nHot instructions:");
+        let _ = writeln!(out, "{:>14} {:>7}  {:>18}  op  {:>9}  symbol", "count", "%", "offset", "line:col");
+        for (offset, count) in hot.iter().take(HOT_INSTRUCTIONS){
+            let _ = writeln!(out, "{:>14} {:>6.2}%  {:#018X}  {}   {:>9}  {}",
+                count, self.percentage(*count), offset, program.code[*offset] as char, Profiler::location(program, *offset), program.symbolise(*offset));
+        }
+
+        let _ = writeln!(out, "\nPer label:");
+        let _ = writeln!(out, "{:>14} {:>7}  {:>12}  label", "count", "%", "instructions");
+        for (name, count, size) in self.per_label(program){
+            let _ = writeln!(out, "{:>14} {:>6.2}%  {:>12}  {}", count, self.percentage(count), size, name);
+        }
+
+        let mut edges:Vec<(&(usize, usize), &u64)> = self.edges.iter().collect();
+        edges.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+
+        let _ = writeln!(out, "\nJump edges ('@'):");
+        let _ = writeln!(out, "{:>14}  {:>18}    {:>18}  symbols", "count", "from", "to");
+        for ((from, to), count) in edges.iter().take(HOT_EDGES){
+            let _ = writeln!(out, "{:>14}  {:#018X} -> {:#018X}  {} -> {}", count, from, to, program.symbolise(*from), program.symbolise(*to));
+        }
+
+        out
+    }
+
+    // The source with executions per line in front of it, or the pure script when there is no source
+    pub fn listing(&self, program:&Program) -> String{
+        let mut out = String::new();
+
+        if program.locations.is_empty(){
+            for (offset, count) in self.counts.iter().enumerate(){
+                let _ = writeln!(out, "{:>14} {:>6.2}% | {:#018X}: {}", count, self.percentage(*count), offset, program.code[offset] as char);
+            }
+            return out;
+        }
+
+        let mut per_line:BTreeMap<usize, u64> = BTreeMap::new();
+        for (offset, count) in self.counts.iter().enumerate(){
+            *per_line.entry(program.locations[offset].line).or_insert(0) += count;
+        }
+
+        let source = String::from_utf8_lossy(&program.source);
+        for (index, line) in source.lines().enumerate(){
+            match per_line.get(&(index+1)){
+                Some(count) => {let _ = writeln!(out, "{:>14} {:>6.2}% | {}", count, self.percentage(*count), line);},
+                None => {let _ = writeln!(out, "{:>14} {:>7} | {}", "", "", line);},
+            }
+        }
+
+        out
+    }
+
+    // Collapsed stacks (program;label;line count) as consumed by flamegraph.pl and compatible tools
+    pub fn collapsed(&self, program:&Program, name:&str) -> String{
+        let mut frames:BTreeMap<String, u64> = BTreeMap::new();
+        for (offset, count) in self.counts.iter().enumerate(){
+            if *count == 0 {continue}
+            let position = match program.location(offset){
+                Some(location) => format!("line {}", location.line),
+                None => format!("{:#018X}", offset),
+            };
+            *frames.entry(format!("{};{};{}", name, Profiler::region(program, offset), position)).or_insert(0) += count;
+        }
+
+        let mut out = String::new();
+        for (frame, count) in frames{
+            let _ = writeln!(out, "{} {}", frame, count);
+        }
+        out
+    }
+}
+
+impl Observer for Profiler{
+    fn before(&mut self, vm:&Vm){
+        self.counts[vm.cp] += 1;
+    }
+
+    fn after(&mut self, vm:&Vm, cp:usize, effect:&Effect){
+        if let Effect::Jump(_) = effect{
+            *self.edges.entry((cp, vm.cp)).or_insert(0) += 1;
+        }
+    }
+}
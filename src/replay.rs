@@ -0,0 +1,131 @@
+// Recording and replaying program input
+//
+// A recording lists every value '?' pushed, EOF (-1) included, with the step that read it, one
+// "STEP VALUE" pair per line. Replaying feeds the values back in order, Replayer hands them to the
+// machine and ReplayCheck watches that they are read at the same steps as when recorded.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use crate::vm::{Effect, Io, Observer, Vm};
+
+const HEADER:&str = "# stackofstacks input recording: STEP VALUE";
+
+// Writes the recording while the program runs, so it survives the program being killed
+pub struct Recorder{
+    out:BufWriter<File>,
+    error:Option<io::Error>,
+}
+
+impl Recorder{
+    pub fn create(filename:&str) -> io::Result<Recorder>{
+        let mut out = BufWriter::new(File::create(filename)?);
+        writeln!(out, "{}", HEADER)?;
+        Ok(Recorder{out, error: None})
+    }
+
+    pub fn error(&self) -> Option<&io::Error>{
+        self.error.as_ref()
+    }
+}
+
+impl Observer for Recorder{
+    fn after(&mut self, vm:&Vm, _cp:usize, effect:&Effect){
+        if let Effect::Read(value) = effect{
+            // flushed per read, input is slow anyway
+            if let Err(e) = writeln!(self.out, "{} {}", vm.steps, value).and_then(|_| self.out.flush()){
+                self.error.get_or_insert(e);
+            }
+        }
+    }
+}
+
+pub type Recording = Vec<(u64, i64)>;
+
+pub fn parse(text:&str) -> Result<Recording, String>{
+    let mut recording = vec!();
+    for (index, line) in text.lines().enumerate(){
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {continue}
+        let entry = line.split_once(' ').and_then(|(step, value)| Some((step.parse().ok()?, value.trim().parse().ok()?)));
+        match entry{
+            Some((step, value)) if (-1..=255).contains(&value) => recording.push((step, value)),
+            _ => return Err(format!("Invalid recording on line {}: '{}'", index+1, line)),
+        }
+    }
+    Ok(recording)
+}
+
+pub fn load(filename:&str) -> Result<Recording, String>{
+    parse(&std::fs::read_to_string(filename).map_err(|e| e.to_string())?)
+}
+
+// Gives the recorded values in order, EOF once they run out. Output goes to the wrapped Io.
+pub struct Replayer<O:Io>{
+    recording:Recording,
+    position:usize,
+    pub output:O,
+}
+
+impl<O:Io> Replayer<O>{
+    pub fn new(recording:Recording, output:O) -> Replayer<O>{
+        Replayer{recording, position: 0, output}
+    }
+}
+
+impl<O:Io> Io for Replayer<O>{
+    fn read(&mut self) -> i64{
+        let value = self.recording.get(self.position).map_or(-1, |(_, value)| *value);
+        self.position += 1;
+        value
+    }
+
+    fn write(&mut self, byte:u8){
+        self.output.write(byte);
+    }
+}
+
+// The first read that did not happen as recorded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Divergence{
+    pub read:usize,             // index in the recording
+    pub step:u64,               // when the program read
+    pub recorded:Option<u64>,   // when it was recorded to, None when past the end
+}
+
+impl std::fmt::Display for Divergence{
+    fn fmt(&self, f:&mut std::fmt::Formatter) -> std::fmt::Result{
+        match self.recorded{
+            Some(recorded) => write!(f, "Replay diverged: read {} happened at step {} but was recorded at step {}", self.read+1, self.step, recorded),
+            None => write!(f, "Replay diverged: read {} at step {} is past the end of the recording", self.read+1, self.step),
+        }
+    }
+}
+
+pub struct ReplayCheck{
+    steps:Vec<u64>,
+    position:usize,
+    pub divergence:Option<Divergence>,
+}
+
+impl ReplayCheck{
+    pub fn new(recording:&Recording) -> ReplayCheck{
+        ReplayCheck{steps: recording.iter().map(|(step, _)| *step).collect(), position: 0, divergence: None}
+    }
+
+    // Recorded reads the program never got to
+    pub fn unread(&self) -> usize{
+        self.steps.len().saturating_sub(self.position)
+    }
+}
+
+impl Observer for ReplayCheck{
+    fn after(&mut self, vm:&Vm, _cp:usize, effect:&Effect){
+        if !matches!(effect, Effect::Read(_)) {return}
+        let recorded = self.steps.get(self.position).copied();
+        if self.divergence.is_none() && recorded != Some(vm.steps){
+            self.divergence = Some(Divergence{read: self.position, step: vm.steps, recorded});
+        }
+        self.position += 1;
+    }
+}
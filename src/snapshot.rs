@@ -0,0 +1,158 @@
+// Snapshots of the complete machine state, for checkpointing long runs and resuming them later
+//
+// A snapshot is a JSON object. The code itself is not stored, only a hash of it, so a snapshot
+// can only be resumed with the program it was taken from. The input position is the number of
+// bytes '?' consumed, on resume that many bytes are skipped from the input.
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+
+use crate::json::{self, Value};
+use crate::vm::{Effect, Observer, Vm};
+
+const VERSION:i64 = 1;
+
+// FNV-1a, stable across builds unlike the std hashers
+pub fn code_hash(code:&[u8]) -> u64{
+    code.iter().fold(0xCBF2_9CE4_8422_2325, |hash, b| (hash ^ *b as u64).wrapping_mul(0x0000_0100_0000_01B3))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot{
+    pub code_hash:u64,
+    pub cp:usize,
+    pub active:usize,
+    pub stacks:[Vec<i64>;2],
+    pub ram:HashMap<i64, i64>,
+    pub steps:u64,
+    pub input:u64, // bytes read so far
+}
+
+impl Snapshot{
+    pub fn capture(vm:&Vm, input:u64) -> Snapshot{
+        Snapshot{
+            code_hash: code_hash(&vm.code),
+            cp: vm.cp,
+            active: vm.active,
+            stacks: vm.stacks.clone(),
+            ram: vm.ram.clone(),
+            steps: vm.steps,
+            input,
+        }
+    }
+
+    // Puts the machine in the saved state, the code has to be the same
+    pub fn restore(&self, vm:&mut Vm) -> Result<(), String>{
+        if code_hash(&vm.code) != self.code_hash{
+            return Err("Snapshot was taken of a different program".to_owned());
+        }
+        if self.cp >= vm.code.len(){
+            return Err(format!("Snapshot CP {} is outside of the code", self.cp));
+        }
+        vm.cp = self.cp;
+        vm.active = self.active;
+        vm.stacks = self.stacks.clone();
+        vm.ram = self.ram.clone();
+        vm.steps = self.steps;
+        Ok(())
+    }
+
+    pub fn to_json(&self) -> String{
+        let list = |values:&mut dyn Iterator<Item=String>| values.collect::<Vec<_>>().join(",");
+        let mut ram:Vec<(&i64, &i64)> = self.ram.iter().collect();
+        ram.sort();
+
+        let mut out = String::new();
+        let _ = writeln!(out, "{{\"version\":{},\"code\":\"{:016x}\",\"cp\":{},\"stack\":{},\"steps\":{},\"input\":{},",
+            VERSION, self.code_hash, self.cp, self.active, self.steps, self.input);
+        let _ = writeln!(out, "\"stacks\":[[{}],", list(&mut self.stacks[0].iter().map(i64::to_string)));
+        let _ = writeln!(out, "[{}]],", list(&mut self.stacks[1].iter().map(i64::to_string)));
+        let _ = writeln!(out, "\"ram\":[{}]}}", list(&mut ram.iter().map(|(a, v)| format!("[{},{}]", a, v))));
+        out
+    }
+
+    pub fn from_json(value:&Value) -> Result<Snapshot, String>{
+        let int = |key:&str| value.get(key).and_then(Value::as_i64).filter(|v| *v >= 0).ok_or(format!("Missing or invalid '{}'", key));
+        let values = |value:&Value| -> Option<Vec<i64>>{
+            value.as_array()?.iter().map(Value::as_i64).collect()
+        };
+
+        if int("version")? != VERSION{
+            return Err(format!("Unsupported snapshot version {}", int("version")?));
+        }
+        let code_hash = value.get("code").and_then(Value::as_str).and_then(|h| u64::from_str_radix(h, 16).ok()).ok_or("Missing or invalid 'code'")?;
+        let stacks = match value.get("stacks").and_then(Value::as_array).map(|a| a.as_slice()){
+            Some([a, b]) => [values(a).ok_or("Invalid 'stacks'")?, values(b).ok_or("Invalid 'stacks'")?],
+            _ => return Err("Missing or invalid 'stacks'".to_owned()),
+        };
+        let mut ram = HashMap::new();
+        for entry in value.get("ram").and_then(Value::as_array).ok_or("Missing or invalid 'ram'")?{
+            match values(entry).as_deref(){
+                Some([address, value]) => {ram.insert(*address, *value);},
+                _ => return Err("Invalid 'ram'".to_owned()),
+            }
+        }
+
+        Ok(Snapshot{
+            code_hash,
+            cp: int("cp")? as usize,
+            active: int("stack")? as usize & 1,
+            stacks,
+            ram,
+            steps: int("steps")? as u64,
+            input: int("input")? as u64,
+        })
+    }
+
+    pub fn save(&self, filename:&str) -> io::Result<()>{
+        fs::write(filename, self.to_json())
+    }
+
+    pub fn load(filename:&str) -> Result<Snapshot, String>{
+        let text = fs::read_to_string(filename).map_err(|e| e.to_string())?;
+        Snapshot::from_json(&json::parse(&text)?)
+    }
+}
+
+// Saves a snapshot every so many steps and when the run stops before the program ends. A halt
+// or fault leaves the last checkpoint alone, there is nothing left to resume.
+pub struct Checkpoint{
+    pub filename:String,
+    pub every:Option<u64>,
+    pub input:u64,                  // bytes read, including before a resume
+    pub error:Option<io::Error>,    // first failure to save
+    completed:Option<u64>,          // steps after the last instruction that completed, None once halted
+}
+
+impl Checkpoint{
+    pub fn new(filename:&str, every:Option<u64>, input:u64) -> Checkpoint{
+        Checkpoint{filename: filename.to_owned(), every, input, error: None, completed: None}
+    }
+
+    fn save(&mut self, vm:&Vm){
+        if let Err(e) = Snapshot::capture(vm, self.input).save(&self.filename){
+            self.error.get_or_insert(e);
+        }
+    }
+}
+
+impl Observer for Checkpoint{
+    fn after(&mut self, vm:&Vm, _cp:usize, effect:&Effect){
+        self.completed = (*effect != Effect::Halt).then_some(vm.steps);
+        if matches!(effect, Effect::Read(value) if *value != -1){
+            self.input += 1;
+        }
+        if self.completed.is_some() && self.every.is_some_and(|every| vm.steps.is_multiple_of(every)){
+            self.save(vm);
+        }
+    }
+
+    fn finish(&mut self, vm:&Vm){
+        // a fault counted the step without completing it
+        if self.completed == Some(vm.steps){
+            self.save(vm);
+        }
+    }
+}
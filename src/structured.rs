@@ -0,0 +1,683 @@
+// A small structured language compiled to Stack Of Stacks source
+//
+//     # comments run to the end of the line
+//     fn digit(n) { return '0' + n % 10; }
+//     var count = 3;
+//     while count > 0 {
+//         put(digit(count));
+//         count = count - 1;
+//     }
+//     if get() == -1 { put('!'); } else { put('?'); }
+//
+// Values are i64 like everywhere else. Every value lives on stack 0 above a -1 at the bottom, and
+// since blocks leave the stack as they found it the compiler knows how high it is at every point,
+// so a variable is its position from the bottom. Reading one moves what is above it over to stack
+// 1 (which has a -1 at the bottom too), copies it and carries the copy back on top. Assigning
+// carries the value down the same way. 'if' and 'while' are the branch and while from turing/,
+// a 0/1 test feeding a 'K*@' to labels the assembler resolves, statements just follow each other.
+// Functions are inlined where they are called, so they have to be defined before that and can't
+// call themselves. Their parameters are local variables, they see the variables declared at the
+// top before them and give back what 'return' says or 0. Comparisons go by the sign of the
+// difference, 'put' writes the low byte of a value, 'get()' reads a byte and gives -1 on EOF.
+
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+
+use crate::optimize::push;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Position{
+    line:usize,
+    column:usize,
+}
+
+impl fmt::Display for Position{
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result{
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token{
+    Number(i64),
+    Name(String),
+    Symbol(&'static str),
+    End,
+}
+
+impl fmt::Display for Token{
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result{
+        match self{
+            Token::Number(n) => write!(f, "{}", n),
+            Token::Name(name) => write!(f, "'{}'", name),
+            Token::Symbol(s) => write!(f, "'{}'", s),
+            Token::End => write!(f, "end of file"),
+        }
+    }
+}
+
+// Longest first so '==' isn't taken for two '='
+const SYMBOLS:[&str; 25] = ["==", "!=", "<=", ">=", "&&", "||", "(", ")", "{", "}", ";", ",", "=", "<", ">",
+    "+", "-", "*", "/", "%", "&", "|", "^", "!", "~"];
+
+const KEYWORDS:[&str; 8] = ["var", "fn", "if", "else", "while", "return", "put", "get"];
+
+fn character(bytes:&[u8]) -> Option<(i64, usize)>{
+    match bytes{
+        [b'\\', escaped, b'\'', ..] => {
+            let value = match escaped{
+                b'n' => b'\n',
+                b't' => b'\t',
+                b'0' => 0,
+                b'\\' | b'\'' => *escaped,
+                _ => return None,
+            };
+            Some((i64::from(value), 3))
+        },
+        [c, b'\'', ..] if c.is_ascii() && *c != b'\\' => Some((i64::from(*c), 2)),
+        _ => None,
+    }
+}
+
+fn number(text:&str) -> Option<i64>{
+    match text.as_bytes(){
+        // bit patterns, so 0x8000000000000000 is the lowest value
+        [b'0', b'x', ..] => u64::from_str_radix(&text[2..], 16).ok().map(|v| v as i64),
+        [b'0', b'b', ..] => u64::from_str_radix(&text[2..], 2).ok().map(|v| v as i64),
+        _ => text.parse().ok(),
+    }
+}
+
+fn tokenise(source:&[u8]) -> Result<Vec<(Token, Position)>, String>{
+    let mut tokens = vec!();
+    let mut position = Position{line: 1, column: 1};
+    let mut index = 0;
+    while index < source.len(){
+        let start = position;
+        let rest = &source[index..];
+        let length = match rest[0]{
+            b'\n' => {
+                position = Position{line: position.line + 1, column: 1};
+                index += 1;
+                continue;
+            },
+            b' ' | b'\t' | b'\r' => 1,
+            b'#' => rest.iter().take_while(|b| **b != b'\n').count(),
+            b'\'' => {
+                let (value, length) = character(&rest[1..]).ok_or(format!("Bad character literal at {}", start))?;
+                tokens.push((Token::Number(value), start));
+                length + 1
+            },
+            c if c.is_ascii_alphanumeric() || c == b'_' => {
+                let length = rest.iter().take_while(|b| b.is_ascii_alphanumeric() || **b == b'_').count();
+                let text = std::str::from_utf8(&rest[..length]).unwrap();
+                if c.is_ascii_digit(){
+                    let value = number(text).ok_or(format!("Bad number '{}' at {}", text, start))?;
+                    tokens.push((Token::Number(value), start));
+                }else{
+                    tokens.push((Token::Name(text.to_owned()), start));
+                }
+                length
+            },
+            _ => {
+                let symbol = SYMBOLS.iter().find(|s| rest.starts_with(s.as_bytes()))
+                    .ok_or(format!("Unexpected character '{}' at {}", String::from_utf8_lossy(&rest[..1]), start))?;
+                tokens.push((Token::Symbol(symbol), start));
+                symbol.len()
+            },
+        };
+        index += length;
+        position.column += length;
+    }
+    tokens.push((Token::End, position));
+    Ok(tokens)
+}
+
+
+#[derive(Debug, Clone)]
+enum Expression{
+    Number(i64),
+    Variable(String, Position),
+    Call(String, Vec<Expression>, Position),
+    Get,
+    Unary(&'static str, Box<Expression>),
+    Binary(&'static str, Box<Expression>, Box<Expression>),
+}
+
+#[derive(Debug, Clone)]
+enum Statement{
+    Var(String, Expression),
+    Assign(String, Expression, Position),
+    Put(Expression),
+    Expression(Expression),
+    If(Expression, Vec<Line>, Vec<Line>),
+    While(Expression, Vec<Line>),
+    Return(Option<Expression>, Position),
+    Function(String, Vec<String>, Vec<Line>),
+}
+
+// A statement and the line it starts on
+type Line = (usize, Statement);
+
+// Operators from the loosest to the tightest
+const PRECEDENCE:[&[&str]; 8] = [&["||"], &["&&"], &["==", "!=", "<", ">", "<=", ">="], &["|"], &["^"], &["&"], &["+", "-"], &["*", "/", "%"]];
+
+struct Parser{
+    tokens:Vec<(Token, Position)>,
+    index:usize,
+}
+
+impl Parser{
+    fn peek(&self) -> &Token{
+        &self.tokens[self.index].0
+    }
+
+    fn position(&self) -> Position{
+        self.tokens[self.index].1
+    }
+
+    fn next(&mut self) -> Token{
+        let token = self.peek().clone();
+        if token != Token::End {self.index += 1}
+        token
+    }
+
+    fn unexpected<T>(&self) -> Result<T, String>{
+        Err(format!("Unexpected {} at {}", self.peek(), self.position()))
+    }
+
+    fn is(&self, symbol:&str) -> bool{
+        matches!(self.peek(), Token::Symbol(s) if *s == symbol)
+    }
+
+    fn keyword(&self, keyword:&str) -> bool{
+        matches!(self.peek(), Token::Name(name) if name == keyword)
+    }
+
+    fn expect(&mut self, symbol:&str) -> Result<(), String>{
+        if !self.is(symbol){
+            return Err(format!("Expected '{}' but found {} at {}", symbol, self.peek(), self.position()));
+        }
+        self.next();
+        Ok(())
+    }
+
+    fn name(&mut self) -> Result<String, String>{
+        match self.peek(){
+            Token::Name(name) if !KEYWORDS.contains(&name.as_str()) => {
+                let name = name.clone();
+                self.next();
+                Ok(name)
+            },
+            _ => self.unexpected(),
+        }
+    }
+
+    fn block(&mut self) -> Result<Vec<Line>, String>{
+        self.expect("{")?;
+        let mut lines = vec!();
+        while !self.is("}"){
+            if *self.peek() == Token::End {return self.unexpected()}
+            lines.push(self.statement(false)?);
+        }
+        self.next();
+        Ok(lines)
+    }
+
+    fn statement(&mut self, top:bool) -> Result<Line, String>{
+        let position = self.position();
+        let statement = match self.peek(){
+            Token::Name(keyword) => match keyword.as_str(){
+                "fn" if top => {
+                    self.next();
+                    let name = self.name()?;
+                    self.expect("(")?;
+                    let mut parameters = vec!();
+                    while !self.is(")"){
+                        if !parameters.is_empty() {self.expect(",")?}
+                        parameters.push(self.name()?);
+                    }
+                    self.next();
+                    Statement::Function(name, parameters, self.block()?)
+                },
+                "var" => {
+                    self.next();
+                    let name = self.name()?;
+                    self.expect("=")?;
+                    let value = self.expression(0)?;
+                    self.expect(";")?;
+                    Statement::Var(name, value)
+                },
+                "if" => self.branch()?,
+                "while" => {
+                    self.next();
+                    Statement::While(self.expression(0)?, self.block()?)
+                },
+                "return" => {
+                    self.next();
+                    let value = if self.is(";") {None} else {Some(self.expression(0)?)};
+                    self.expect(";")?;
+                    Statement::Return(value, position)
+                },
+                "put" => {
+                    self.next();
+                    self.expect("(")?;
+                    let value = self.expression(0)?;
+                    self.expect(")")?;
+                    self.expect(";")?;
+                    Statement::Put(value)
+                },
+                _ if matches!(self.tokens[self.index+1].0, Token::Symbol("=")) => {
+                    let name = self.name()?;
+                    self.next();
+                    let value = self.expression(0)?;
+                    self.expect(";")?;
+                    Statement::Assign(name, value, position)
+                },
+                _ => self.expression_statement()?,
+            },
+            _ => self.expression_statement()?,
+        };
+        Ok((position.line, statement))
+    }
+
+    fn expression_statement(&mut self) -> Result<Statement, String>{
+        let value = self.expression(0)?;
+        self.expect(";")?;
+        Ok(Statement::Expression(value))
+    }
+
+    fn branch(&mut self) -> Result<Statement, String>{
+        self.next();
+        let condition = self.expression(0)?;
+        let then = self.block()?;
+        let mut otherwise = vec!();
+        if self.keyword("else"){
+            self.next();
+            if self.keyword("if"){
+                let line = self.position().line;
+                otherwise.push((line, self.branch()?));
+            }else{
+                otherwise = self.block()?;
+            }
+        }
+        Ok(Statement::If(condition, then, otherwise))
+    }
+
+    fn expression(&mut self, level:usize) -> Result<Expression, String>{
+        if level == PRECEDENCE.len() {return self.unary()}
+        let mut left = self.expression(level+1)?;
+        while let Token::Symbol(symbol) = *self.peek(){
+            if !PRECEDENCE[level].contains(&symbol) {break}
+            self.next();
+            left = Expression::Binary(symbol, Box::new(left), Box::new(self.expression(level+1)?));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expression, String>{
+        match *self.peek(){
+            Token::Symbol(symbol @ ("-" | "!" | "~")) => {
+                self.next();
+                match (symbol, self.unary()?){
+                    ("-", Expression::Number(value)) => Ok(Expression::Number(value.wrapping_neg())),
+                    (_, value) => Ok(Expression::Unary(symbol, Box::new(value))),
+                }
+            },
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expression, String>{
+        let position = self.position();
+        match self.peek().clone(){
+            Token::Number(value) => {
+                self.next();
+                Ok(Expression::Number(value))
+            },
+            Token::Symbol("(") => {
+                self.next();
+                let value = self.expression(0)?;
+                self.expect(")")?;
+                Ok(value)
+            },
+            Token::Name(name) if name == "get" => {
+                self.next();
+                self.expect("(")?;
+                self.expect(")")?;
+                Ok(Expression::Get)
+            },
+            Token::Name(_) => {
+                let name = self.name()?;
+                if !self.is("(") {return Ok(Expression::Variable(name, position))}
+                self.next();
+                let mut arguments = vec!();
+                while !self.is(")"){
+                    if !arguments.is_empty() {self.expect(",")?}
+                    arguments.push(self.expression(0)?);
+                }
+                self.next();
+                Ok(Expression::Call(name, arguments, position))
+            },
+            _ => self.unexpected(),
+        }
+    }
+}
+
+
+// Moves the top of stack 0 over to stack 1 and back, both keep their -1 at the bottom
+const OUT:&str = "$!$~&";
+const IN:&str = "!$~&$";
+const DROP:&str = "!!^&|";
+const NOT:&str = "!!^1^"; // of a 0/1 test
+const NEGATE:&str = "!^!!^1+";
+
+#[derive(Debug, Clone)]
+struct Function{
+    parameters:Vec<String>,
+    body:Vec<Line>,
+    globals:Vec<(String, usize)>,
+}
+
+// A function being inlined
+struct Frame{
+    name:String,
+    base:usize, // height before its arguments
+    end:String,
+}
+
+struct Compiler<'a>{
+    lines:Vec<&'a str>,
+    out:String,
+    code:String, // of the line being written
+    indent:usize,
+    commented:usize, // line of the last comment, statements sharing it get one
+    height:usize,
+    scopes:Vec<Vec<(String, usize)>>, // variables and their positions, innermost last
+    functions:HashMap<String, Function>,
+    frames:Vec<Frame>,
+    labels:usize,
+}
+
+// Lower case name for n, labels can only have those
+fn name(mut n:usize) -> String{
+    let mut name = String::new();
+    loop{
+        name.push((b'a' + (n % 26) as u8) as char);
+        n /= 26;
+        if n == 0 {return name}
+    }
+}
+
+impl Compiler<'_>{
+    fn flush(&mut self){
+        if !self.code.is_empty(){
+            let _ = writeln!(self.out, "{}{}", "\t".repeat(self.indent), self.code);
+            self.code.clear();
+        }
+    }
+
+    // The source line as a comment, non ASCII would upset the assembler
+    fn comment(&mut self, line:usize){
+        self.flush();
+        if line == self.commented {return}
+        self.commented = line;
+        let text:String = self.lines[line-1].trim().chars().map(|c| if c.is_ascii() {c} else {'?'}).collect();
+        let _ = writeln!(self.out, "{}# {}: {}", "\t".repeat(self.indent), line, text);
+    }
+
+    fn emit(&mut self, code:&str, change:isize){
+        self.code.push_str(code);
+        self.height = self.height.checked_add_signed(change).expect("stack height below the bottom");
+    }
+
+    fn label(&mut self, kind:&str) -> String{
+        self.labels += 1;
+        format!("{}{}", kind, name(self.labels - 1))
+    }
+
+    fn place(&mut self, label:&str){
+        let _ = write!(self.code, ":{} ", label);
+    }
+
+    // Jumps to target, if conditional only when the 0/1 on top is 1
+    fn jump(&mut self, target:&str, conditional:bool){
+        let at = self.label("at");
+        let _ = write!(self.code, "[{}-{}]{}@:{} ", target, at, if conditional {"*"} else {""}, at);
+        if conditional {self.height -= 1}
+    }
+
+    fn lookup(&self, name:&str, position:Position) -> Result<usize, String>{
+        self.scopes.iter().rev().flat_map(|scope| scope.iter().rev()).find(|(n, _)| n == name)
+            .map(|(_, p)| *p).ok_or(format!("Unknown variable '{}' at {}", name, position))
+    }
+
+    // Copies the value depth below the top onto it
+    fn pick(&mut self, depth:usize){
+        let code = format!("{}={}", OUT.repeat(depth), format!("~{}", IN).repeat(depth));
+        self.emit(&code, 1);
+    }
+
+    // Pops the top into the value depth below it
+    fn store(&mut self, depth:usize){
+        let code = format!("{}${}${}", format!("{}~", OUT).repeat(depth), DROP, IN.repeat(depth-1));
+        self.emit(&code, -1);
+    }
+
+    fn expression(&mut self, expression:&Expression) -> Result<(), String>{
+        match expression{
+            Expression::Number(value) => self.emit(std::str::from_utf8(&push(*value, 0)).unwrap(), 1),
+            Expression::Variable(name, position) => {
+                let variable = self.lookup(name, *position)?;
+                self.pick(self.height - 1 - variable);
+            },
+            Expression::Call(name, arguments, position) => self.call(name, arguments, *position)?,
+            Expression::Get => self.emit("?", 1),
+            Expression::Unary(operator, value) => {
+                self.expression(value)?;
+                self.emit(match *operator{
+                    "-" => NEGATE,
+                    "!" => "=/!!^1^",
+                    _ => "!^",
+                }, 0);
+            },
+            Expression::Binary(operator @ ("&&" | "||"), left, right) => {
+                self.test(left)?;
+                self.test(right)?;
+                self.emit(if *operator == "&&" {"&"} else {"|"}, -1);
+            },
+            Expression::Binary(operator, left, right) => {
+                self.expression(left)?;
+                self.expression(right)?;
+                let sign = format!("{}&=/", String::from_utf8(push(i64::MIN, 0)).unwrap());
+                let code = match *operator{
+                    "%" => {
+                        // a - a / b * b
+                        self.pick(1);
+                        self.pick(1);
+                        "/*-".to_owned()
+                    },
+                    "==" => format!("-=/{}", NOT),
+                    "!=" => "-=/".to_owned(),
+                    "<" => format!("-{}", sign),
+                    ">" => format!("-{}{}", NEGATE, sign),
+                    "<=" => format!("-{}{}{}", NEGATE, sign, NOT),
+                    ">=" => format!("-{}{}", sign, NOT),
+                    _ => operator.to_string(),
+                };
+                self.emit(&code, if *operator == "%" {-3} else {-1});
+            },
+        }
+        Ok(())
+    }
+
+    // Pushes 1 for a value that isn't 0 and 0 for one that is
+    fn test(&mut self, expression:&Expression) -> Result<(), String>{
+        self.expression(expression)?;
+        let boolean = match expression{
+            Expression::Unary(operator, _) => *operator == "!",
+            Expression::Binary(operator, ..) => ["&&", "||", "==", "!=", "<", ">", "<=", ">="].contains(operator),
+            _ => false,
+        };
+        if !boolean {self.emit("=/", 0)}
+        Ok(())
+    }
+
+    fn call(&mut self, name:&str, arguments:&[Expression], position:Position) -> Result<(), String>{
+        let Some(function) = self.functions.get(name).cloned() else {
+            return Err(format!("Unknown function '{}' at {}, functions have to be defined before they are called", name, position));
+        };
+        if self.frames.iter().any(|f| f.name == name){
+            return Err(format!("Recursive call to '{}' at {}, functions are inlined", name, position));
+        }
+        if arguments.len() != function.parameters.len(){
+            return Err(format!("'{}' takes {} arguments, not {} at {}", name, function.parameters.len(), arguments.len(), position));
+        }
+
+        let base = self.height;
+        for argument in arguments{
+            self.expression(argument)?;
+        }
+        let parameters = function.parameters.iter().enumerate().map(|(index, p)| (p.clone(), base + index)).collect();
+        let scopes = std::mem::replace(&mut self.scopes, vec![function.globals, parameters]);
+        let end = self.label("return");
+        self.frames.push(Frame{name: name.to_owned(), base, end: end.clone()});
+        self.flush();
+        self.indent += 1;
+        self.commented = 0;
+
+        for line in &function.body{
+            self.statement(line)?;
+        }
+        // falling off the end returns 0
+        self.emit("!!^", 1);
+        self.finish(base);
+        self.place(&end);
+        self.flush();
+
+        self.indent -= 1;
+        self.frames.pop();
+        self.scopes = scopes;
+        Ok(())
+    }
+
+    // Drops everything between base and the value on top
+    fn finish(&mut self, base:usize){
+        while self.height > base + 1{
+            self.store(1);
+        }
+    }
+
+    fn block(&mut self, lines:&[Line]) -> Result<(), String>{
+        self.flush();
+        self.scopes.push(vec!());
+        self.indent += 1;
+        for line in lines{
+            self.statement(line)?;
+        }
+        for _ in self.scopes.pop().unwrap(){
+            self.emit(DROP, -1);
+        }
+        self.flush();
+        self.indent -= 1;
+        Ok(())
+    }
+
+    fn statement(&mut self, (line, statement):&Line) -> Result<(), String>{
+        if !matches!(statement, Statement::Function(..)) {self.comment(*line)}
+        match statement{
+            Statement::Var(name, value) => {
+                self.expression(value)?;
+                let position = self.height - 1;
+                self.scopes.last_mut().unwrap().push((name.clone(), position));
+            },
+            Statement::Assign(name, value, position) => {
+                let variable = self.lookup(name, *position)?;
+                self.expression(value)?;
+                self.store(self.height - 1 - variable);
+            },
+            Statement::Put(value) => {
+                self.expression(value)?;
+                self.emit(".", -1);
+            },
+            Statement::Expression(value) => {
+                self.expression(value)?;
+                self.emit(DROP, -1);
+            },
+            Statement::If(condition, then, otherwise) => {
+                let (skip, end) = (self.label("else"), self.label("end"));
+                self.test(condition)?;
+                self.emit(NOT, 0);
+                self.jump(&skip, true);
+                self.block(then)?;
+                if !otherwise.is_empty() {self.jump(&end, false)}
+                self.place(&skip);
+                self.block(otherwise)?;
+                self.place(&end);
+            },
+            Statement::While(condition, body) => {
+                let (top, end) = (self.label("top"), self.label("end"));
+                self.place(&top);
+                self.test(condition)?;
+                self.emit(NOT, 0);
+                self.jump(&end, true);
+                self.block(body)?;
+                self.jump(&top, false);
+                self.place(&end);
+            },
+            Statement::Return(value, position) => {
+                let Some(frame) = self.frames.last() else {
+                    return Err(format!("'return' outside of a function at {}", position));
+                };
+                let (base, end, height) = (frame.base, frame.end.clone(), self.height);
+                match value{
+                    Some(value) => self.expression(value)?,
+                    None => self.emit("!!^", 1),
+                }
+                self.finish(base);
+                self.jump(&end, false);
+                // what follows is only reached some other way, with the stack as it was before
+                self.height = height;
+            },
+            Statement::Function(name, parameters, body) => {
+                let globals = self.scopes[0].clone();
+                self.functions.insert(name.clone(), Function{parameters: parameters.clone(), body: body.clone(), globals});
+            },
+        }
+        Ok(())
+    }
+}
+
+// Stack Of Stacks source for program, with its lines in comments
+pub fn compile(program:&[u8]) -> Result<String, String>{
+    if let Some(index) = program.iter().position(|b| !b.is_ascii()){
+        let line = program[..index].iter().filter(|b| **b == b'\n').count() + 1;
+        return Err(format!("Non ASCII character on line {}", line));
+    }
+    let mut parser = Parser{tokens: tokenise(program)?, index: 0};
+    let mut lines = vec!();
+    while *parser.peek() != Token::End{
+        lines.push(parser.statement(true)?);
+    }
+
+    let source = std::str::from_utf8(program).unwrap();
+    let mut compiler = Compiler{
+        lines: source.lines().collect(),
+        out: "#!./target/release/stackofstacks\n# compiled from the structured language\n\n".to_owned(),
+        code: String::new(),
+        indent: 0,
+        commented: 0,
+        height: 0,
+        scopes: vec![vec!()],
+        functions: HashMap::new(),
+        frames: vec!(),
+        labels: 0,
+    };
+    let _ = writeln!(compiler.out, "!$!$\t# -1 at the bottom of both stacks");
+    for line in &lines{
+        compiler.statement(line)?;
+    }
+    compiler.flush();
+    compiler.out.push_str("!@\n");
+    Ok(compiler.out)
+}
@@ -0,0 +1,321 @@
+// Structured execution traces
+//
+// A trace holds one record per executed instruction with the machine state as it was right before
+// executing it, plus the I/O the instruction did. Two formats are supported:
+//  - jsonl:  one JSON object per line, for humans and scripts
+//  - bin:    a compact varint encoding for long runs (typically 5-8 bytes per step)
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Cursor, Read, Write};
+
+use crate::TOKENS;
+use crate::json::{self, Value};
+use crate::vm::{Effect, Observer, Vm};
+
+const MAGIC:&[u8;8] = b"SOSTRACE";
+const VERSION:u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format{
+    Jsonl,
+    Binary,
+}
+
+impl Format{
+    pub fn from_name(name:&str) -> Option<Format>{
+        match name{
+            "jsonl" => Some(Format::Jsonl),
+            "bin" => Some(Format::Binary),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event{
+    Read(i64),
+    Write(u8),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record{
+    pub step:u64,
+    pub cp:usize,
+    pub opcode:u8,
+    pub stack:usize, // active stack
+    pub heights:[usize;2],
+    pub tops:[Option<i64>;2],
+    pub event:Option<Event>,
+}
+
+impl Record{
+    pub fn capture(vm:&Vm) -> Record{
+        Record{
+            step: vm.steps,
+            cp: vm.cp,
+            opcode: vm.opcode(),
+            stack: vm.active,
+            heights: [vm.stacks[0].len(), vm.stacks[1].len()],
+            tops: [vm.stacks[0].last().copied(), vm.stacks[1].last().copied()],
+            event: None,
+        }
+    }
+
+    pub fn to_json(&self) -> String{
+        let top = |t:Option<i64>| t.map_or("null".to_owned(), |v| v.to_string());
+        let mut line = format!(
+            "{{\"step\":{},\"cp\":{},\"op\":\"{}\",\"stack\":{},\"heights\":[{},{}],\"tops\":[{},{}]",
+            self.step, self.cp, self.opcode as char, self.stack, self.heights[0], self.heights[1], top(self.tops[0]), top(self.tops[1])
+        );
+        match self.event{
+            Some(Event::Read(v)) => line.push_str(&format!(",\"read\":{}", v)),
+            Some(Event::Write(b)) => line.push_str(&format!(",\"write\":{}", b)),
+            None => (),
+        }
+        line.push('}');
+        line
+    }
+
+    pub fn from_json(value:&Value) -> Result<Record, String>{
+        let int = |key:&str| value.get(key).and_then(Value::as_i64).ok_or(format!("Missing or invalid '{}'", key));
+        let pair = |key:&str| -> Result<[Option<i64>;2], String>{
+            match value.get(key).and_then(Value::as_array).map(|a| a.as_slice()){
+                Some([a, b]) => Ok([a.as_i64(), b.as_i64()]),
+                _ => Err(format!("Missing or invalid '{}'", key)),
+            }
+        };
+
+        let opcode = match value.get("op").and_then(Value::as_str).map(str::as_bytes){
+            Some([op]) if TOKENS.contains(op) => *op,
+            _ => return Err("Missing or invalid 'op'".to_owned()),
+        };
+        let heights = pair("heights")?;
+        let event = match (value.get("read"), value.get("write")){
+            (Some(v), _) => Some(Event::Read(v.as_i64().ok_or("Invalid 'read'")?)),
+            (None, Some(v)) => Some(Event::Write(v.as_i64().ok_or("Invalid 'write'")? as u8)),
+            (None, None) => None,
+        };
+
+        Ok(Record{
+            step: int("step")? as u64,
+            cp: int("cp")? as usize,
+            opcode,
+            stack: int("stack")? as usize & 1,
+            heights: [heights[0].ok_or("Invalid 'heights'")? as usize, heights[1].ok_or("Invalid 'heights'")? as usize],
+            tops: pair("tops")?,
+            event,
+        })
+    }
+}
+
+
+fn write_varint(out:&mut Vec<u8>, mut value:u64){
+    while value >= 0x80{
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn zigzag(value:i64) -> u64{
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn unzigzag(value:u64) -> i64{
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+
+pub struct Tracer{
+    out:BufWriter<File>,
+    format:Format,
+    pending:Option<Record>,
+    last_step:u64,
+    buffer:Vec<u8>,
+    error:Option<io::Error>,
+}
+
+impl Tracer{
+    pub fn create(format:Format, filename:&str) -> io::Result<Tracer>{
+        let mut out = BufWriter::new(File::create(filename)?);
+        if format == Format::Binary{
+            out.write_all(MAGIC)?;
+            out.write_all(&[VERSION])?;
+        }
+        Ok(Tracer{out, format, pending: None, last_step: 0, buffer: vec!(), error: None})
+    }
+
+    // The first I/O error hit while tracing, if any
+    pub fn error(&self) -> Option<&io::Error>{
+        self.error.as_ref()
+    }
+
+    fn encode(&mut self, record:&Record){
+        self.buffer.clear();
+        match self.format{
+            Format::Jsonl => {
+                self.buffer.extend(record.to_json().as_bytes());
+                self.buffer.push(b'\n');
+            },
+            Format::Binary => {
+                let opcode = TOKENS.iter().position(|t| *t == record.opcode).unwrap() as u8;
+                let event = match record.event{
+                    None => 0,
+                    Some(Event::Read(_)) => 1,
+                    Some(Event::Write(_)) => 2,
+                };
+                self.buffer.push(opcode | ((record.stack as u8 & 1) << 4) | (event << 5));
+                write_varint(&mut self.buffer, record.step.wrapping_sub(self.last_step));
+                write_varint(&mut self.buffer, record.cp as u64);
+                for i in 0..2{
+                    write_varint(&mut self.buffer, record.heights[i] as u64);
+                }
+                for top in record.tops.iter().flatten(){
+                    write_varint(&mut self.buffer, zigzag(*top));
+                }
+                match record.event{
+                    Some(Event::Read(v)) => write_varint(&mut self.buffer, zigzag(v)),
+                    Some(Event::Write(b)) => self.buffer.push(b),
+                    None => (),
+                }
+            },
+        }
+        self.last_step = record.step;
+    }
+
+    pub fn write(&mut self, record:&Record){
+        if self.error.is_some() {return}
+        self.encode(record);
+        if let Err(e) = self.out.write_all(&self.buffer){
+            self.error = Some(e);
+        }
+    }
+}
+
+impl Observer for Tracer{
+    fn before(&mut self, vm:&Vm){
+        self.pending = Some(Record::capture(vm));
+    }
+
+    fn after(&mut self, _vm:&Vm, _cp:usize, effect:&Effect){
+        let Some(mut record) = self.pending.take() else {return};
+        record.event = match effect{
+            Effect::Read(v) => Some(Event::Read(*v)),
+            Effect::Write(b) => Some(Event::Write(*b)),
+            _ => None,
+        };
+        self.write(&record);
+    }
+
+    fn finish(&mut self, _vm:&Vm){
+        // an instruction that faulted still gets its record, without the I/O it may have done
+        if let Some(record) = self.pending.take(){
+            self.write(&record);
+        }
+        if let Err(e) = self.out.flush(){
+            self.error.get_or_insert(e);
+        }
+    }
+}
+
+
+pub struct Reader{
+    input:Box<dyn BufRead>,
+    format:Format,
+    last_step:u64,
+    line:String,
+}
+
+// Opens a trace of either format, telling them apart by the magic of the binary format
+pub fn read(mut input:impl Read + 'static) -> io::Result<Reader>{
+    let mut head = vec!();
+    (&mut input).take(MAGIC.len() as u64 + 1).read_to_end(&mut head)?;
+
+    if head.len() == MAGIC.len() + 1 && head.starts_with(MAGIC){
+        if head[MAGIC.len()] != VERSION{
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Unsupported trace version {}", head[MAGIC.len()])));
+        }
+        Ok(Reader{input: Box::new(BufReader::new(input)), format: Format::Binary, last_step: 0, line: String::new()})
+    }else{
+        Ok(Reader{input: Box::new(BufReader::new(Cursor::new(head).chain(input))), format: Format::Jsonl, last_step: 0, line: String::new()})
+    }
+}
+
+impl Reader{
+    pub fn format(&self) -> Format{
+        self.format
+    }
+
+    fn byte(&mut self) -> io::Result<u8>{
+        let mut b = [0];
+        self.input.read_exact(&mut b)?;
+        Ok(b[0])
+    }
+
+    fn varint(&mut self) -> io::Result<u64>{
+        let mut value = 0u64;
+        let mut shift = 0;
+        loop{
+            let b = self.byte()?;
+            if shift > 63{
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "Varint too long"));
+            }
+            value |= u64::from(b & 0x7F) << shift;
+            if b & 0x80 == 0 {return Ok(value)}
+            shift += 7;
+        }
+    }
+
+    fn binary_record(&mut self, flags:u8) -> io::Result<Record>{
+        let step = self.last_step.wrapping_add(self.varint()?);
+        let cp = self.varint()? as usize;
+        let heights = [self.varint()? as usize, self.varint()? as usize];
+        let mut tops = [None, None];
+        for i in 0..2{
+            if heights[i] > 0{
+                tops[i] = Some(unzigzag(self.varint()?));
+            }
+        }
+        let event = match flags >> 5{
+            0 => None,
+            1 => Some(Event::Read(unzigzag(self.varint()?))),
+            2 => Some(Event::Write(self.byte()?)),
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid event kind")),
+        };
+        self.last_step = step;
+
+        Ok(Record{step, cp, opcode: TOKENS[usize::from(flags & 0xF)], stack: usize::from(flags >> 4 & 1), heights, tops, event})
+    }
+}
+
+impl Iterator for Reader{
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<io::Result<Record>>{
+        match self.format{
+            Format::Binary => {
+                let mut flags = [0];
+                match self.input.read(&mut flags){
+                    Ok(0) => None,
+                    Ok(_) => Some(self.binary_record(flags[0])),
+                    Err(e) => Some(Err(e)),
+                }
+            },
+            Format::Jsonl => {
+                loop{
+                    self.line.clear();
+                    match self.input.read_line(&mut self.line){
+                        Ok(0) => return None,
+                        Ok(_) => (),
+                        Err(e) => return Some(Err(e)),
+                    }
+                    if self.line.trim().is_empty() {continue}
+
+                    return Some(json::parse(&self.line)
+                        .and_then(|value| Record::from_json(&value))
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)));
+                }
+            },
+        }
+    }
+}
@@ -0,0 +1,316 @@
+use std::fmt;
+use std::num::Wrapping;
+use std::io::{Read, Write, stdin, stdout};
+use std::collections::HashMap;
+
+
+// Where '?' gets its bytes from and '.' sends them to
+pub trait Io{
+    fn read(&mut self) -> i64; // -1 on EOF
+    fn write(&mut self, byte:u8);
+}
+
+pub struct StdIo;
+
+impl Io for StdIo{
+    fn read(&mut self) -> i64{
+        let mut buffer:[u8;1] = [0];
+        match stdin().lock().read_exact(&mut buffer){
+            Ok(_) => buffer[0] as i64,
+            Err(_) => -1,
+        }
+    }
+
+    fn write(&mut self, byte:u8){
+        let _ = stdout().lock().write(&[byte]);
+    }
+}
+
+// Reads from and writes to memory, for running programs from within other tools
+#[derive(Debug, Clone, Default)]
+pub struct BufferIo{
+    pub input:Vec<u8>,
+    pub position:usize, // in input
+    pub output:Vec<u8>,
+}
+
+impl BufferIo{
+    pub fn new(input:Vec<u8>) -> BufferIo{
+        BufferIo{input, position: 0, output: vec!()}
+    }
+}
+
+impl Io for BufferIo{
+    fn read(&mut self) -> i64{
+        match self.input.get(self.position){
+            Some(b) => {
+                self.position += 1;
+                *b as i64
+            },
+            None => -1,
+        }
+    }
+
+    fn write(&mut self, byte:u8){
+        self.output.push(byte);
+    }
+}
+
+
+// What executing one instruction did, for whoever is watching
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Effect{
+    None,
+    Jump(i64),  // '@' with its (non -1) offset, 0 means not taken
+    Read(i64),  // '?' with the value pushed (-1 on EOF)
+    Write(u8),  // '.' with the byte written
+    Halt,       // '@' with -1
+}
+
+// Why run returned without a fault
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop{
+    Halted,
+    StepLimit,
+    Interrupted, // an observer asked to stop
+}
+
+// Strict mode violations, these end the program
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault{
+    StackDepleted,
+    OutOfCode(usize),
+}
+
+impl fmt::Display for Fault{
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result{
+        match self{
+            Fault::StackDepleted => write!(f, "Strict mode violation: Stack depleted"),
+            Fault::OutOfCode(index) => write!(f, "Strict mode violation: Outside of code memmory (offset: 0x{:#018X})", index),
+        }
+    }
+}
+
+
+pub trait Oos{
+    fn oos(&self, strict:bool)->Result<i64, Fault>;
+}
+
+impl Oos for Option<i64>{
+    fn oos(&self, strict:bool) -> Result<i64, Fault>{
+        match self{
+            Some(v) => Ok(*v),
+            None => {
+                if strict{
+                    Err(Fault::StackDepleted)
+                }else{
+                    Ok(-1)
+                }
+            }
+        }
+    }
+}
+
+
+pub struct Vm{
+    pub code:Vec<u8>,
+    pub cp:usize,
+    pub stacks:[Vec<i64>;2],
+    pub active:usize, // index of the stack instructions operate on
+    pub ram:HashMap<i64, i64>,
+    pub strict:bool,
+    pub steps:u64,
+    pub max_steps:Option<u64>, // run stops once steps reaches this
+}
+
+impl Vm{
+    pub fn new(code:Vec<u8>, strict:bool) -> Vm{
+        Vm{
+            code,
+            cp: 0,
+            stacks: [vec!(), vec!()],
+            active: 0,
+            ram: HashMap::new(),
+            strict,
+            steps: 0,
+            max_steps: None,
+        }
+    }
+
+    pub fn opcode(&self) -> u8{
+        self.code[self.cp]
+    }
+
+    // Execute the instruction at CP and move CP to the next one
+    pub fn step(&mut self, io:&mut dyn Io) -> Result<Effect, Fault>{
+        let strict = self.strict;
+        let mut effect = Effect::None;
+
+        let [first, second] = &mut self.stacks;
+        let (stack, other) = if self.active == 0 {(first, second)} else {(second, first)};
+
+        self.steps += 1;
+
+        match self.code[self.cp]{
+            b'$' => {//Switch stack
+                self.active = !self.active&1;
+            }
+            b'~' => {//Xchange stack heads
+                let a = stack.pop().oos(strict)?;
+                let b = other.pop().oos(strict)?;
+
+                other.push(a);
+                stack.push(b);
+            }
+            b'=' => {// Duplicate top value
+                let a = stack.pop().oos(strict)?;
+                stack.push(a);
+                stack.push(a);
+            }
+            b'+' => {
+                let b = stack.pop().oos(strict)?;
+                let a = stack.pop().oos(strict)?;
+                stack.push( (Wrapping(a)+Wrapping(b)).0 );
+            }
+            b'-' => {
+                let b = stack.pop().oos(strict)?;
+                let a = stack.pop().oos(strict)?;
+                stack.push( (Wrapping(a)-Wrapping(b)).0 );
+            }
+            b'*' => { // MULTIPLY stack:[a,b] -> [low, high]
+                let b = stack.pop().oos(strict)?;
+                let a = stack.pop().oos(strict)?;
+                let x = i128::from(a)*i128::from(b);
+                stack.push(x as i64);
+                // stack.push((x >> 64) as i64); //just lose the eccess
+            }
+            b'/' => {
+                let b = stack.pop().oos(strict)?;
+                let a = stack.pop().oos(strict)?;
+                if b != 0{
+                    stack.push(a.wrapping_div(b));
+                }else{
+                    stack.push(0); //div by 0 is 0 by design (can replace test)
+                }
+            }
+            b'|' => {
+                let b = stack.pop().oos(strict)?;
+                let a = stack.pop().oos(strict)?;
+                stack.push( a | b );
+            }
+            b'&' => {
+                let b = stack.pop().oos(strict)?;
+                let a = stack.pop().oos(strict)?;
+                stack.push( a & b );
+            }
+            b'^' => {
+                let b = stack.pop().oos(strict)?;
+                let a = stack.pop().oos(strict)?;
+                stack.push( a ^ b );
+            }
+            b'!' => {
+                stack.push( -1 );
+            }
+            b'1' => {
+                let a = stack.pop().oos(strict)?;
+                stack.push((a << 1) | 0b1);
+            }
+            b'0' => {
+                let a = stack.pop().oos(strict)?;
+                stack.push(a << 1);
+            }
+            b'@' => {
+                let a = stack.pop().oos(strict)?;
+                if a == -1 {return Ok(Effect::Halt)} // This would lead to perpetual spinlock basically haling ,execution, so better make it an exit strategy
+                self.cp = (Wrapping(self.cp)+Wrapping(a as usize)).0;
+                effect = Effect::Jump(a);
+            }
+            b'?' => {
+                let value = io.read();
+                stack.push(value);
+                effect = Effect::Read(value);
+            }
+            b'.' => {
+                let ch = stack.pop().oos(strict)?;
+                io.write(ch as u8);
+                effect = Effect::Write(ch as u8);
+            }
+            _ => {
+                panic!("INTERPRETER's FAULT: Invalid token!")
+            }
+        }
+
+        self.cp = (Wrapping(self.cp)+Wrapping(1usize)).0;
+        if self.cp >= self.code.len() {
+            if strict{
+                return Err(Fault::OutOfCode(self.cp));
+            }
+            self.cp = 0;
+        }
+
+        Ok(effect)
+    }
+}
+
+
+// Hooks into a running machine, used for debugging, tracing and the like
+pub trait Observer{
+    fn before(&mut self, _vm:&Vm){}
+    fn after(&mut self, _vm:&Vm, _cp:usize, _effect:&Effect){} // cp is where the executed instruction was
+    fn finish(&mut self, _vm:&Vm){}
+    fn stop(&self) -> bool {false} // checked after every instruction
+}
+
+// The classic --debug output on STDERR
+pub struct Debugger;
+
+impl Debugger{
+    fn stacks(vm:&Vm){
+        eprintln!("{:?}", &vm.stacks[vm.active]);
+        eprintln!("{:?}", &vm.stacks[!vm.active&1]);
+    }
+}
+
+impl Observer for Debugger{
+    fn before(&mut self, vm:&Vm){
+        Debugger::stacks(vm);
+        eprintln!("{:#018X}: {}", vm.cp, vm.opcode() as char);
+    }
+
+    fn finish(&mut self, vm:&Vm){
+        Debugger::stacks(vm);
+    }
+}
+
+
+pub fn run(vm:&mut Vm, io:&mut dyn Io, observers:&mut [&mut dyn Observer]) -> Result<Stop, Fault>{
+
+    if vm.code.is_empty(){return Ok(Stop::Halted)}
+
+    let result = loop{
+
+        if vm.max_steps.is_some_and(|max| vm.steps >= max) {break Ok(Stop::StepLimit)}
+
+        for observer in observers.iter_mut(){
+            observer.before(vm);
+        }
+
+        let cp = vm.cp;
+        match vm.step(io){
+            Ok(effect) => {
+                for observer in observers.iter_mut(){
+                    observer.after(vm, cp, &effect);
+                }
+                if effect == Effect::Halt {break Ok(Stop::Halted)}
+                if observers.iter().any(|o| o.stop()) {break Ok(Stop::Interrupted)}
+            },
+            Err(fault) => break Err(fault),
+        }
+    };
+
+    for observer in observers.iter_mut(){
+        observer.finish(vm);
+    }
+
+    result
+}
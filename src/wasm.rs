@@ -0,0 +1,550 @@
+// WebAssembly backend for the pure script
+//
+// Lowers the code to a module that does what vm::run does, as binary WASM and as WAT text for
+// reading. Both stacks live in linear memory, interleaved so they can both grow: item i of stack s
+// is the i64 at ((i*2)+s)*8. Input and output go through the imported functions sos.read_byte
+// (returning -1 on EOF) and sos.write_byte. Every offset gets a block and '@' sets CP and goes back
+// to a loop around them that dispatches with br_table. The module exports:
+//  - run(max_steps:i64) -> i32: runs the program (-1 is no limit) and gives what stopped it, one of
+//    the STATUS_ constants below
+//  - depth(stack:i32) -> i32 and item(stack:i32, index:i32) -> i64, index 0 being the bottom
+//  - steps() -> i64, and cp() -> i64 for the offset that left code memory
+//  - memory
+
+use std::fmt::Write;
+
+pub const STATUS_HALTED:i32 = 0;
+pub const STATUS_STEP_LIMIT:i32 = 1;
+pub const STATUS_STACK_DEPLETED:i32 = 2;
+pub const STATUS_OUT_OF_CODE:i32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Type{
+    I32,
+    I64,
+}
+
+impl Type{
+    fn name(self) -> &'static str{
+        match self {Type::I32 => "i32", Type::I64 => "i64"}
+    }
+
+    fn code(self) -> u8{
+        match self {Type::I32 => 0x7F, Type::I64 => 0x7E}
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Instr{
+    Comment(String), // only in the text
+    Unreachable,
+    Block,
+    Loop,
+    If,
+    Else,
+    End,
+    Br(u32),
+    BrTable(Vec<u32>, u32),
+    Return,
+    Call(u32),
+    Select,
+    LocalGet(u32),
+    LocalSet(u32),
+    LocalTee(u32),
+    GlobalGet(u32),
+    GlobalSet(u32),
+    I64Load,
+    I64Store,
+    MemorySize,
+    MemoryGrow,
+    I32Const(i32),
+    I64Const(i64),
+    Op(&'static str, u8), // anything without immediates
+}
+
+use Instr::*;
+
+const I32_EQZ:Instr = Op("i32.eqz", 0x45);
+const I32_EQ:Instr = Op("i32.eq", 0x46);
+const I32_GT_U:Instr = Op("i32.gt_u", 0x4B);
+const I64_EQZ:Instr = Op("i64.eqz", 0x50);
+const I64_EQ:Instr = Op("i64.eq", 0x51);
+const I64_GE_U:Instr = Op("i64.ge_u", 0x5A);
+const I32_ADD:Instr = Op("i32.add", 0x6A);
+const I32_SUB:Instr = Op("i32.sub", 0x6B);
+const I32_AND:Instr = Op("i32.and", 0x71);
+const I32_OR:Instr = Op("i32.or", 0x72);
+const I32_XOR:Instr = Op("i32.xor", 0x73);
+const I32_SHL:Instr = Op("i32.shl", 0x74);
+const I64_ADD:Instr = Op("i64.add", 0x7C);
+const I64_SUB:Instr = Op("i64.sub", 0x7D);
+const I64_MUL:Instr = Op("i64.mul", 0x7E);
+const I64_DIV_S:Instr = Op("i64.div_s", 0x7F);
+const I64_AND:Instr = Op("i64.and", 0x83);
+const I64_OR:Instr = Op("i64.or", 0x84);
+const I64_XOR:Instr = Op("i64.xor", 0x85);
+const I64_SHL:Instr = Op("i64.shl", 0x86);
+const I32_WRAP_I64:Instr = Op("i32.wrap_i64", 0xA7);
+const I64_EXTEND_I32_S:Instr = Op("i64.extend_i32_s", 0xAC);
+
+impl Instr{
+    fn text(&self) -> String{
+        match self{
+            Comment(text) => format!(";; {}", text),
+            Unreachable => "unreachable".to_owned(),
+            Block => "block".to_owned(),
+            Loop => "loop".to_owned(),
+            If => "if".to_owned(),
+            Else => "else".to_owned(),
+            End => "end".to_owned(),
+            Br(depth) => format!("br {}", depth),
+            BrTable(depths, default) => {
+                let mut text = "br_table".to_owned();
+                for depth in depths.iter().chain([default]){
+                    let _ = write!(text, " {}", depth);
+                }
+                text
+            },
+            Return => "return".to_owned(),
+            Call(index) => format!("call ${}", FUNCTIONS[*index as usize]),
+            Select => "select".to_owned(),
+            LocalGet(index) => format!("local.get {}", index),
+            LocalSet(index) => format!("local.set {}", index),
+            LocalTee(index) => format!("local.tee {}", index),
+            GlobalGet(index) => format!("global.get ${}", GLOBAL_NAMES[*index as usize]),
+            GlobalSet(index) => format!("global.set ${}", GLOBAL_NAMES[*index as usize]),
+            I64Load => "i64.load".to_owned(),
+            I64Store => "i64.store".to_owned(),
+            MemorySize => "memory.size".to_owned(),
+            MemoryGrow => "memory.grow".to_owned(),
+            I32Const(value) => format!("i32.const {}", value),
+            I64Const(value) => format!("i64.const {}", value),
+            Op(name, _) => (*name).to_owned(),
+        }
+    }
+
+    fn encode(&self, out:&mut Vec<u8>){
+        match self{
+            Comment(_) => {},
+            Unreachable => out.push(0x00),
+            Block => out.extend([0x02, 0x40]),
+            Loop => out.extend([0x03, 0x40]),
+            If => out.extend([0x04, 0x40]),
+            Else => out.push(0x05),
+            End => out.push(0x0B),
+            Br(depth) => {
+                out.push(0x0C);
+                unsigned(out, u64::from(*depth));
+            },
+            BrTable(depths, default) => {
+                out.push(0x0E);
+                unsigned(out, depths.len() as u64);
+                for depth in depths.iter().chain([default]){
+                    unsigned(out, u64::from(*depth));
+                }
+            },
+            Return => out.push(0x0F),
+            Call(index) => {
+                out.push(0x10);
+                unsigned(out, u64::from(*index));
+            },
+            Select => out.push(0x1B),
+            LocalGet(index) | LocalSet(index) | LocalTee(index) | GlobalGet(index) | GlobalSet(index) => {
+                out.push(match self {LocalGet(_) => 0x20, LocalSet(_) => 0x21, LocalTee(_) => 0x22, GlobalGet(_) => 0x23, _ => 0x24});
+                unsigned(out, u64::from(*index));
+            },
+            I64Load => out.extend([0x29, 3, 0]),  // 8 byte aligned, offset 0
+            I64Store => out.extend([0x37, 3, 0]),
+            MemorySize => out.extend([0x3F, 0]),
+            MemoryGrow => out.extend([0x40, 0]),
+            I32Const(value) => {
+                out.push(0x41);
+                signed(out, i64::from(*value));
+            },
+            I64Const(value) => {
+                out.push(0x42);
+                signed(out, *value);
+            },
+            Op(_, code) => out.push(*code),
+        }
+    }
+}
+
+fn unsigned(out:&mut Vec<u8>, mut value:u64){
+    loop{
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0{
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn signed(out:&mut Vec<u8>, mut value:i64){
+    loop{
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0){
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+// Function indices, the imported ones come first, and names in the text
+const FUNCTIONS:[&str; 10] = ["read_byte", "write_byte", "push", "pop", "tick", "run", "depth", "item", "steps", "cp"];
+const READ_BYTE:u32 = 0;
+const WRITE_BYTE:u32 = 1;
+const PUSH:u32 = 2;
+const POP:u32 = 3;
+const TICK:u32 = 4;
+
+// Globals
+const ACTIVE:u32 = 0;
+const LEN0:u32 = 1;
+const LEN1:u32 = 2;
+const STEPS:u32 = 3;
+const MAX_STEPS:u32 = 4;
+const STATUS:u32 = 5;
+const CP:u32 = 6;
+const GLOBALS:[Type; 7] = [Type::I32, Type::I32, Type::I32, Type::I64, Type::I64, Type::I32, Type::I64];
+const GLOBAL_NAMES:[&str; 7] = ["active", "len0", "len1", "steps", "max_steps", "status", "cp"];
+
+// Locals of run
+const RUN_CP:u32 = 1;
+const RUN_A:u32 = 2;
+const RUN_B:u32 = 3;
+
+struct Function{
+    export:Option<&'static str>,
+    params:Vec<Type>,
+    results:Vec<Type>,
+    locals:Vec<Type>,
+    body:Vec<Instr>,
+}
+
+// Address of item index (on the stack) of stack s, both i32
+fn address(s:Instr) -> Vec<Instr>{
+    vec!(I32Const(1), I32_SHL, s, I32_OR, I32Const(3), I32_SHL)
+}
+
+// Length of stack s
+fn length(s:Instr) -> Vec<Instr>{
+    vec!(GlobalGet(LEN1), GlobalGet(LEN0), s, Select)
+}
+
+// push(s:i32, value:i64)
+fn push_function() -> Function{
+    let mut body = length(LocalGet(0));
+    body.extend(address(LocalGet(0)));
+    body.extend([
+        LocalSet(2),
+        // one page more is always enough for 8 bytes
+        LocalGet(2), I32Const(8), I32_ADD, MemorySize, I32Const(16), I32_SHL, I32_GT_U, If,
+        I32Const(1), MemoryGrow, I32Const(-1), I32_EQ, If, Unreachable, End,
+        End,
+        LocalGet(2), LocalGet(1), I64Store,
+        LocalGet(0), If,
+        GlobalGet(LEN1), I32Const(1), I32_ADD, GlobalSet(LEN1),
+        Else,
+        GlobalGet(LEN0), I32Const(1), I32_ADD, GlobalSet(LEN0),
+        End,
+    ]);
+    Function{export: None, params: vec!(Type::I32, Type::I64), results: vec!(), locals: vec!(Type::I32), body}
+}
+
+// pop(s:i32) -> i64, setting the status when strict and there is nothing to pop
+fn pop_function(strict:bool) -> Function{
+    let mut body = length(LocalGet(0));
+    body.extend([LocalTee(1), I32_EQZ, If]);
+    if strict{
+        body.extend([I32Const(STATUS_STACK_DEPLETED), GlobalSet(STATUS)]);
+    }
+    body.extend([
+        I64Const(-1), Return,
+        End,
+        LocalGet(1), I32Const(1), I32_SUB, LocalSet(1),
+        LocalGet(0), If, LocalGet(1), GlobalSet(LEN1), Else, LocalGet(1), GlobalSet(LEN0), End,
+        LocalGet(1),
+    ]);
+    body.extend(address(LocalGet(0)));
+    body.push(I64Load);
+    Function{export: None, params: vec!(Type::I32), results: vec!(Type::I64), locals: vec!(Type::I32), body}
+}
+
+// tick() -> i32, counts a step or gives 1 when there are no steps left
+fn tick_function() -> Function{
+    let body = vec!(
+        GlobalGet(STEPS), GlobalGet(MAX_STEPS), I64_GE_U, If, I32Const(1), Return, End,
+        GlobalGet(STEPS), I64Const(1), I64_ADD, GlobalSet(STEPS),
+        I32Const(0),
+    );
+    Function{export: None, params: vec!(), results: vec!(Type::I32), locals: vec!(), body}
+}
+
+// run(max_steps:i64) -> i32
+fn run_function(code:&[u8], strict:bool) -> Function{
+    let mut body = vec!(LocalGet(0), GlobalSet(MAX_STEPS));
+    let len = code.len();
+    if len == 0{
+        body.push(I32Const(STATUS_HALTED));
+        return Function{export: Some("run"), params: vec!(Type::I64), results: vec!(Type::I32), locals: vec!(), body};
+    }
+
+    // where CP went past the end of code memory
+    body.extend([Loop, LocalGet(RUN_CP), I64Const(len as i64), I64_GE_U, If]);
+    if strict{
+        body.extend([LocalGet(RUN_CP), GlobalSet(CP), I32Const(STATUS_OUT_OF_CODE), Return]);
+    }else{
+        body.extend([I64Const(0), LocalSet(RUN_CP)]);
+    }
+    body.push(End);
+
+    body.extend(std::iter::repeat_n(Block, len));
+    body.extend([LocalGet(RUN_CP), I32_WRAP_I64, BrTable((0..len as u32).collect(), 0)]);
+
+    for (offset, op) in code.iter().enumerate(){
+        // blocks of the later offsets are still open, the loop is right outside them
+        let top = (len - 1 - offset) as u32;
+        body.push(End);
+        body.push(Comment(format!("{:#X}: {}", offset, *op as char)));
+        body.extend([Call(TICK), If, I32Const(STATUS_STEP_LIMIT), Return, End]);
+
+        let pop = |stack:Vec<Instr>, local:u32| {
+            let mut pop = stack;
+            pop.extend([Call(POP), LocalSet(local)]);
+            if strict{
+                pop.extend([GlobalGet(STATUS), If, I32Const(STATUS_STACK_DEPLETED), Return, End]);
+            }
+            pop
+        };
+        let active = || GlobalGet(ACTIVE);
+        let other = || vec!(active(), I32Const(1), I32_XOR);
+        let binary = |body:&mut Vec<Instr>, ops:&[Instr]| {
+            body.extend(pop(vec!(active()), RUN_B));
+            body.extend(pop(vec!(active()), RUN_A));
+            body.extend([active(), LocalGet(RUN_A), LocalGet(RUN_B)]);
+            body.extend(ops.iter().cloned());
+            body.push(Call(PUSH));
+        };
+
+        match op{
+            b'!' => body.extend([active(), I64Const(-1), Call(PUSH)]),
+            b'^' => binary(&mut body, &[I64_XOR]),
+            b'|' => binary(&mut body, &[I64_OR]),
+            b'&' => binary(&mut body, &[I64_AND]),
+            b'+' => binary(&mut body, &[I64_ADD]),
+            b'-' => binary(&mut body, &[I64_SUB]),
+            b'*' => binary(&mut body, &[I64_MUL]),
+            b'/' => {
+                // a / b with b replaced by 1 where it is 0 or -1, then picking 0 or -a for those
+                body.extend(pop(vec!(active()), RUN_B));
+                body.extend(pop(vec!(active()), RUN_A));
+                body.extend([
+                    active(),
+                    I64Const(0),
+                    I64Const(0), LocalGet(RUN_A), I64_SUB,
+                    LocalGet(RUN_A), I64Const(1), LocalGet(RUN_B), LocalGet(RUN_B), I64_EQZ, LocalGet(RUN_B), I64Const(-1), I64_EQ, I32_OR, Select, I64_DIV_S,
+                    LocalGet(RUN_B), I64Const(-1), I64_EQ, Select,
+                    LocalGet(RUN_B), I64_EQZ, Select,
+                    Call(PUSH),
+                ]);
+            },
+            b'$' => body.extend([active(), I32Const(1), I32_XOR, GlobalSet(ACTIVE)]),
+            b'~' => {
+                body.extend(pop(vec!(active()), RUN_A));
+                body.extend(pop(other(), RUN_B));
+                body.extend(other());
+                body.extend([LocalGet(RUN_A), Call(PUSH), active(), LocalGet(RUN_B), Call(PUSH)]);
+            },
+            b'=' => {
+                body.extend(pop(vec!(active()), RUN_A));
+                body.extend([active(), LocalGet(RUN_A), Call(PUSH), active(), LocalGet(RUN_A), Call(PUSH)]);
+            },
+            b'?' => body.extend([active(), Call(READ_BYTE), I64_EXTEND_I32_S, Call(PUSH)]),
+            b'.' => {
+                body.extend(pop(vec!(active()), RUN_A));
+                body.extend([LocalGet(RUN_A), I32_WRAP_I64, I32Const(0xFF), I32_AND, Call(WRITE_BYTE)]);
+            },
+            b'0' | b'1' => {
+                body.extend(pop(vec!(active()), RUN_A));
+                body.extend([active(), LocalGet(RUN_A), I64Const(1), I64_SHL]);
+                if *op == b'1'{
+                    body.extend([I64Const(1), I64_OR]);
+                }
+                body.push(Call(PUSH));
+            },
+            _ => { // '@'
+                body.extend(pop(vec!(active()), RUN_A));
+                body.extend([
+                    LocalGet(RUN_A), I64Const(-1), I64_EQ, If, I32Const(STATUS_HALTED), Return, End,
+                    I64Const(offset as i64 + 1), LocalGet(RUN_A), I64_ADD, LocalSet(RUN_CP),
+                    Br(top),
+                ]);
+                continue;
+            },
+        }
+        if offset+1 == len{
+            // the loop takes care of leaving code memory
+            body.extend([I64Const(len as i64), LocalSet(RUN_CP), Br(top)]);
+        }
+    }
+    body.extend([End, Unreachable]);
+
+    Function{export: Some("run"), params: vec!(Type::I64), results: vec!(Type::I32), locals: vec!(Type::I64; 3), body}
+}
+
+fn functions(code:&[u8], strict:bool) -> Vec<Function>{
+    let getter = |name, global| Function{export: Some(name), params: vec!(), results: vec!(Type::I64), locals: vec!(), body: vec!(GlobalGet(global))};
+    let mut item = vec!(LocalGet(1));
+    item.extend(address(LocalGet(0)));
+    item.push(I64Load);
+
+    vec!(
+        push_function(),
+        pop_function(strict),
+        tick_function(),
+        run_function(code, strict),
+        Function{export: Some("depth"), params: vec!(Type::I32), results: vec!(Type::I32), locals: vec!(), body: length(LocalGet(0))},
+        Function{export: Some("item"), params: vec!(Type::I32, Type::I32), results: vec!(Type::I64), locals: vec!(), body: item},
+        getter("steps", STEPS),
+        getter("cp", CP),
+    )
+}
+
+fn types(types:&[Type]) -> String{
+    types.iter().map(|t| t.name()).collect::<Vec<_>>().join(" ")
+}
+
+// The module in the WebAssembly text format
+pub fn wat(code:&[u8], strict:bool) -> String{
+    let mut out = format!(";; Stack Of Stacks program, {} instructions{}\n(module\n", code.len(), if strict {", strict mode"} else {""});
+    out.push_str("  (import \"sos\" \"read_byte\" (func $read_byte (result i32)))\n");
+    out.push_str("  (import \"sos\" \"write_byte\" (func $write_byte (param i32)))\n");
+    out.push_str("  (memory (export \"memory\") 1)\n");
+    for (global, name) in GLOBALS.iter().zip(GLOBAL_NAMES){
+        let _ = writeln!(out, "  (global ${0} (mut {1}) ({1}.const 0))", name, global.name());
+    }
+
+    for (index, function) in functions(code, strict).iter().enumerate(){
+        let _ = write!(out, "  (func ${}", FUNCTIONS[2 + index]);
+        if let Some(name) = function.export{
+            let _ = write!(out, " (export \"{}\")", name);
+        }
+        for (keyword, list) in [("param", &function.params), ("result", &function.results), ("local", &function.locals)]{
+            if !list.is_empty(){
+                let _ = write!(out, " ({} {})", keyword, types(list));
+            }
+        }
+        out.push('\n');
+        // the blocks of the dispatch would nest as deep as the code is long, only loops and ifs indent
+        let mut open = vec!();
+        for instr in &function.body{
+            let outdent = matches!(instr, Else | End) && open.last() == Some(&true);
+            let _ = writeln!(out, "    {}{}", "  ".repeat(open.iter().filter(|o| **o).count() - usize::from(outdent)), instr.text());
+            match instr{
+                Block => open.push(false),
+                Loop | If => open.push(true),
+                End => {open.pop();},
+                _ => {},
+            }
+        }
+        out.push_str("  )\n");
+    }
+    out.push_str(")\n");
+    out
+}
+
+fn section(out:&mut Vec<u8>, id:u8, contents:Vec<u8>){
+    out.push(id);
+    unsigned(out, contents.len() as u64);
+    out.extend(contents);
+}
+
+fn name(out:&mut Vec<u8>, name:&str){
+    unsigned(out, name.len() as u64);
+    out.extend(name.as_bytes());
+}
+
+fn signature(out:&mut Vec<u8>, params:&[Type], results:&[Type]){
+    out.push(0x60);
+    for list in [params, results]{
+        unsigned(out, list.len() as u64);
+        out.extend(list.iter().map(|t| t.code()));
+    }
+}
+
+// The module in the WebAssembly binary format
+pub fn wasm(code:&[u8], strict:bool) -> Vec<u8>{
+    let functions = functions(code, strict);
+    let mut out = b"\0asm\x01\0\0\0".to_vec();
+
+    // one type per function, imports first
+    let mut contents = vec!();
+    unsigned(&mut contents, 2 + functions.len() as u64);
+    signature(&mut contents, &[], &[Type::I32]);
+    signature(&mut contents, &[Type::I32], &[]);
+    for function in &functions{
+        signature(&mut contents, &function.params, &function.results);
+    }
+    section(&mut out, 1, contents);
+
+    let mut contents = vec!(2);
+    for (index, import) in ["read_byte", "write_byte"].iter().enumerate(){
+        name(&mut contents, "sos");
+        name(&mut contents, import);
+        contents.extend([0x00, index as u8]);
+    }
+    section(&mut out, 2, contents);
+
+    let mut contents = vec!();
+    unsigned(&mut contents, functions.len() as u64);
+    for index in 0..functions.len(){
+        unsigned(&mut contents, 2 + index as u64);
+    }
+    section(&mut out, 3, contents);
+
+    section(&mut out, 5, vec!(1, 0x00, 1)); // one memory of at least a page
+
+    let mut contents = vec!(GLOBALS.len() as u8);
+    for global in GLOBALS{
+        contents.extend([global.code(), 0x01]);
+        if global == Type::I32 {I32Const(0).encode(&mut contents)} else {I64Const(0).encode(&mut contents)}
+        End.encode(&mut contents);
+    }
+    section(&mut out, 6, contents);
+
+    let exports:Vec<(&str, usize)> = functions.iter().enumerate().filter_map(|(index, f)| f.export.map(|name| (name, index))).collect();
+    let mut contents = vec!();
+    unsigned(&mut contents, exports.len() as u64 + 1);
+    for (export, index) in exports{
+        name(&mut contents, export);
+        contents.push(0x00);
+        unsigned(&mut contents, 2 + index as u64);
+    }
+    name(&mut contents, "memory");
+    contents.extend([0x02, 0]);
+    section(&mut out, 7, contents);
+
+    let mut contents = vec!();
+    unsigned(&mut contents, functions.len() as u64);
+    for function in &functions{
+        let mut body = vec!();
+        unsigned(&mut body, function.locals.len() as u64);
+        for local in &function.locals{
+            body.extend([1, local.code()]);
+        }
+        for instr in &function.body{
+            instr.encode(&mut body);
+        }
+        End.encode(&mut body);
+        unsigned(&mut contents, body.len() as u64);
+        contents.extend(body);
+    }
+    section(&mut out, 10, contents);
+
+    out
+}
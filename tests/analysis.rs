@@ -0,0 +1,77 @@
+use stackofstacks::analysis::{Finding, Interval, analyse};
+use stackofstacks::assembler::assemble;
+use stackofstacks::cfg::{self, Jump};
+
+fn code(source:&str) -> Vec<u8>{
+    assemble(source.as_bytes().to_vec()).unwrap().code
+}
+
+fn findings(source:&str, strict:bool) -> Vec<Finding>{
+    analyse(&code(source), strict).findings
+}
+
+#[test]
+fn underflow_is_certain_or_possible(){
+    // '+' on one value pops an empty stack on every path
+    assert_eq!(findings("!+!@", false), [Finding::Underflow{offset: 1, stack: 0, certain: true}]);
+    // '~' needs stack 1 as well
+    assert_eq!(findings("!~!@", false), [Finding::Underflow{offset: 1, stack: 1, certain: true}]);
+    // reading EOF or a byte both push one, but the loop around it only sometimes left a second
+    let source = "!:top ?=/[skip-at]*@:at ! :skip +[top-back]@:back ";
+    assert!(findings(source, false).contains(&Finding::Underflow{offset: code(source).iter().position(|b| *b == b'+').unwrap(), stack: 0, certain: false}));
+}
+
+#[test]
+fn heights_are_tracked(){
+    let code = code("!!$!!@");
+    let analysis = analyse(&code, false);
+    assert_eq!(analysis.heights(3), [Some(Interval::exact(2)), Some(Interval::exact(0))]);
+    assert!(analysis.findings.is_empty());
+}
+
+#[test]
+fn loops_that_push_grow_without_bound(){
+    let source = ":top ![top-back]@:back ";
+    let found = findings(source, false);
+    assert!(found.iter().any(|f| matches!(f, Finding::Unbounded{stack: 0, ..})), "{:?}", found);
+    // a loop that pops what it pushes stays bounded
+    let found = findings(":top !!^&|!!^|[top-back]@:back ", false);
+    assert!(!found.iter().any(|f| matches!(f, Finding::Unbounded{..})), "{:?}", found);
+}
+
+#[test]
+fn unreachable_code_is_only_reported_without_dynamic_jumps(){
+    // everything after the halt
+    assert_eq!(findings("!@!!.", false), [Finding::Unreachable{start: 2, end: 5}]);
+    // a computed jump could land there, so nothing is unreachable but the jump is noted
+    assert_eq!(findings("??+@!@!!.", false), [Finding::DynamicJump{offset: 3}]);
+}
+
+#[test]
+fn unguarded_conditional_jump_is_dynamic(){
+    // K*@ without a 0/1 test in front jumps by K times whatever was read: 'a' takes it to the print
+    let source = format!("?!!^1*@!@{}!!^1000001.!@", "$".repeat(100));
+    let code = code(&source);
+    assert_eq!(cfg::jump(&code, 6), Jump::Dynamic);
+    assert!(cfg::successors(&code, &cfg::jumps(&code), 6, false).dynamic);
+    assert_eq!(analyse(&code, false).findings, [Finding::DynamicJump{offset: 6}]);
+
+    // with the test it is a two way branch
+    let guarded = self::code("?=/!!^10*@!@!!.!@");
+    assert_eq!(cfg::jump(&guarded, 9), Jump::Conditional(2));
+    assert!(findings("?=/!!^10*@!@!!.!@", false).is_empty());
+}
+
+#[test]
+fn jumping_into_a_constant_makes_its_jump_dynamic(){
+    // the first jump lands on the '^' of the guarded '!!^10*@', which then jumps by whatever is there
+    let code = b"!!^101@?=/!!^10*@!@!!.!@";
+    assert_eq!(cfg::jump(code, 16), Jump::Conditional(2));
+    let jumps = cfg::jumps(code);
+    assert_eq!((jumps[6], jumps[16]), (Jump::Constant(5), Jump::Dynamic));
+    assert!(analyse(code, false).findings.contains(&Finding::DynamicJump{offset: 16}));
+
+    // landing on the test in front is the same, landing at its start is fine
+    assert_eq!(cfg::jumps(b"!!^10@?=/!!^10*@!@!!.!@")[15], Jump::Dynamic);
+    assert_eq!(cfg::jumps(b"!!^1@?=/!!^10*@!@!!.!@")[14], Jump::Conditional(2));
+}
@@ -0,0 +1,151 @@
+use std::fs;
+
+use stackofstacks::assembler::assemble;
+use stackofstacks::brainfuck::{export, interpret, transpile};
+use stackofstacks::bytecode::bytecode;
+use stackofstacks::explore::Rng;
+use stackofstacks::fast;
+use stackofstacks::vm::{self, BufferIo, Stop, Vm};
+
+const MAX_STEPS:u64 = 50_000_000;
+
+fn corpus(name:&str) -> Vec<u8>{
+    fs::read(format!("{}/brainfuck/{}", env!("CARGO_MANIFEST_DIR"), name)).unwrap()
+}
+
+// Output of the translated program on the plain and the fast interpreter, which have to agree
+fn run(program:&[u8], input:&[u8]) -> Vec<u8>{
+    let code = assemble(transpile(program).unwrap().into_bytes()).unwrap().code;
+    let mut outputs = vec!();
+    for plain in [true, false]{
+        let mut vm = Vm::new(code.clone(), false);
+        vm.max_steps = Some(MAX_STEPS);
+        let mut io = BufferIo::new(input.to_vec());
+        let result = if plain {vm::run(&mut vm, &mut io, &mut [])} else {fast::run(&mut vm, &mut io)};
+        assert_eq!(result, Ok(Stop::Halted));
+        outputs.push(io.output);
+    }
+    assert_eq!(outputs[0], outputs[1]);
+    outputs.pop().unwrap()
+}
+
+#[test]
+fn translated_corpus_matches_brainfuck(){
+    let countdown:Vec<u8> = (1..=255).rev().collect();
+    let cases:[(&str, &[u8], &[u8]); 9] = [
+        ("hello.b", b"", b"Hello World!\n"),
+        ("short_hello.b", b"", b"Hello, World!"),
+        ("cat.b", b"Stack Of Stacks\n", b"Stack Of Stacks\n"),
+        ("cat.b", b"", b""),
+        ("reverse.b", b"stressed", b"desserts"),
+        ("add.b", b"34", b"7"),
+        ("countdown.b", b"", &countdown),
+        ("rot13.b", b"Hello, World!", b"Uryyb, Jbeyq!"),
+        ("rot13.b", b"Uryyb, Jbeyq!", b"Hello, World!"),
+    ];
+    for (name, input, expected) in cases{
+        let program = corpus(name);
+        assert_eq!(interpret(&program, input, MAX_STEPS).unwrap(), (expected.to_vec(), Stop::Halted), "{}", name);
+        assert_eq!(run(&program, input), expected, "{}", name);
+    }
+}
+
+#[test]
+fn cells_are_bytes(){
+    // 256 increments wrap to 0 so the loop is skipped, 255 decrements leave 1
+    let program = format!("{}[.]{}.", "+".repeat(256), "-".repeat(255));
+    assert_eq!(run(program.as_bytes(), b""), [1]);
+    // ',' reads 0 on EOF, bytes above 127 come through
+    assert_eq!(run(b",.,.,.", b"\xFF"), [255, 0, 0]);
+}
+
+#[test]
+fn transpiled_source_keeps_the_commands(){
+    let source = transpile(b"read ,\n\nloop [->+<] done\n").unwrap();
+    let lines:Vec<&str> = source.lines().filter(|l| l.contains("\t# ")).collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].ends_with("# ,"));
+    assert!(lines[1].ends_with("# [->+<]"));
+}
+
+#[test]
+fn unmatched_brackets(){
+    assert_eq!(transpile(b"+\n+[[-]"), Err("Unmatched '[' at 2:2".to_owned()));
+    assert_eq!(transpile(b"+]"), Err("Unmatched ']' at 1:2".to_owned()));
+    assert!(interpret(b"[", b"", 10).is_err());
+}
+
+fn sample(name:&str) -> Vec<u8>{
+    assemble(fs::read(format!("{}/{}", env!("CARGO_MANIFEST_DIR"), name)).unwrap()).unwrap().code
+}
+
+// Output of code on the interpreter and whether it stopped (halted or faulted) within max_steps
+fn reference(code:&[u8], input:&[u8], strict:bool, max_steps:u64) -> (Vec<u8>, bool){
+    let mut vm = Vm::new(code.to_vec(), strict);
+    vm.max_steps = Some(max_steps);
+    let mut io = BufferIo::new(input.to_vec());
+    let result = vm::run(&mut vm, &mut io, &mut []);
+    (io.output, result != Ok(Stop::StepLimit))
+}
+
+// Both have to end with the same output
+fn exported_matches(name:&str, code:&[u8], input:&[u8]){
+    for strict in [false, true]{
+        let (expected, stopped) = reference(code, input, strict, MAX_STEPS);
+        assert!(stopped, "{}", name);
+        let exported = export(code, strict);
+        assert_eq!(interpret(exported.as_bytes(), input, 1_000_000_000).unwrap(), (expected, Stop::Halted), "{} (strict: {})", name, strict);
+    }
+}
+
+#[test]
+fn exported_samples_match_the_interpreter(){
+    let cases:[(&str, &[u8]); 7] = [
+        ("helloworld.sos", b""),
+        ("helloworldworldworld_macro.sos", b""),
+        ("loop.sos", b""),
+        ("turing/branch.sos", b"a"),
+        ("turing/branch.sos", b"b"),
+        ("turing/while.sos", b"x"),
+        ("turing/sequence.sos", b""),
+    ];
+    for (name, input) in cases{
+        exported_matches(name, &sample(name), input);
+    }
+}
+
+#[test]
+fn exported_arithmetic_wraps(){
+    // i64::MIN / -1, overflowing add, sub, mul and shifts, division by 0, signs and popping from empty stacks
+    let min = format!("!1{}", "0".repeat(63));
+    let wrapping = format!("{min}!/{min}!+!!^1{min}-{min}=*{min}1!!^11/!!^!!^/$~$=.!@");
+    exported_matches("wrapping", wrapping.as_bytes(), b"");
+    let signs = "!!^1100100!!^111-=.!!^1011*=.!!^11/=.!!^101-.!!^111!!^101-=.!!^11/.!0!!^1111/.!@";
+    exported_matches("signs", signs.as_bytes(), b"");
+    exported_matches("input", b"?.?.?=.$?~..!@", b"Hi\xFF");
+}
+
+#[test]
+fn exported_random_programs_match_the_interpreter(){
+    let mut rng = Rng::new(48);
+    let mut count = 0;
+    while count < 12{
+        let mut bytes = vec![0; 1 + (rng.next_u64() % 12) as usize];
+        rng.fill(&mut bytes);
+        let code = bytecode(&bytes);
+        // only the ones that stop soon, running on and on is too slow on Brainfuck
+        if reference(&code, b"In", false, 40).1{
+            exported_matches(&String::from_utf8_lossy(&code), &code, b"In");
+            count += 1;
+        }
+    }
+}
+
+#[test]
+fn exported_rule_110_starts_the_same(){
+    let code = sample("turing/110.sos");
+    let (output, _) = interpret(export(&code, false).as_bytes(), b"", 40_000_000).unwrap();
+    let (expected, _) = reference(&code, b"", false, 100_000);
+    assert!(output.len() >= 7, "two generations");
+    assert!(expected.starts_with(&output));
+}
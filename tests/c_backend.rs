@@ -0,0 +1,103 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use stackofstacks::assembler::{assemble, Program};
+use stackofstacks::bytecode::bytecode;
+use stackofstacks::c;
+use stackofstacks::explore::Rng;
+use stackofstacks::vm::{self, BufferIo, Stop, Vm};
+
+const MAX_STEPS:u64 = 5000;
+
+fn scratch(name:&str) -> PathBuf{
+    let dir = std::env::temp_dir().join(format!("sos-c-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// stdout, stderr and exit code the compiled program should give, the way the command line reports a run
+fn interpret(code:&[u8], input:&[u8], strict:bool) -> (Vec<u8>, String, i32){
+    let mut vm = Vm::new(code.to_vec(), strict);
+    vm.max_steps = Some(MAX_STEPS);
+    let mut io = BufferIo::new(input.to_vec());
+    let (mut stderr, status) = match vm::run(&mut vm, &mut io, &mut []){
+        Ok(Stop::Halted) => ("".to_owned(), 0),
+        Ok(_) => (format!("Step limit reached after {} steps\n", vm.steps), 1),
+        Err(fault) => (format!("{}\n", fault), 1),
+    };
+    stderr.push_str(&format!("{:?}\n{:?}\n", vm.stacks[0], vm.stacks[1]));
+    (io.output, stderr, status)
+}
+
+fn compile_and_run(dir:&Path, name:&str, program:&Program, input:&[u8], strict:bool) -> (Vec<u8>, String, i32){
+    let source = dir.join(format!("{}.c", name));
+    let binary = dir.join(name);
+    fs::write(&source, c::emit(program, strict)).unwrap();
+    let status = Command::new("cc")
+        .args(["-O0", "-w", "-DSOS_STACKS", &format!("-DSOS_MAX_STEPS={}", MAX_STEPS), "-o"])
+        .arg(&binary).arg(&source)
+        .status().unwrap();
+    assert!(status.success(), "{}: cc failed", name);
+
+    let mut child = Command::new(&binary).stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().unwrap();
+    // the program may stop before reading everything
+    let _ = child.stdin.take().unwrap().write_all(input);
+    let output = child.wait_with_output().unwrap();
+    (output.stdout, String::from_utf8(output.stderr).unwrap(), output.status.code().unwrap())
+}
+
+fn cc_available() -> bool{
+    Command::new("cc").arg("--version").stdout(Stdio::null()).stderr(Stdio::null()).status().is_ok_and(|s| s.success())
+}
+
+#[test]
+fn compiled_programs_match_the_interpreter(){
+    if !cc_available(){
+        eprintln!("no cc found, skipping");
+        return;
+    }
+    let dir = scratch("programs");
+
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let mut programs = vec!();
+    for filename in ["helloworld.sos", "helloworldworldworld_macro.sos", "loop.sos", "turing/110.sos", "turing/branch.sos", "turing/while.sos"]{
+        programs.push((filename.replace(['/', '.'], "_"), assemble(fs::read(root.join(filename)).unwrap()).unwrap()));
+    }
+    let mut rng = Rng::new(44);
+    for index in 0..24{
+        let mut bytes = vec![0; 1 + (rng.next_u64() % 24) as usize];
+        rng.fill(&mut bytes);
+        programs.push((format!("random_{}", index), Program::from_code(bytecode(&bytes))));
+    }
+
+    for (name, program) in &programs{
+        for strict in [false, true]{
+            let input = b"Input\xFF\x00";
+            let expected = interpret(&program.code, input, strict);
+            let actual = compile_and_run(&dir, name, program, input, strict);
+            assert_eq!(actual, expected, "{} (strict: {}) {}", name, strict, String::from_utf8_lossy(&program.code));
+        }
+    }
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn arithmetic_wraps_like_the_interpreter(){
+    if !cc_available(){
+        eprintln!("no cc found, skipping");
+        return;
+    }
+    let dir = scratch("wraps");
+
+    // i64::MIN / -1, overflowing add, sub, mul and shifts, division by 0 and popping from empty stacks
+    let min = format!("!1{}", "0".repeat(63));
+    let code = format!("{min}!/{min}!+!!^1{min}-{min}=*{min}1!!^11/!!^!!^/$~$=.!@");
+    let program = Program::from_code(code.into_bytes());
+    for strict in [false, true]{
+        assert_eq!(compile_and_run(&dir, "wraps", &program, b"", strict), interpret(&program.code, b"", strict));
+    }
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,85 @@
+use stackofstacks::assembler::{Program, assemble};
+use stackofstacks::coverage::{Coverage, Totals};
+use stackofstacks::vm::{self, BufferIo, Vm};
+
+// Skips the second line unless the byte read is 0
+const BRANCH:&str = "?=/[skip-a]*@:a\n!!^.\n:skip !@\n";
+
+fn run(program:&Program, coverage:&mut Coverage, input:&[u8]){
+    let mut vm = Vm::new(program.code.clone(), true);
+    vm::run(&mut vm, &mut BufferIo::new(input.to_vec()), &mut [coverage]).unwrap();
+}
+
+#[test]
+fn branch_taken_one_way(){
+    let program = assemble(BRANCH.as_bytes().to_vec()).unwrap();
+    let mut coverage = Coverage::new(&program.code);
+    run(&program, &mut coverage, b"x");
+
+    // only the guarded jump is a branch, the halt is not
+    assert_eq!(coverage.branches.keys().copied().collect::<Vec<_>>(), [69]);
+    assert_eq!(coverage.branches[&69], [0, 1]);
+
+    let (labels, all) = coverage.totals(&program);
+    assert_eq!(all, Totals{instructions: 76, instructions_hit: 72, branches: 2, branches_hit: 1});
+    assert_eq!(labels, [
+        ("(top)", Totals{instructions: 70, instructions_hit: 70, branches: 2, branches_hit: 1}),
+        ("a", Totals{instructions: 4, instructions_hit: 0, branches: 0, branches_hit: 0}),
+        ("skip", Totals{instructions: 2, instructions_hit: 2, branches: 0, branches_hit: 0}),
+    ]);
+
+    let summary = coverage.summary(&program);
+    assert!(summary.starts_with("Coverage: 72/76 instructions (94.74%), 1/2 branch outcomes (50.00%)"));
+    assert!(summary.lines().any(|l| l.contains("1:13") && l.ends_with("never fell through")), "{}", summary);
+
+    assert_eq!(coverage.lcov(&program, "branch.sos"), "\
+TN:
+SF:branch.sos
+FN:2,a
+FN:3,skip
+FNDA:0,a
+FNDA:1,skip
+FNF:2
+FNH:1
+BRDA:1,69,0,0
+BRDA:1,69,1,1
+BRF:2
+BRH:1
+DA:1,1
+DA:2,0
+DA:3,1
+LF:3
+LH:2
+end_of_record
+");
+}
+
+#[test]
+fn runs_add_up(){
+    let program = assemble(BRANCH.as_bytes().to_vec()).unwrap();
+    let mut coverage = Coverage::new(&program.code);
+    run(&program, &mut coverage, b"x");
+    run(&program, &mut coverage, b"\0");
+    run(&program, &mut coverage, b"\0");
+
+    assert_eq!(coverage.branches[&69], [2, 1]);
+    assert_eq!(coverage.totals(&program).1, Totals{instructions: 76, instructions_hit: 76, branches: 2, branches_hit: 2});
+    assert!(!coverage.summary(&program).contains("Incomplete branches"));
+
+    let lcov = coverage.lcov(&program, "branch.sos");
+    assert!(lcov.contains("BRDA:1,69,0,2\nBRDA:1,69,1,1\n"));
+    assert!(lcov.contains("DA:2,2\n"));
+    assert!(lcov.ends_with("LH:3\nend_of_record\n"));
+}
+
+#[test]
+fn branches_never_reached_have_no_count(){
+    // the jump on the second line only runs after the first line falls through
+    let program = assemble(b"?=/[skip-a]*@:a\n?=/[skip-b]*@:b\n:skip !@\n".to_vec()).unwrap();
+    let mut coverage = Coverage::new(&program.code);
+    run(&program, &mut coverage, b"x");
+
+    let lcov = coverage.lcov(&program, "two.sos");
+    assert!(lcov.contains("BRDA:2,139,0,-\nBRDA:2,139,1,-\n"), "{}", lcov);
+    assert!(coverage.summary(&program).contains("never executed"));
+}
@@ -0,0 +1,91 @@
+use stackofstacks::assembler::assemble;
+use stackofstacks::bytecode::bytecode;
+use stackofstacks::explore::{self, Behaviour, Ending, Exploration, Rng, Source};
+
+const MAX_STEPS:u64 = 2_000;
+
+// What --explore does, without the printing
+fn explore(seed:u64, runs:u64, strict:bool) -> Exploration{
+    let mut source = Source::Random(Rng::new(seed));
+    let mut exploration = Exploration::default();
+    while exploration.runs < runs{
+        let program = source.program(16).unwrap();
+        let behaviour = explore::run(bytecode(&program), strict, MAX_STEPS);
+        exploration.add(program, behaviour);
+    }
+    exploration
+}
+
+fn ending(source:&str, strict:bool) -> Ending{
+    explore::run(assemble(source.as_bytes().to_vec()).unwrap().code, strict, 100).ending
+}
+
+#[test]
+fn endings_are_classified(){
+    assert_eq!(ending("!@", false), Ending::Halted);
+    // underflow and leaving the code only fault in strict mode
+    assert_eq!(ending("+!@", true), Ending::Fault);
+    assert_eq!(ending("!!^1@", true), Ending::Fault);
+    // without it the jump past the end wraps to the start, to the exact same state
+    assert_eq!(ending("!!^1@", false), Ending::Loop{period: 5});
+    // hitting the step limit, either piling up values or just counting
+    assert_eq!(ending("!", false), Ending::Growing);
+    assert_eq!(ending("!!^:top !!^1+[top-a]@:a", false), Ending::Running);
+}
+
+#[test]
+fn same_behaviour_is_counted_once(){
+    let mut exploration = Exploration::default();
+    let halt = explore::run(b"!@".to_vec(), false, MAX_STEPS);
+    // reading from the empty input pushes -1 as well
+    let read = explore::run(b"?@".to_vec(), false, MAX_STEPS);
+    assert_eq!(halt, read);
+    assert_eq!(halt.signature(), read.signature());
+
+    assert!(exploration.add(b"!@".to_vec(), halt).is_some());
+    assert!(exploration.add(b"?@".to_vec(), read).is_none());
+    assert!(exploration.add(b"!".to_vec(), explore::run(b"!".to_vec(), false, MAX_STEPS)).is_some());
+
+    assert_eq!(exploration.runs, 3);
+    assert_eq!(exploration.specimens.len(), 2);
+    assert_eq!((exploration.specimens[0].program.as_slice(), exploration.specimens[0].count), (&b"!@"[..], 2));
+}
+
+#[test]
+fn a_seed_explores_the_same_way_every_time(){
+    for strict in [false, true]{
+        let first = explore(42, 500, strict);
+        let second = explore(42, 500, strict);
+
+        let specimens = |e:&Exploration| -> Vec<(Vec<u8>, Behaviour, u64)> {
+            e.specimens.iter().map(|s| (s.program.clone(), s.behaviour.clone(), s.count)).collect()
+        };
+        assert_eq!(specimens(&first), specimens(&second));
+        assert_eq!(first.report(), second.report());
+        assert!(first.specimens.len() > 1 && first.specimens.len() < 500);
+    }
+    // strict mode is where faults come from
+    assert!(!explore(42, 500, false).specimens.iter().any(|s| s.behaviour.ending == Ending::Fault));
+    assert!(explore(42, 500, true).specimens.iter().any(|s| s.behaviour.ending == Ending::Fault));
+}
+
+#[test]
+fn saved_specimens_reproduce(){
+    let dir = std::env::temp_dir().join(format!("sos-explore-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let exploration = explore(7, 2_000, false);
+    let mut saved = 0;
+    for specimen in exploration.specimens.iter().filter(|s| s.behaviour.interesting()){
+        let name = format!("{}-{:016x}.bin", specimen.behaviour.ending.name(), specimen.behaviour.signature());
+        std::fs::write(dir.join(&name), &specimen.program).unwrap();
+
+        let program = std::fs::read(dir.join(&name)).unwrap();
+        let behaviour = explore::run(bytecode(&program), false, MAX_STEPS);
+        assert!(name.starts_with(behaviour.ending.name()));
+        assert_eq!(behaviour, specimen.behaviour);
+        saved += 1;
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+    assert!(saved > 0);
+}
@@ -0,0 +1,37 @@
+use proptest::prelude::*;
+
+use stackofstacks::TOKENS;
+use stackofstacks::fast;
+use stackofstacks::vm::{self, BufferIo, Vm};
+
+// Code rich in the idioms the fast interpreter fuses
+fn idioms() -> impl Strategy<Value=Vec<u8>>{
+    let piece = prop_oneof![
+        prop::sample::select(TOKENS.to_vec()).prop_map(|t| vec!(t)),
+        "[01]{0,6}".prop_map(|digits| format!("!{}", digits).into_bytes()),
+        "[01]{0,6}".prop_map(|digits| format!("!!^{}", digits).into_bytes()),
+        Just(b"==/".to_vec()),
+        Just(b"*@".to_vec()),
+    ];
+    prop::collection::vec(piece, 1..40).prop_map(|pieces| pieces.concat())
+}
+
+proptest!{
+    #[test]
+    fn fast_interpreter_matches_the_plain_one(code in idioms(), input in prop::collection::vec(any::<u8>(), 0..8), strict in any::<bool>(), limit in 1u64..3000){
+        let mut plain = Vm::new(code.clone(), strict);
+        plain.max_steps = Some(limit);
+        let mut plain_io = BufferIo::new(input.clone());
+        let plain_result = vm::run(&mut plain, &mut plain_io, &mut []);
+
+        let mut fast = Vm::new(code, strict);
+        fast.max_steps = Some(limit);
+        let mut fast_io = BufferIo::new(input);
+        let fast_result = fast::run(&mut fast, &mut fast_io);
+
+        prop_assert_eq!(fast_result, plain_result);
+        prop_assert_eq!((fast.cp, fast.active, fast.steps), (plain.cp, plain.active, plain.steps));
+        prop_assert_eq!(fast.stacks, plain.stacks);
+        prop_assert_eq!(fast_io.output, plain_io.output);
+    }
+}
@@ -0,0 +1,52 @@
+use proptest::prelude::*;
+
+use stackofstacks::fold;
+use stackofstacks::vm::{self, BufferIo, Vm};
+
+// Code computing constants, some of them jump offsets, between things that are not known
+fn computed() -> impl Strategy<Value=Vec<u8>>{
+    let piece = prop_oneof![
+        prop::sample::select(b"!^|&+-*/$~=?.01".to_vec()).prop_map(|t| vec!(t)),
+        "[01]{0,4}".prop_map(|digits| format!("!!^{}", digits).into_bytes()),
+        prop::sample::select(vec!("!!^1=+", "!!^11=*", "!0!0-", "!!^1!!^10|", "!!^111!!^10/", "!=&")).prop_map(|p| p.as_bytes().to_vec()),
+        (0i64..8).prop_map(|v| format!("!!^{:b}!!^1+@", v).into_bytes()),
+        (1i64..8).prop_map(|v| format!("!!^{:b}==+-@", v).into_bytes()),
+        (0i64..6).prop_map(|v| format!("?=/!!^1!!^{:b}+*@", v).into_bytes()),
+        Just(b"!@".to_vec()),
+    ];
+    prop::collection::vec(piece, 1..30).prop_map(|pieces| pieces.concat())
+}
+
+#[test]
+fn folding_resolves_computed_jumps(){
+    let folded = fold::fold(b"!!^1!!^1+@!@!!^11!!^1+@!@!@!@?.", false);
+    assert_eq!(folded.code, b"!!^000010@!@!!^0000100@!@!@!@?.");
+    assert_eq!(folded.resolved, vec!(9, 22));
+    assert_eq!(folded.dynamic, None);
+
+    // a jump that stays unknown keeps everything as it was
+    assert_eq!(fold::fold(b"?!!^1+@", false).dynamic, Some(6));
+}
+
+proptest!{
+    #[test]
+    fn folding_keeps_the_behaviour(code in computed(), input in prop::collection::vec(any::<u8>(), 0..8), strict in any::<bool>()){
+        let folded = fold::fold(&code, strict);
+        prop_assert_eq!(folded.code.len(), code.len());
+
+        let mut plain = Vm::new(code, strict);
+        plain.max_steps = Some(2000);
+        let mut plain_io = BufferIo::new(input.clone());
+        let plain_result = vm::run(&mut plain, &mut plain_io, &mut []);
+        if plain_result == Ok(vm::Stop::StepLimit) {return Ok(())}
+
+        let mut fast = Vm::new(folded.code, strict);
+        fast.max_steps = Some(2000);
+        let mut fast_io = BufferIo::new(input);
+        let fast_result = vm::run(&mut fast, &mut fast_io, &mut []);
+
+        prop_assert_eq!(fast_result, plain_result);
+        prop_assert_eq!(fast.stacks, plain.stacks);
+        prop_assert_eq!(fast_io.output, plain_io.output);
+    }
+}
@@ -0,0 +1,53 @@
+use stackofstacks::format::{Options, format};
+
+fn fmt(source:&str) -> String{
+    String::from_utf8(format(source.as_bytes(), &Options::default()).unwrap()).unwrap()
+}
+
+#[test]
+fn trailing_comments_are_aligned(){
+    // to the next multiple of 4 at least 2 past the longest line of the run
+    assert_eq!(fmt("!@ # halt\n!!^!  # zero and -1\n"), "!@      # halt\n!!^!    # zero and -1\n");
+    // a line without code or without comment ends a run
+    assert_eq!(fmt("!!^# a\n\n!!^!!^!!^# b\n!@\n"), "!!^     # a\n\n!!^!!^!!^   # b\n!@\n");
+    assert_eq!(fmt("!!^ # a\n# about b\n!!^!!^!!^ # b\n!@\n"), "!!^     # a\n# about b\n!!^!!^!!^   # b\n!@\n");
+}
+
+#[test]
+fn tabs_become_spaces(){
+    // a tab goes to the next multiple of 4
+    assert_eq!(fmt("\t!!^\n  \t!!^\n\t\t!@\n"), "    !!^\n    !!^\n        !@\n");
+    // between code it is just a space
+    assert_eq!(fmt("!!^\t!!^\t\t# x\n!@\n"), "!!^ !!^     # x\n!@\n");
+}
+
+#[test]
+fn labels_and_code_are_spaced(){
+    assert_eq!(fmt("!!^:top!!^1+   [top-a]@:a\n"), "!!^ :top! !^1+ [top-a]@ :a\n");
+}
+
+#[test]
+fn formatting_twice_changes_nothing(){
+    let mut files:Vec<std::path::PathBuf> = vec!();
+    for dir in [".", "library", "turing"]{
+        for entry in std::fs::read_dir(dir).unwrap(){
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|e| e == "sos"){
+                files.push(path);
+            }
+        }
+    }
+    assert!(files.len() >= 8);
+
+    for path in files{
+        let source = std::fs::read(&path).unwrap();
+        let once = format(&source, &Options::default()).unwrap();
+        assert_eq!(format(&once, &Options::default()).unwrap(), once, "{}", path.display());
+        let grouped = format(&source, &Options{group: Some(4)}).unwrap();
+        assert_eq!(format(&grouped, &Options{group: Some(4)}).unwrap(), grouped, "{}", path.display());
+    }
+    // the examples above as well
+    for source in ["!@ # halt\n!!^!  # zero and -1\n", "\t!!^\n  \t!!^\n\t\t!@\n", "!!^:top!!^1+   [top-a]@:a\n"]{
+        assert_eq!(fmt(&fmt(source)), fmt(source));
+    }
+}
@@ -0,0 +1,96 @@
+use std::fs;
+use std::path::PathBuf;
+
+use stackofstacks::golden::{self, Exit};
+
+fn scratch(name:&str) -> PathBuf{
+    let dir = std::env::temp_dir().join(format!("sos-golden-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn example_programs_match_their_expectations(){
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let suites:Vec<_> = golden::discover(&[root])
+        .iter()
+        .filter_map(|path| golden::load(path).unwrap())
+        .collect();
+    assert!(suites.len() >= 5, "expected the example programs to have expectations");
+
+    for report in golden::run_all(suites, 4){
+        for (case, outcome) in report.suite.cases.iter().zip(&report.outcomes){
+            let problems = golden::check(case, outcome.as_ref().unwrap());
+            assert!(problems.is_empty(), "{}: {}: {:?}", report.suite.program.display(), case.name, problems);
+        }
+    }
+}
+
+#[test]
+fn escapes_round_trip(){
+    for bytes in [&b""[..], b" leading", b"trailing ", b"tab\tnew\nline\\", b"\x00\x1D\xFF", b"  "]{
+        assert_eq!(golden::unescape(&golden::escape(bytes)).unwrap(), bytes);
+    }
+}
+
+#[test]
+fn embedded_expectations_are_updated_in_place(){
+    let dir = scratch("embedded");
+    let program = dir.join("echo.sos");
+    fs::write(&program, "?.?.!@\n#| [case two bytes]\n#| stdin: ab\n#| stdout: wrong\n").unwrap();
+
+    let suite = golden::load(&program).unwrap().unwrap();
+    let report = golden::run_all(vec!(suite), 1).remove(0);
+    let outcome = report.outcomes[0].as_ref().unwrap();
+    assert_eq!(outcome.stdout, b"ab");
+    assert!(!golden::check(&report.suite.cases[0], outcome).is_empty());
+
+    assert!(golden::write_update(&report).unwrap());
+    assert_eq!(fs::read_to_string(&program).unwrap(),
+        "?.?.!@\n#| [case two bytes]\n#| stdin: ab\n#| stdout: ab\n#| stacks: [] []\n#| exit: halted\n");
+
+    let suite = golden::load(&program).unwrap().unwrap();
+    let report = golden::run_all(vec!(suite), 1).remove(0);
+    assert!(golden::check(&report.suite.cases[0], report.outcomes[0].as_ref().unwrap()).is_empty());
+
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn sidecar_checks_exit_and_steps(){
+    let dir = scratch("sidecar");
+    let program = dir.join("spin.sos");
+    fs::write(&program, "#!stackofstacks --strict\n!011@\n").unwrap();
+    fs::write(golden::sidecar(&program), "limit: 50\n[case spins]\nexit: step-limit\nsteps: 50..50\n[case too few]\nsteps: ..10\n").unwrap();
+
+    let suite = golden::load(&program).unwrap().unwrap();
+    assert!(suite.cases.iter().all(|c| c.strict && c.limit == 50));
+    let report = golden::run_all(vec!(suite), 2).remove(0);
+
+    let spins = report.outcomes[0].as_ref().unwrap();
+    assert_eq!(spins.exit, Exit::StepLimit);
+    assert!(golden::check(&report.suite.cases[0], spins).is_empty());
+
+    let problems = golden::check(&report.suite.cases[1], report.outcomes[1].as_ref().unwrap());
+    assert_eq!(problems.len(), 2, "{:?}", problems); // neither halted nor fast enough
+
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn discovery_skips_hidden_and_build_directories(){
+    let dir = scratch("discover");
+    for sub in ["examples/deep", ".git", ".hidden", "target/debug"]{
+        fs::create_dir_all(dir.join(sub)).unwrap();
+        fs::write(dir.join(sub).join("a.sos"), "!@\n").unwrap();
+    }
+    fs::write(dir.join("b.sos"), "!@\n").unwrap();
+    fs::write(dir.join("b.expect"), "[case halts]\n").unwrap();
+
+    assert_eq!(golden::discover(std::slice::from_ref(&dir)), [dir.join("b.sos"), dir.join("examples/deep/a.sos")]);
+    // asked for by name they are still searched
+    assert_eq!(golden::discover(&[dir.join("target")]), [dir.join("target/debug/a.sos")]);
+
+    let _ = fs::remove_dir_all(dir);
+}
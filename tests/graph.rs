@@ -0,0 +1,67 @@
+use stackofstacks::assembler::assemble;
+use stackofstacks::graph::{Format, export};
+
+// A test, a dynamic jump, a constant jump and one that leaves the code in strict mode
+const EDGES:&str = "?=/[skip-a]*@:a ?@ :skip [top-b]@:b :top !!^1111111@";
+
+fn graph(source:&str, strict:bool, format:Format) -> String{
+    export(&assemble(source.as_bytes().to_vec()).unwrap(), strict, format)
+}
+
+#[test]
+fn dot_has_every_kind_of_edge(){
+    let expected = r#"digraph sos {
+    node [shape=box, fontname="monospace"];
+    entry [shape=point];
+    halt [shape=doublecircle, label="halt"];
+    entry -> b0;
+    b0 [label="(top)\l0x0..0x45 (line 1)\l?=/!00000000000000000000...\l"];
+    b70 [label="a\l0x46..0x47 (line 1)\l?@\l"];
+    b72 [label="skip\l0x48..0x89 (line 1)\l!00000000000000000000000...\l"];
+    b138 [label="top\l0x8A..0x94 (line 1)\l!!^1111111@\l"];
+    b0 -> b70 [label="not taken"];
+    b0 -> b72 [label="taken"];
+    b70 -> dynamic [style=dashed, label="dynamic"];
+    b72 -> b138 [label="const"];
+    b138 -> out_of_code [label="const"];
+    dynamic [shape=diamond, style=dashed, label="?"];
+    out_of_code [shape=octagon, label="out of code"];
+}
+"#;
+    assert_eq!(graph(EDGES, true, Format::Dot), expected);
+}
+
+#[test]
+fn mermaid_has_every_kind_of_edge(){
+    let expected = r#"flowchart TD
+    entry(( )) --> b0
+    halt(((halt)))
+    b0["(top)<br>0x0..0x45 (line 1)<br>?=/!00000000000000000000..."]
+    b70["a<br>0x46..0x47 (line 1)<br>?@"]
+    b72["skip<br>0x48..0x89 (line 1)<br>!00000000000000000000000..."]
+    b138["top<br>0x8A..0x94 (line 1)<br>!!^1111111@"]
+    b0 -->|not taken| b70
+    b0 -->|taken| b72
+    b70 -.->|dynamic| dynamic
+    b72 -->|const| b138
+    b138 -->|const| out_of_code
+    dynamic{?}
+    out_of_code{{out of code}}
+    classDef unreachable stroke-dasharray: 5 5, color:gray
+"#;
+    assert_eq!(graph(EDGES, true, Format::Mermaid), expected);
+}
+
+#[test]
+fn only_fully_resolved_graphs_grey_out_blocks(){
+    // nothing jumps past the halt
+    let dot = graph("!@!!.", false, Format::Dot);
+    assert!(dot.contains("b2 [label=\"(top)+2\\l0x2..0x4 (line 1)\\l!!.\\l\", style=dashed, color=gray];"), "{}", dot);
+    assert!(graph("!@!!.", false, Format::Mermaid).contains("class b2 unreachable"));
+
+    // K*@ without a test in front could go anywhere, 'a' takes it to the print
+    let source = format!("?!!^1*@!@{}!!^1000001.!@", "$".repeat(100));
+    let dot = graph(&source, false, Format::Dot);
+    assert!(dot.contains("b0 -> dynamic [style=dashed, label=\"dynamic\"];"), "{}", dot);
+    assert!(!dot.contains("taken") && !dot.contains("color=gray"), "{}", dot);
+}
@@ -0,0 +1,99 @@
+use stackofstacks::assembler::{Location, assemble};
+use stackofstacks::library::{includes, link};
+use stackofstacks::vm::{self, BufferIo, Stop, Vm};
+
+const VALUES:[i64; 12] = [0, 1, -1, 2, 7, -7, 10, 255, 1 << 40, -(1 << 33) + 5, i64::MAX, i64::MIN];
+
+// Stack 0 above its bottom -1 and the output after calling routine on arguments, in strict mode
+fn call(routine:&str, arguments:&[i64], input:&[u8]) -> (Vec<i64>, Vec<u8>){
+    let pushes:String = arguments.iter().map(|a| format!("!{:064b}", a)).collect();
+    let source = format!("#include std\n!{}$[back]$[{}-back]@:back $!@", pushes, routine);
+    let program = assemble(link(source.as_bytes()).source).unwrap();
+    let mut vm = Vm::new(program.code, true);
+    vm.max_steps = Some(1_000_000);
+    let mut io = BufferIo::new(input.to_vec());
+    assert_eq!(vm::run(&mut vm, &mut io, &mut []), Ok(Stop::Halted), "{} {:?}", routine, arguments);
+    // the return address is gone again
+    assert_eq!(vm.stacks[1], [], "{} {:?}", routine, arguments);
+    assert_eq!(vm.stacks[0][0], -1, "{} {:?}", routine, arguments);
+    (vm.stacks[0][1..].to_vec(), io.output)
+}
+
+#[test]
+fn comparisons_match_i64(){
+    for a in VALUES{
+        assert_eq!(call("stdabs", &[a], b"").0, [a.wrapping_abs()], "{}", a);
+        for b in VALUES{
+            assert_eq!(call("stdless", &[a, b], b"").0, [i64::from(a < b)], "{} {}", a, b);
+            assert_eq!(call("stdcompare", &[a, b], b"").0, [a.cmp(&b) as i64], "{} {}", a, b);
+            assert_eq!(call("stdmin", &[a, b], b"").0, [a.min(b)], "{} {}", a, b);
+            assert_eq!(call("stdmax", &[a, b], b"").0, [a.max(b)], "{} {}", a, b);
+        }
+    }
+}
+
+#[test]
+fn division_is_unsigned(){
+    for a in VALUES{
+        for b in VALUES{
+            let (a, b) = (a as u64, b as u64);
+            let expected = match b{
+                0 => [0, a as i64],
+                _ => [(a / b) as i64, (a % b) as i64],
+            };
+            assert_eq!(call("stddivmod", &[a as i64, b as i64], b"").0, expected, "{} {}", a, b);
+        }
+    }
+}
+
+#[test]
+fn numbers_are_written_and_read(){
+    for value in VALUES{
+        let (stack, output) = call("stdprint", &[value], b"");
+        assert!(stack.is_empty());
+        assert_eq!(output, value.to_string().as_bytes());
+        assert_eq!(call("stdread", &[], format!("{} ", value).as_bytes()).0, [value]);
+    }
+    // the byte after the number is read as well, EOF ends it too
+    assert_eq!(call("stdread", &[], b"12x34").0, [12]);
+    assert_eq!(call("stdread", &[], b"-").0, [0]);
+    assert_eq!(call("stdread", &[], b"").0, [0]);
+    assert_eq!(call("stdread", &[], b"18446744073709551617").0, [1]);
+}
+
+#[test]
+fn strings_are_written(){
+    let text:Vec<i64> = b"\0!skcatS".iter().map(|b| i64::from(*b)).collect();
+    assert_eq!(call("stdputs", &text, b""), (vec!(), b"Stacks!".to_vec()));
+    assert_eq!(call("stdputs", &[0], b""), (vec!(), vec!()));
+}
+
+#[test]
+fn only_included_when_asked(){
+    assert!(includes(b"!@\n  #include std\n"));
+    assert!(includes(b"#include std # and a comment"));
+    assert!(!includes(b"#include standard"));
+    assert!(!includes(b"!@ #include std\n"));
+    assert_eq!(link(b"!@").source, b"!@");
+    assert_eq!(link(b"!@").library_start, None);
+    // a program running off its end still starts over
+    let program = assemble(link(b"?.\n#include std # and a comment").source).unwrap();
+    let mut vm = Vm::new(program.code, false);
+    let mut io = BufferIo::new(b"ab".to_vec());
+    vm.max_steps = Some(1_000);
+    assert_eq!(vm::run(&mut vm, &mut io, &mut []), Ok(Stop::StepLimit));
+    assert!(io.output.starts_with(b"ab\xFF\xFF"));
+}
+
+#[test]
+fn the_library_is_told_apart_from_the_program(){
+    let source = b"#include std\n!@ # no newline";
+    let linked = link(source);
+    assert_eq!(linked.library_start, Some(source.len() + 1));
+    assert!(!linked.in_library(Location{line: 2, column: 15}));
+    assert!(linked.in_library(Location{line: 3, column: 1}));
+
+    let program = assemble(linked.source.clone()).unwrap();
+    assert_eq!(linked.library_code(&program), 2);
+    assert!(program.labels.iter().all(|l| linked.in_library(l.location)));
+}
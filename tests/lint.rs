@@ -0,0 +1,99 @@
+use stackofstacks::analysis::Severity;
+use stackofstacks::assembler::Location;
+use stackofstacks::lint::{Diagnostic, Rule, lint};
+
+fn only(source:&str, rule:Rule) -> Vec<Diagnostic>{
+    lint(source.as_bytes(), false).into_iter().filter(|d| d.rule == rule).collect()
+}
+
+fn at(line:usize, column:usize) -> Option<Location>{
+    Some(Location{line, column})
+}
+
+#[test]
+fn rule_names_round_trip(){
+    for rule in Rule::ALL{
+        assert_eq!(Rule::from_name(rule.name()), Some(rule));
+        assert_eq!(rule.to_string(), rule.name());
+    }
+    assert_eq!(Rule::from_name("unused-label"), Some(Rule::UnusedLabel));
+    assert_eq!(Rule::from_name("UnusedLabel"), None);
+    assert_eq!(Rule::from_name(""), None);
+}
+
+#[test]
+fn allow_comments_cover_their_own_or_the_next_line(){
+    assert_eq!(only(":unused !@\n", Rule::UnusedLabel).len(), 1);
+    // a comment after code is about that line
+    assert_eq!(only(":unused !@ # lint: allow(unused-label)\n", Rule::UnusedLabel), []);
+    // a comment on a line of its own is about the next one
+    assert_eq!(only("# lint: allow(stray-character, unused-label)\n:unused !@\n", Rule::UnusedLabel), []);
+    // but not the one after that, nor the line before a trailing comment
+    assert_eq!(only("# lint: allow(unused-label)\n\n:unused !@\n", Rule::UnusedLabel).len(), 1);
+    assert_eq!(only(":unused !@\n!@ # lint: allow(unused-label)\n", Rule::UnusedLabel).len(), 1);
+    // other rules on the line are still reported
+    assert_eq!(only(":unused !@ x # lint: allow(unused-label)\n", Rule::StrayCharacter).len(), 1);
+}
+
+#[test]
+fn unknown_rules_are_reported_where_they_are_named(){
+    let diagnostics = only("!@ # lint: allow(unused-label, unused-lable)\n", Rule::UnknownRule);
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].location, at(1, 4));
+    assert_eq!(diagnostics[0].severity, Severity::Warning);
+    assert!(diagnostics[0].message.contains("'unused-lable'"));
+    // comments that are not allow lists are just comments
+    assert_eq!(only("!@ # lint: unused-lable\n", Rule::UnknownRule), []);
+}
+
+#[test]
+fn crlf_is_noted_once_and_bare_cr_warned_every_time(){
+    let crlf = only("!!^\r\n!@\r\n", Rule::LineEnding);
+    assert_eq!(crlf.len(), 1);
+    assert_eq!((crlf[0].severity, crlf[0].location), (Severity::Note, at(1, 4)));
+
+    let cr = only("!!^\r!!^\r!@\n", Rule::LineEnding);
+    assert_eq!(cr.len(), 2);
+    assert!(cr.iter().all(|d| d.severity == Severity::Warning));
+    // a bare CR does not start a line (nor take a column), so both are on line 1
+    assert_eq!(cr[0].location, at(1, 4));
+    assert_eq!(cr[1].location, at(1, 7));
+
+    assert_eq!(only("!!^\n!@\n", Rule::LineEnding), []);
+}
+
+#[test]
+fn characters_ending_a_label_are_swallowed(){
+    let swallowed = only(":top!!^[top-a]@:a\n", Rule::SwallowedCharacter);
+    assert_eq!(swallowed.len(), 1);
+    assert_eq!(swallowed[0].location, at(1, 5));
+    assert!(swallowed[0].message.ends_with("the instruction '!'"));
+
+    let comment = only("!!^[top-a]@:a :top# halt\n!@\n", Rule::SwallowedCharacter);
+    assert_eq!(comment.len(), 1);
+    assert!(comment[0].message.ends_with("the '#' so the comment after it"));
+
+    // whitespace is the one character that is fine to lose
+    assert_eq!(only(":top !!^[top-a]@:a\n", Rule::SwallowedCharacter), []);
+    assert_eq!(only(":top\n!!^[top-a]@:a\n", Rule::SwallowedCharacter), []);
+}
+
+#[test]
+fn only_guarded_conditional_jumps_are_checked_for_alignment(){
+    // '=/' makes the jump by 0 or 3, which lands inside the expansion of [0]
+    let guarded = lint(b"?=/[3]*@[0]!@\n", false);
+    assert!(guarded.iter().any(|d| d.rule == Rule::MisalignedJump && d.message.contains("3 instructions into")));
+    // without the test the jump goes who knows where, which is not a misaligned jump by 3
+    let unguarded = lint(b"?[3]*@[0]!@\n", false);
+    assert!(!unguarded.iter().any(|d| d.rule == Rule::MisalignedJump));
+    assert!(unguarded.iter().any(|d| d.rule == Rule::DynamicJump));
+}
+
+#[test]
+fn the_included_library_is_not_linted(){
+    // none of its routines are called, nor is its code reached
+    assert_eq!(lint(b"#include std\n!@", false), []);
+    let diagnostics = lint(b"#include std\n:unused !@ x", false);
+    assert_eq!(diagnostics.iter().map(|d| (d.rule, d.location)).collect::<Vec<_>>(),
+        [(Rule::UnusedLabel, at(2, 1)), (Rule::StrayCharacter, at(2, 12))]);
+}
@@ -0,0 +1,36 @@
+use stackofstacks::assembler::assemble;
+use stackofstacks::loops::Detector;
+use stackofstacks::vm::{self, BufferIo, Stop, Vm};
+
+fn detect(source:&str, input:&[u8]) -> (Result<Stop, vm::Fault>, Detector){
+    let program = assemble(source.as_bytes().to_vec()).unwrap();
+    let mut detector = Detector::new(program.code.len());
+    let mut vm = Vm::new(program.code, false);
+    vm.max_steps = Some(10_000);
+    let result = vm::run(&mut vm, &mut BufferIo::new(input.to_vec()), &mut [&mut detector]);
+    (result, detector)
+}
+
+#[test]
+fn repeated_state_stops_with_the_cycle(){
+    let (result, detector) = detect("?.\n:spin !0@\n", b"");
+    assert_eq!(result, Ok(Stop::Interrupted));
+    let cycle = detector.cycle.unwrap();
+    assert_eq!(cycle.period, 2);
+    assert_eq!(cycle.offsets, vec!(false, false, false, true, true));
+}
+
+#[test]
+fn growing_stacks_are_not_a_cycle(){
+    let (result, detector) = detect(":top !1\n", b"");
+    assert_eq!(result, Ok(Stop::StepLimit));
+    assert!(detector.cycle.is_none());
+}
+
+#[test]
+fn reading_input_is_not_a_repeat(){
+    // reads and drops input, wrapping around, the stack stays [-1] all the time
+    let (result, detector) = detect("?=^+\n", &[7; 100]);
+    assert_eq!(result, Ok(Stop::Interrupted));
+    assert!(detector.cycle.unwrap().steps > 400);
+}
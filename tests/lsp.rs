@@ -0,0 +1,142 @@
+use stackofstacks::json::{self, Value};
+use stackofstacks::lsp::{read_message, serve, write_message};
+
+const URI:&str = "file:///calls.sos";
+
+// Calls stdprint, the 'x' after the halt is a stray character
+const TEXT:&str = "#include std\n!$!$[5]$[back]$[stdprint-back]@ :back $\n!@ x\n";
+
+fn message(text:&str) -> Value{
+    json::parse(text).unwrap()
+}
+
+fn position(line:usize, character:usize) -> String{
+    format!(r#"{{"textDocument": {{"uri": "{}"}}, "position": {{"line": {}, "character": {}}}}}"#, URI, line, character)
+}
+
+// Runs a whole session through serve and returns its exit code and everything it sent back
+fn session(messages:&[Value]) -> (i32, Vec<Value>){
+    let mut input = vec!();
+    for m in messages{
+        write_message(&mut input, m).unwrap();
+    }
+    run(&input)
+}
+
+fn run(input:&[u8]) -> (i32, Vec<Value>){
+    let mut output = vec!();
+    let code = serve(input, &mut output).unwrap();
+
+    let mut replies = vec!();
+    let mut output = &output[..];
+    while let Some(reply) = read_message(&mut output).unwrap(){
+        replies.push(reply);
+    }
+    (code, replies)
+}
+
+fn reply(replies:&[Value], id:i64) -> &Value{
+    replies.iter().find(|r| r.get("id").and_then(Value::as_i64) == Some(id)).unwrap().get("result").unwrap()
+}
+
+fn start(range:&Value) -> (i64, i64){
+    let start = range.get("start").unwrap();
+    (start.get("line").unwrap().as_i64().unwrap(), start.get("character").unwrap().as_i64().unwrap())
+}
+
+#[test]
+fn serves_a_session_over_stdio(){
+    let back = TEXT.lines().nth(1).unwrap().find("[back]").unwrap() + 1;
+    let defined = TEXT.lines().nth(1).unwrap().find(":back").unwrap();
+
+    let (code, replies) = session(&[
+        message(r#"{"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {}}"#),
+        message(r#"{"jsonrpc": "2.0", "method": "initialized", "params": {}}"#),
+        message(&format!(r#"{{"jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {{"textDocument": {{"uri": "{}", "languageId": "sos", "version": 1, "text": {}}}}}}}"#,
+            URI, json::escape(TEXT))),
+        message(&format!(r#"{{"jsonrpc": "2.0", "id": 2, "method": "textDocument/hover", "params": {}}}"#, position(1, back))),
+        message(&format!(r#"{{"jsonrpc": "2.0", "id": 3, "method": "textDocument/definition", "params": {}}}"#, position(1, back))),
+        message(&format!(r#"{{"jsonrpc": "2.0", "id": 4, "method": "textDocument/inlayHint", "params": {{"textDocument": {{"uri": "{}"}}}}}}"#, URI)),
+        message(r#"{"jsonrpc": "2.0", "id": 5, "method": "shutdown"}"#),
+        message(r#"{"jsonrpc": "2.0", "method": "exit"}"#),
+    ]);
+    assert_eq!(code, 0);
+
+    let capabilities = reply(&replies, 1).get("capabilities").unwrap();
+    assert_eq!(capabilities.get("hoverProvider"), Some(&Value::Bool(true)));
+    assert_eq!(capabilities.get("definitionProvider"), Some(&Value::Bool(true)));
+
+    // only the stray character, nothing from the library that got included
+    let published:Vec<&Value> = replies.iter().filter(|r| r.get("method").and_then(Value::as_str) == Some("textDocument/publishDiagnostics")).collect();
+    assert_eq!(published.len(), 1);
+    assert_eq!(published[0].get("params").unwrap().get("uri").and_then(Value::as_str), Some(URI));
+    let diagnostics = published[0].get("params").unwrap().get("diagnostics").unwrap().as_array().unwrap();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].get("code").and_then(Value::as_str), Some("stray-character"));
+    assert_eq!(start(diagnostics[0].get("range").unwrap()), (2, 3));
+
+    let hover = reply(&replies, 2).get("contents").unwrap().get("value").and_then(Value::as_str).unwrap();
+    assert!(hover.starts_with("label `back` at offset"), "{}", hover);
+
+    let definition = reply(&replies, 3);
+    assert_eq!(definition.get("uri").and_then(Value::as_str), Some(URI));
+    assert_eq!(start(definition.get("range").unwrap()), (1, defined as i64));
+
+    // one hint for each line with code, none for the library after it
+    let hints = reply(&replies, 4).as_array().unwrap();
+    let lines:Vec<i64> = hints.iter().map(|h| h.get("position").unwrap().get("line").unwrap().as_i64().unwrap()).collect();
+    assert_eq!(lines, [1, 2]);
+
+    assert_eq!(reply(&replies, 5), &Value::Null);
+}
+
+#[test]
+fn exit_without_shutdown_is_an_error(){
+    let (code, replies) = session(&[message(r#"{"jsonrpc": "2.0", "method": "exit"}"#)]);
+    assert_eq!(code, 1);
+    assert_eq!(replies, []);
+}
+
+#[test]
+fn broken_messages_are_answered_and_skipped(){
+    let mut input = vec!();
+    for body in [&b"{\"jsonrpc\": \"2.0\", \"id\": "[..], b"\xff\xfe"]{
+        input.extend(format!("Content-Length: {}\r\n\r\n", body.len()).as_bytes());
+        input.extend(body);
+    }
+    write_message(&mut input, &message(r#"{"jsonrpc": "2.0", "id": 1, "method": "shutdown"}"#)).unwrap();
+    write_message(&mut input, &message(r#"{"jsonrpc": "2.0", "method": "exit"}"#)).unwrap();
+
+    let (code, replies) = run(&input);
+    assert_eq!(code, 0);
+    assert_eq!(replies.len(), 3);
+    for broken in &replies[..2]{
+        assert_eq!(broken.get("id"), Some(&Value::Null));
+        assert_eq!(broken.get("error").unwrap().get("code").and_then(Value::as_i64), Some(-32700));
+    }
+    assert_eq!(reply(&replies, 1), &Value::Null);
+}
+
+#[test]
+fn library_labels_stay_out_of_the_document(){
+    let stdprint = TEXT.lines().nth(1).unwrap().find("stdprint").unwrap() + 1;
+    let (_, replies) = session(&[
+        message(&format!(r#"{{"jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {{"textDocument": {{"uri": "{}", "languageId": "sos", "version": 1, "text": {}}}}}}}"#,
+            URI, json::escape(TEXT))),
+        message(&format!(r#"{{"jsonrpc": "2.0", "id": 1, "method": "textDocument/definition", "params": {}}}"#, position(1, stdprint))),
+        message(&format!(r#"{{"jsonrpc": "2.0", "id": 2, "method": "textDocument/references", "params": {{"textDocument": {{"uri": "{}"}}, "position": {{"line": 1, "character": {}}}, "context": {{"includeDeclaration": true}}}}}}"#,
+            URI, stdprint)),
+        message(&format!(r#"{{"jsonrpc": "2.0", "id": 3, "method": "textDocument/hover", "params": {}}}"#, position(1, stdprint))),
+    ]);
+
+    // defined in the library, which is no range in this document
+    assert_eq!(reply(&replies, 1), &Value::Null);
+
+    let references = reply(&replies, 2).as_array().unwrap();
+    assert_eq!(references.len(), 1);
+    assert_eq!(start(references[0].get("range").unwrap()), (1, stdprint as i64 - 1));
+
+    // where it ends up is still worth knowing
+    let hover = reply(&replies, 3).get("contents").unwrap().get("value").and_then(Value::as_str).unwrap();
+    assert!(hover.starts_with("label `stdprint` at offset"), "{}", hover);
+}
@@ -0,0 +1,34 @@
+use stackofstacks::assembler::assemble;
+
+fn value(text:&str) -> Result<i64, String>{
+    assemble(format!("[{}]", text).into_bytes()).map(|p| p.macros[0].value).map_err(|e| e.message)
+}
+
+#[test]
+fn macros_evaluate_left_to_right(){
+    assert_eq!(value("1+2-4"), Ok(-1));
+    assert_eq!(value("-4"), Ok(-4));
+    assert_eq!(value("'A'+0x10-0b1+0o7+0d3"), Ok(65 + 16 - 1 + 7 + 3));
+    assert_eq!(assemble(b"[end-2]:end".to_vec()).unwrap().macros[0].value, 63);
+}
+
+#[test]
+fn malformed_macros_are_errors(){
+    for (text, message) in [
+        ("", "Empty macro"),
+        ("1+", "cannot end with operator"),
+        ("1+-2", "Unexpected (extra) Operator"),
+        ("0x", "invalid number representation"),
+        ("nowhere", "Label 'nowhere' not found"),
+    ]{
+        let error = value(text).unwrap_err();
+        assert!(error.contains(message), "[{}]: {}", text, error);
+    }
+}
+
+#[test]
+fn macro_arithmetic_wraps(){
+    assert_eq!(value("9223372036854775807+1"), Ok(i64::MIN));
+    assert_eq!(value("0-9223372036854775807-2"), Ok(i64::MAX));
+    assert_eq!(value("0-9223372036854775807-1-1+1"), Ok(i64::MIN));
+}
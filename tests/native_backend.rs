@@ -0,0 +1,174 @@
+#![cfg(feature = "cranelift")]
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use stackofstacks::assembler::{assemble, Program};
+use stackofstacks::bytecode::bytecode;
+use stackofstacks::explore::Rng;
+use stackofstacks::native::{self, Jit};
+use stackofstacks::vm::{self, BufferIo, Fault, Stop, Vm};
+
+const MAX_STEPS:u64 = 5000;
+
+// Everything a run leaves behind
+#[derive(Debug, PartialEq)]
+struct Outcome{
+    result:Result<Stop, Fault>,
+    output:Vec<u8>,
+    stacks:[Vec<i64>; 2],
+    active:usize,
+    cp:usize,
+    steps:u64,
+}
+
+fn outcome(vm:Vm, io:BufferIo, result:Result<Stop, Fault>) -> Outcome{
+    Outcome{result, output: io.output, stacks: vm.stacks, active: vm.active, cp: vm.cp, steps: vm.steps}
+}
+
+fn machine(code:&[u8], strict:bool) -> Vm{
+    let mut vm = Vm::new(code.to_vec(), strict);
+    vm.max_steps = Some(MAX_STEPS);
+    vm
+}
+
+fn interpret(code:&[u8], input:&[u8], strict:bool) -> Outcome{
+    let mut vm = machine(code, strict);
+    let mut io = BufferIo::new(input.to_vec());
+    let result = vm::run(&mut vm, &mut io, &mut []);
+    outcome(vm, io, result)
+}
+
+fn jit(code:&[u8], input:&[u8], strict:bool) -> Outcome{
+    let mut vm = machine(code, strict);
+    let mut io = BufferIo::new(input.to_vec());
+    let result = Jit::new(code, strict).unwrap().run(&mut vm, &mut io);
+    outcome(vm, io, result)
+}
+
+fn programs() -> Vec<(String, Program)>{
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let mut programs = vec!();
+    for filename in ["helloworld.sos", "helloworldworldworld_macro.sos", "loop.sos", "turing/110.sos", "turing/branch.sos", "turing/while.sos"]{
+        programs.push((filename.to_owned(), assemble(fs::read(root.join(filename)).unwrap()).unwrap()));
+    }
+    let mut rng = Rng::new(46);
+    for index in 0..64{
+        let mut bytes = vec![0; 1 + (rng.next_u64() % 24) as usize];
+        rng.fill(&mut bytes);
+        programs.push((format!("random {}", index), Program::from_code(bytecode(&bytes))));
+    }
+    programs
+}
+
+#[test]
+fn jit_matches_the_interpreter(){
+    for (name, program) in programs(){
+        for strict in [false, true]{
+            let input = b"Input\xFF\x00";
+            assert_eq!(jit(&program.code, input, strict), interpret(&program.code, input, strict),
+                "{} (strict: {}) {}", name, strict, String::from_utf8_lossy(&program.code));
+        }
+    }
+}
+
+#[test]
+fn jit_continues_where_the_machine_stopped(){
+    let code = assemble(fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/turing/110.sos")).unwrap()).unwrap().code;
+    let compiled = Jit::new(&code, false).unwrap();
+    let mut vm = machine(&code, false);
+    let mut io = BufferIo::default();
+    for limit in [1000, 1700, MAX_STEPS]{
+        vm.max_steps = Some(limit);
+        assert_eq!(compiled.run(&mut vm, &mut io), Ok(Stop::StepLimit));
+        assert_eq!(vm.steps, limit);
+    }
+    assert_eq!(outcome(vm, io, Ok(Stop::StepLimit)), interpret(&code, b"", false));
+}
+
+#[test]
+fn jit_arithmetic_wraps_like_the_interpreter(){
+    // i64::MIN / -1, overflowing add, sub, mul and shifts, division by 0 and popping from empty stacks
+    let min = format!("!1{}", "0".repeat(63));
+    let code = format!("{min}!/{min}!+!!^1{min}-{min}=*{min}1!!^11/!!^!!^/$~$=.!@");
+    for strict in [false, true]{
+        assert_eq!(jit(code.as_bytes(), b"", strict), interpret(code.as_bytes(), b"", strict));
+    }
+    assert_eq!(jit(b"", b"", false).result, Ok(Stop::Halted));
+}
+
+#[test]
+fn objects_for_other_architectures(){
+    let code = assemble(fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/helloworld.sos")).unwrap()).unwrap().code;
+    // e_machine in the ELF header
+    for (target, machine) in [("aarch64-unknown-linux-gnu", 183u16), ("x86_64-unknown-linux-gnu", 62)]{
+        let object = native::object(&code, false, Some(target)).unwrap();
+        assert_eq!(&object[..4], b"\x7FELF", "{}", target);
+        assert_eq!(u16::from_le_bytes([object[18], object[19]]), machine, "{}", target);
+    }
+    assert!(native::object(&code, false, Some("nonsense-unknown-none")).is_err());
+}
+
+const HARNESS:&str = r#"#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+struct sos_stack{ int64_t *items; uint64_t len, cap; };
+struct sos_context{
+    struct sos_stack stacks[2];
+    uint64_t steps, max_steps, cp, active;
+    void *io;
+    int64_t (*read)(void *io);
+    void (*write)(void *io, int64_t byte);
+    void (*grow)(struct sos_stack *stack);
+};
+int32_t sos_run(struct sos_context *context);
+
+static int64_t read_byte(void *io){ int c = getchar(); return c == EOF ? -1 : c; }
+static void write_byte(void *io, int64_t byte){ putchar((unsigned char)byte); }
+static void grow(struct sos_stack *stack){
+    stack->cap = stack->cap ? stack->cap * 2 : 64;
+    stack->items = realloc(stack->items, stack->cap * sizeof(int64_t));
+}
+
+int main(void){
+    struct sos_context context = {{{0, 0, 0}, {0, 0, 0}}, 0, 5000, 0, 0, 0, read_byte, write_byte, grow};
+    int32_t status = sos_run(&context);
+    fflush(stdout);
+    fprintf(stderr, "%d %llu %llu\n", status, (unsigned long long)context.steps, (unsigned long long)context.cp);
+    return 0;
+}
+"#;
+
+#[test]
+fn objects_link_into_c_programs(){
+    if !cfg!(all(target_arch = "x86_64", target_os = "linux")) || Command::new("cc").arg("--version").output().is_err(){
+        eprintln!("no cc for x86_64 linux found, skipping");
+        return;
+    }
+    let dir = std::env::temp_dir().join(format!("sos-native-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    for (name, program) in programs().into_iter().take(8){
+        let object = dir.join("program.o");
+        let harness = dir.join("harness.c");
+        let binary = dir.join("program");
+        fs::write(&object, native::object(&program.code, true, None).unwrap()).unwrap();
+        fs::write(&harness, HARNESS).unwrap();
+        let status = Command::new("cc").arg("-o").arg(&binary).arg(&harness).arg(&object).status().unwrap();
+        assert!(status.success(), "{}: cc failed", name);
+
+        let output = Command::new(&binary).stdin(Stdio::null()).output().unwrap();
+        let expected = interpret(&program.code, b"", true);
+        let status = match expected.result{
+            Ok(Stop::Halted) => native::STATUS_HALTED,
+            Ok(_) => native::STATUS_STEP_LIMIT,
+            Err(Fault::StackDepleted) => native::STATUS_STACK_DEPLETED,
+            Err(_) => native::STATUS_OUT_OF_CODE,
+        };
+        assert_eq!(output.stdout, expected.output, "{}", name);
+        assert_eq!(String::from_utf8(output.stderr).unwrap(), format!("{} {} {}\n", status, expected.steps, expected.cp), "{}", name);
+    }
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,47 @@
+use proptest::prelude::*;
+
+use stackofstacks::optimize;
+use stackofstacks::vm::{self, BufferIo, Vm};
+
+// Code with what the optimizer rewrites and constant jumps over it
+fn wasteful() -> impl Strategy<Value=Vec<u8>>{
+    let piece = prop_oneof![
+        prop::sample::select(b"!^|&+-*/$~=?.01".to_vec()).prop_map(|t| vec!(t)),
+        prop::sample::select(vec!("$$", "~~", "!&", "!!^+", "!!^-", "!!^|", "!!^^", "=!|&", "=!!^&|")).prop_map(|p| p.as_bytes().to_vec()),
+        any::<i8>().prop_map(|v| format!("!{:064b}", v as i64).into_bytes()),
+        (-12i64..12).prop_map(|v| if v < 0 {format!("!{:b}@", v & 0xFF)} else {format!("!!^{:b}@", v)}.into_bytes()),
+        (-12i64..12).prop_map(|v| format!("?=/!!^{:b}*@", v).into_bytes()),
+        (-12i64..12).prop_map(|v| format!("?!!^1&!!^{:b}*@", v).into_bytes()),
+        Just(b"?!!^11*@".to_vec()),
+        Just(b"!@".to_vec()),
+    ];
+    prop::collection::vec(piece, 1..30).prop_map(|pieces| pieces.concat())
+}
+
+// How a run ended, without offsets that move when the code does
+fn ending(result:Result<vm::Stop, vm::Fault>) -> String{
+    format!("{:?}", result).chars().take_while(|c| c.is_alphabetic() || *c == '(').collect()
+}
+
+proptest!{
+    #[test]
+    fn optimizing_keeps_the_behaviour(code in wasteful(), input in prop::collection::vec(any::<u8>(), 0..8), strict in any::<bool>()){
+        let optimized = optimize::optimize(&code, strict);
+
+        let mut plain = Vm::new(code, strict);
+        plain.max_steps = Some(2000);
+        let mut plain_io = BufferIo::new(input.clone());
+        let plain_result = vm::run(&mut plain, &mut plain_io, &mut []);
+        // loops run into the limit at different points
+        if plain_result == Ok(vm::Stop::StepLimit) {return Ok(())}
+
+        let mut fast = Vm::new(optimized.code, strict);
+        fast.max_steps = Some(4000);
+        let mut fast_io = BufferIo::new(input);
+        let fast_result = vm::run(&mut fast, &mut fast_io, &mut []);
+
+        prop_assert_eq!(ending(fast_result), ending(plain_result));
+        prop_assert_eq!(fast.stacks, plain.stacks);
+        prop_assert_eq!(fast_io.output, plain_io.output);
+    }
+}
@@ -0,0 +1,44 @@
+use stackofstacks::assembler::{Program, assemble};
+use stackofstacks::profile::Profiler;
+use stackofstacks::vm::{self, BufferIo, Vm};
+
+// Counts 3 down to 0: 5 instructions before the loop, 75 in it and the halt after it
+const LOOP:&str = "!!^11 :loop !!^1- ==/[loop-a]*@:a !@";
+
+fn profile(program:&Program) -> Profiler{
+    let mut profiler = Profiler::new(program.code.len());
+    let mut vm = Vm::new(program.code.clone(), true);
+    vm::run(&mut vm, &mut BufferIo::default(), &mut [&mut profiler]).unwrap();
+    profiler
+}
+
+#[test]
+fn counts_per_label_and_jump_edge(){
+    let program = assemble(LOOP.as_bytes().to_vec()).unwrap();
+    let profiler = profile(&program);
+
+    assert_eq!(profiler.total(), 5 + 3*75 + 2);
+    assert_eq!(profiler.per_label(&program), [("loop", 3*75, 75), ("(top)", 5, 5), ("a", 2, 2)]);
+
+    // the jump at the end of the loop goes back twice and falls through once, the halt is no edge
+    let jump = program.labels[1].offset - 1;
+    let mut edges:Vec<((usize, usize), u64)> = profiler.edges.iter().map(|(e, c)| (*e, *c)).collect();
+    edges.sort();
+    assert_eq!(edges, [((jump, program.labels[0].offset), 2), ((jump, jump+1), 1)]);
+
+    let report = profiler.report(&program);
+    assert!(report.contains("Profile: 232 instructions executed"));
+    assert!(report.lines().any(|l| l.ends_with("loop+74 -> loop") && l.trim_start().starts_with("2 ")), "{}", report);
+}
+
+#[test]
+fn labels_are_found_by_offset(){
+    let program = assemble(b"!:a !:b :c !:d !@".to_vec()).unwrap();
+    assert_eq!(program.label_at(0), None);
+    assert_eq!(program.label_at(1), Some(("a", 0)));
+    // b and c share an offset, the later one wins just like it did before
+    assert_eq!(program.label_at(2), Some(("c", 0)));
+    assert_eq!(program.label_at(4), Some(("d", 1)));
+    assert_eq!(program.symbolise(4), "d+1");
+    assert_eq!(program.label_at(100), Some(("d", 97)));
+}
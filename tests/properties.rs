@@ -0,0 +1,92 @@
+use proptest::prelude::*;
+
+use stackofstacks::TOKENS;
+use stackofstacks::assembler::{assemble, disassemble};
+use stackofstacks::bytecode::{bytecode, compile};
+use stackofstacks::format::{self, Options};
+use stackofstacks::vm::{self, BufferIo, Vm};
+
+// Pure script: any sequence of instructions
+fn code() -> impl Strategy<Value=Vec<u8>>{
+    prop::collection::vec(prop::sample::select(TOKENS.to_vec()), 0..300)
+}
+
+// Source made of the pieces the assembler knows about, including labels directly followed by something else
+fn source() -> impl Strategy<Value=Vec<u8>>{
+    let piece = prop_oneof![
+        code().prop_map(|c| c.into_iter().take(20).collect::<Vec<u8>>()),
+        Just(b" ".to_vec()),
+        Just(b"\t".to_vec()),
+        Just(b"\n".to_vec()),
+        Just(b"\r\n".to_vec()),
+        "[a-z]{1,6}".prop_map(|name| format!(":{}", name).into_bytes()),
+        any::<i64>().prop_map(|n| format!("[{}]", n).into_bytes()),
+        any::<u8>().prop_map(|c| format!("[0x{:X}-0b1+0o7]", c).into_bytes()),
+        "[ -~&&[^\n]]{0,20}".prop_map(|text| format!("# {}\n", text).into_bytes()),
+        "[x-z]{1,3}".prop_map(String::into_bytes),
+    ];
+    prop::collection::vec(piece, 0..40).prop_map(|pieces| {
+        let mut source = pieces.concat();
+        source.push(b'\n');
+        source
+    })
+}
+
+proptest!{
+    #[test]
+    fn bytecode_round_trips_modulo_padding(code in code()){
+        let mut padded = code.clone();
+        if padded.len() % 2 == 1{
+            padded.push(TOKENS[0]);
+        }
+        prop_assert_eq!(bytecode(&compile(&code)), padded);
+    }
+
+    #[test]
+    fn decoding_any_bytecode_compiles_back(bytes in prop::collection::vec(any::<u8>(), 0..300)){
+        prop_assert_eq!(compile(&bytecode(&bytes)), bytes);
+    }
+
+    #[test]
+    fn disassembly_assembles_back(code in code()){
+        prop_assert_eq!(assemble(disassemble(&code)).unwrap().code, code);
+    }
+
+    #[test]
+    fn assembler_never_panics(bytes in prop::collection::vec(any::<u8>(), 0..200)){
+        let _ = assemble(bytes);
+    }
+
+    #[test]
+    fn assembler_never_panics_on_macros(text in "\\[[-+0-9a-zA-Z' ]{0,12}\\]?"){
+        let _ = assemble(text.into_bytes());
+    }
+
+    #[test]
+    fn macros_push_their_value(value in any::<i64>()){
+        let program = assemble(format!("[{}]", value).into_bytes()).unwrap();
+        prop_assert_eq!(program.code.len(), 65);
+
+        let mut vm = Vm::new(program.code, false);
+        vm.max_steps = Some(65);
+        vm::run(&mut vm, &mut BufferIo::default(), &mut []).unwrap();
+        prop_assert_eq!(&vm.stacks[0], &vec!(value));
+    }
+
+    #[test]
+    fn formatting_keeps_the_program(source in source(), group in prop::option::of(1usize..9)){
+        // sources with labels used before definition fail to assemble, which is fine
+        if let Ok(program) = assemble(source.clone()){
+            let formatted = format::format(&source, &Options{group}).unwrap();
+            prop_assert_eq!(&assemble(formatted.clone()).unwrap().code, &program.code);
+            prop_assert_eq!(format::format(&formatted, &Options{group}).unwrap(), formatted);
+        }
+    }
+
+    #[test]
+    fn vm_never_panics(bytes in prop::collection::vec(any::<u8>(), 1..100), input in prop::collection::vec(any::<u8>(), 0..8), strict in any::<bool>()){
+        let mut vm = Vm::new(bytecode(&bytes), strict);
+        vm.max_steps = Some(2000);
+        let _ = vm::run(&mut vm, &mut BufferIo::new(input), &mut []);
+    }
+}
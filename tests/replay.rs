@@ -0,0 +1,38 @@
+use stackofstacks::replay::{self, Divergence, Recorder, ReplayCheck, Replayer};
+use stackofstacks::vm::{self, BufferIo, Vm};
+
+#[test]
+fn replaying_a_recording_reads_the_same_input(){
+    let filename = std::env::temp_dir().join(format!("sos-replay-{}.txt", std::process::id()));
+    let filename = filename.to_str().unwrap();
+
+    let mut recorder = Recorder::create(filename).unwrap();
+    let mut vm = Vm::new(b"?.?.?.!@".to_vec(), false);
+    let mut io = BufferIo::new(b"ab".to_vec());
+    vm::run(&mut vm, &mut io, &mut [&mut recorder]).unwrap();
+    drop(recorder);
+
+    let recording = replay::load(filename).unwrap();
+    assert_eq!(recording, vec!((1, 97), (3, 98), (5, -1)));
+    let _ = std::fs::remove_file(filename);
+
+    let mut check = ReplayCheck::new(&recording);
+    let mut vm = Vm::new(b"?.?.?.!@".to_vec(), false);
+    let mut replayer = Replayer::new(recording.clone(), BufferIo::default());
+    vm::run(&mut vm, &mut replayer, &mut [&mut check]).unwrap();
+    assert_eq!(replayer.output.output, io.output);
+    assert_eq!((check.divergence, check.unread()), (None, 0));
+
+    // an extra instruction moves the second read
+    let mut check = ReplayCheck::new(&recording);
+    let mut vm = Vm::new(b"?.$?.?.!@".to_vec(), false);
+    vm::run(&mut vm, &mut Replayer::new(recording, BufferIo::default()), &mut [&mut check]).unwrap();
+    assert_eq!(check.divergence, Some(Divergence{read: 1, step: 4, recorded: Some(3)}));
+}
+
+#[test]
+fn recordings_are_checked(){
+    assert_eq!(replay::parse("# comment\n\n7 -1\n").unwrap(), vec!((7, -1)));
+    assert!(replay::parse("1 256\n").is_err());
+    assert!(replay::parse("x 1\n").is_err());
+}
@@ -0,0 +1,52 @@
+use stackofstacks::assembler::assemble;
+use stackofstacks::json;
+use stackofstacks::snapshot::{Checkpoint, Snapshot};
+use stackofstacks::vm::{self, BufferIo, Vm};
+
+#[test]
+fn snapshots_survive_json(){
+    let mut vm = Vm::new(b"!!0$!1".to_vec(), false);
+    vm.stacks = [vec!(i64::MIN, -1, 0), vec!(i64::MAX)];
+    vm.active = 1;
+    vm.cp = 3;
+    vm.steps = 1 << 40;
+    vm.ram.insert(-5, 7);
+    let snapshot = Snapshot::capture(&vm, 12);
+
+    let read = Snapshot::from_json(&json::parse(&snapshot.to_json()).unwrap()).unwrap();
+    assert_eq!(read, snapshot);
+
+    let mut other = Vm::new(b"!!0$!0".to_vec(), false);
+    assert!(read.restore(&mut other).is_err());
+}
+
+#[test]
+fn resuming_continues_where_the_run_stopped(){
+    let program = assemble(std::fs::read("turing/110.sos").unwrap()).unwrap();
+    let run = |vm:&mut Vm, observers:&mut [&mut dyn vm::Observer]| -> Vec<u8>{
+        let mut io = BufferIo::default();
+        vm::run(vm, &mut io, observers).unwrap();
+        io.output
+    };
+
+    let mut whole = Vm::new(program.code.clone(), false);
+    whole.max_steps = Some(200_000);
+    let expected = run(&mut whole, &mut []);
+
+    let filename = std::env::temp_dir().join(format!("sos-snapshot-{}.json", std::process::id()));
+    let filename = filename.to_str().unwrap();
+    let mut first = Vm::new(program.code.clone(), false);
+    first.max_steps = Some(76_543);
+    let mut checkpoint = Checkpoint::new(filename, None, 0);
+    let mut output = run(&mut first, &mut [&mut checkpoint]);
+    assert!(checkpoint.error.is_none());
+
+    let mut second = Vm::new(program.code.clone(), false);
+    second.max_steps = Some(200_000);
+    Snapshot::load(filename).unwrap().restore(&mut second).unwrap();
+    output.extend(run(&mut second, &mut []));
+
+    assert_eq!(output, expected);
+    assert_eq!(second.stacks, whole.stacks);
+    let _ = std::fs::remove_file(filename);
+}
@@ -0,0 +1,147 @@
+use std::fs;
+
+use stackofstacks::assembler::assemble;
+use stackofstacks::structured::compile;
+use stackofstacks::vm::{self, BufferIo, Stop, Vm};
+
+const MAX_STEPS:u64 = 50_000_000;
+
+type Operator = (&'static str, fn(i64, i64) -> i64);
+
+fn sample(name:&str) -> Vec<u8>{
+    fs::read(format!("{}/structured/{}", env!("CARGO_MANIFEST_DIR"), name)).unwrap()
+}
+
+// Output of the compiled program, which never pops an empty stack or runs off the code
+fn run(program:&[u8], input:&[u8]) -> Vec<u8>{
+    let code = assemble(compile(program).unwrap().into_bytes()).unwrap().code;
+    let mut vm = Vm::new(code, true);
+    vm.max_steps = Some(MAX_STEPS);
+    let mut io = BufferIo::new(input.to_vec());
+    assert_eq!(vm::run(&mut vm, &mut io, &mut []), Ok(Stop::Halted));
+    io.output
+}
+
+fn error(program:&str) -> String{
+    compile(program.as_bytes()).unwrap_err()
+}
+
+#[test]
+fn samples_run(){
+    let fizzbuzz:String = (1..=100).map(|i| match (i % 3, i % 5){
+        (0, 0) => "FizzBuzz\n".to_owned(),
+        (0, _) => "Fizz\n".to_owned(),
+        (_, 0) => "Buzz\n".to_owned(),
+        _ => format!("{}\n", i),
+    }).collect();
+    assert_eq!(run(&sample("fizzbuzz.sosl"), b""), fizzbuzz.as_bytes());
+    assert_eq!(run(&sample("primes.sosl"), b""), b"2 3 5 7 11 13 17 19 23 29 31 37 41 43 47 53 59 61 67 71 73 79 83 89 97 \n");
+    assert_eq!(run(&sample("upper.sosl"), b"Stack Of Stacks, {az}\n"), b"STACK OF STACKS, {AZ}\n");
+    assert_eq!(run(&sample("upper.sosl"), b""), b"");
+}
+
+#[test]
+fn operators_match_i64(){
+    let values = [0, 1, -1, 2, 7, -7, 255, -256, 1 << 40, -(1 << 33) + 5, i64::MAX, i64::MIN];
+    let operators:[Operator; 16] = [
+        ("+", i64::wrapping_add),
+        ("-", i64::wrapping_sub),
+        ("*", i64::wrapping_mul),
+        ("/", |a, b| if b == 0 {0} else {a.wrapping_div(b)}),
+        ("%", |a, b| if b == 0 {a} else {a.wrapping_rem(b)}),
+        ("&", |a, b| a & b),
+        ("|", |a, b| a | b),
+        ("^", |a, b| a ^ b),
+        ("==", |a, b| i64::from(a == b)),
+        ("!=", |a, b| i64::from(a != b)),
+        // by the sign of the difference
+        ("<", |a, b| i64::from(a.wrapping_sub(b) < 0)),
+        (">", |a, b| i64::from(b.wrapping_sub(a) < 0)),
+        ("<=", |a, b| i64::from(b.wrapping_sub(a) >= 0)),
+        (">=", |a, b| i64::from(a.wrapping_sub(b) >= 0)),
+        ("&&", |a, b| i64::from(a != 0 && b != 0)),
+        ("||", |a, b| i64::from(a != 0 || b != 0)),
+    ];
+    for (operator, function) in operators{
+        let mut program = format!("fn check(a, b, expected) {{ put((a {} b) != expected); }}\n", operator);
+        for a in values{
+            for b in values{
+                program += &format!("check(0x{:x}, 0x{:x}, 0x{:x});\n", a, b, function(a, b));
+            }
+        }
+        assert_eq!(run(program.as_bytes(), b""), vec![0; values.len() * values.len()], "{}", operator);
+    }
+
+    let program = "var x = 0x8000000000000000; put(-x == x); put(-5 + 6); put(!0); put(!7); put(~0 == -1);\
+        put(1 + 2 * 3); put((1 + 2) * 3); put(0 || 1 && 0); put(1 == 1 == 1); put(6 & 3 | 8 ^ 1);";
+    assert_eq!(run(program.as_bytes(), b""), [1, 1, 1, 0, 1, 7, 9, 0, 1, 11]);
+}
+
+#[test]
+fn variables_are_scoped(){
+    let program = "
+        var a = 'a';
+        var b = 'b';
+        fn swap() { var t = a; a = b; b = t; }
+        if 1 {
+            var a = 'x'; # shadows the global
+            put(a);
+            a = 'y';
+            put(a);
+        }
+        put(a);
+        swap();
+        put(a); put(b);
+        var a = 'z';
+        put(a); put(b);
+    ";
+    assert_eq!(run(program.as_bytes(), b""), b"xyabaza");
+    assert_eq!(error("if 1 { var a = 1; } put(a);"), "Unknown variable 'a' at 1:25");
+    // functions only see globals declared before them
+    assert_eq!(error("fn f() { return g; } var g = 1; put(f());"), "Unknown variable 'g' at 1:17");
+}
+
+#[test]
+fn functions_are_inlined(){
+    let program = "
+        fn find(c) {
+            var i = 0;
+            while 1 {
+                var read = get();
+                if read == -1 { return -1; }
+                if read == c { return i; }
+                i = i + 1;
+            }
+        }
+        fn twice(x) { return x + x; }
+        fn nothing() { }
+        put('0' + twice(find(':')));
+        put('0' + find('!') + 2);
+        put(nothing() + 'n');
+        put(twice(twice(1)) + '0');
+    ";
+    assert_eq!(run(program.as_bytes(), b"ab:cde"), b"41n4");
+    assert_eq!(error("fn f(n) { return f(n - 1); } put(f(3));"), "Recursive call to 'f' at 1:18, functions are inlined");
+    assert_eq!(error("put(f()); fn f() { return 1; }"), "Unknown function 'f' at 1:5, functions have to be defined before they are called");
+    assert_eq!(error("fn f(a, b) { return a; } put(f(1));"), "'f' takes 2 arguments, not 1 at 1:30");
+    assert_eq!(error("return 1;"), "'return' outside of a function at 1:1");
+}
+
+#[test]
+fn errors_have_positions(){
+    assert_eq!(error("var x = 1;\nput(x +);"), "Unexpected ')' at 2:8");
+    assert_eq!(error("var while = 1;"), "Unexpected 'while' at 1:5");
+    assert_eq!(error("put(1)"), "Expected ';' but found end of file at 1:7");
+    assert_eq!(error("put('ab');"), "Bad character literal at 1:5");
+    assert_eq!(error("put(99999999999999999999);"), "Bad number '99999999999999999999' at 1:5");
+    assert_eq!(error("put(1 @ 2);"), "Unexpected character '@' at 1:7");
+    assert_eq!(error("fn f() { fn g() { } }"), "Unexpected 'fn' at 1:10");
+    assert_eq!(error("put(1); # \u{e9}"), "Non ASCII character on line 1");
+}
+
+#[test]
+fn lines_become_comments(){
+    let source = compile(b"var x = 1;\nwhile x < 3 { x = x + 1; }\nput(x);").unwrap();
+    assert!(source.contains("# 2: while x < 3 { x = x + 1; }\n"));
+    assert!(source.ends_with("!@\n"));
+}
@@ -0,0 +1,76 @@
+use std::fs::File;
+
+use stackofstacks::assembler::assemble;
+use stackofstacks::trace::{self, Event, Format, Record, Tracer};
+use stackofstacks::vm::{self, BufferIo, Effect, Observer, Vm};
+
+// Builds the records the same way the tracer does, to compare what comes back from the file with
+#[derive(Default)]
+struct Collector{
+    records:Vec<Record>,
+}
+
+impl Observer for Collector{
+    fn before(&mut self, vm:&Vm){
+        self.records.push(Record::capture(vm));
+    }
+
+    fn after(&mut self, _vm:&Vm, _cp:usize, effect:&Effect){
+        let record = self.records.last_mut().unwrap();
+        record.event = match effect{
+            Effect::Read(v) => Some(Event::Read(*v)),
+            Effect::Write(b) => Some(Event::Write(*b)),
+            _ => None,
+        };
+    }
+}
+
+// Reads a byte and EOF, jumps far enough for a multi byte cp, then pushes the extremes and -1 on both stacks
+fn program() -> Vec<u8>{
+    let source = format!("??[skip-a]@:a {} :skip [9223372036854775807][0-9223372036854775807-1]=.$!$!@", "$".repeat(100_000));
+    assemble(source.into_bytes()).unwrap().code
+}
+
+fn round_trip(format:Format, name:&str, code:&[u8]){
+    let filename = std::env::temp_dir().join(format!("sos-trace-{}-{}", name, std::process::id()));
+    let filename = filename.to_str().unwrap();
+
+    let mut tracer = Tracer::create(format, filename).unwrap();
+    let mut collector = Collector::default();
+    let mut vm = Vm::new(code.to_vec(), false);
+    vm::run(&mut vm, &mut BufferIo::new(b"a".to_vec()), &mut [&mut tracer, &mut collector]).unwrap();
+    assert!(tracer.error().is_none());
+    drop(tracer);
+
+    let reader = trace::read(File::open(filename).unwrap()).unwrap();
+    assert_eq!(reader.format(), format);
+    let records:Vec<Record> = reader.collect::<Result<_, _>>().unwrap();
+    let _ = std::fs::remove_file(filename);
+
+    assert_eq!(records, collector.records);
+}
+
+#[test]
+fn jsonl_traces_read_back_the_same(){
+    round_trip(Format::Jsonl, "jsonl", &program());
+}
+
+#[test]
+fn binary_traces_read_back_the_same(){
+    round_trip(Format::Binary, "bin", &program());
+}
+
+#[test]
+fn traces_cover_reads_writes_and_extremes(){
+    let mut collector = Collector::default();
+    let mut vm = Vm::new(program(), false);
+    vm::run(&mut vm, &mut BufferIo::new(b"a".to_vec()), &mut [&mut collector]).unwrap();
+    let records = collector.records;
+
+    let events:Vec<Event> = records.iter().filter_map(|r| r.event).collect();
+    assert_eq!(events, [Event::Read(97), Event::Read(-1), Event::Write(0)]);
+    assert!(records.iter().any(|r| r.cp > 100_000));
+    assert!(records.iter().any(|r| r.tops[0] == Some(i64::MAX)));
+    assert!(records.iter().any(|r| r.tops[0] == Some(i64::MIN)));
+    assert!(records.iter().any(|r| r.stack == 1 && r.tops[1] == Some(-1)));
+}
@@ -0,0 +1,86 @@
+use wasmi::{Caller, Engine, Linker, Module, Store};
+
+use stackofstacks::assembler::assemble;
+use stackofstacks::bytecode::bytecode;
+use stackofstacks::explore::Rng;
+use stackofstacks::vm::{self, BufferIo, Fault, Io, Stop, Vm};
+use stackofstacks::wasm;
+
+const MAX_STEPS:u64 = 5000;
+
+// Output, stacks, steps and how it ended, the same way for the interpreter and the module
+type Outcome = (Vec<u8>, [Vec<i64>;2], u64, Result<Stop, Fault>);
+
+fn interpret(code:&[u8], input:&[u8], strict:bool) -> Outcome{
+    let mut vm = Vm::new(code.to_vec(), strict);
+    vm.max_steps = Some(MAX_STEPS);
+    let mut io = BufferIo::new(input.to_vec());
+    let result = vm::run(&mut vm, &mut io, &mut []);
+    (io.output, vm.stacks, vm.steps, result)
+}
+
+fn instantiate(module:&[u8], input:&[u8]) -> Outcome{
+    let engine = Engine::default();
+    let module = Module::new(&engine, module).unwrap();
+    let mut store = Store::new(&engine, BufferIo::new(input.to_vec()));
+    let mut linker = <Linker<BufferIo>>::new(&engine);
+    linker.func_wrap("sos", "read_byte", |mut caller:Caller<'_, BufferIo>| caller.data_mut().read() as i32).unwrap();
+    linker.func_wrap("sos", "write_byte", |mut caller:Caller<'_, BufferIo>, byte:i32| caller.data_mut().write(byte as u8)).unwrap();
+    let instance = linker.instantiate(&mut store, &module).unwrap().start(&mut store).unwrap();
+
+    let status = instance.get_typed_func::<i64, i32>(&store, "run").unwrap().call(&mut store, MAX_STEPS as i64).unwrap();
+    let depth = instance.get_typed_func::<i32, i32>(&store, "depth").unwrap();
+    let item = instance.get_typed_func::<(i32, i32), i64>(&store, "item").unwrap();
+    let mut stacks = [vec!(), vec!()];
+    for (s, stack) in stacks.iter_mut().enumerate(){
+        for index in 0..depth.call(&mut store, s as i32).unwrap(){
+            stack.push(item.call(&mut store, (s as i32, index)).unwrap());
+        }
+    }
+    let steps = instance.get_typed_func::<(), i64>(&store, "steps").unwrap().call(&mut store, ()).unwrap() as u64;
+    let cp = instance.get_typed_func::<(), i64>(&store, "cp").unwrap().call(&mut store, ()).unwrap() as usize;
+    let result = match status{
+        wasm::STATUS_HALTED => Ok(Stop::Halted),
+        wasm::STATUS_STEP_LIMIT => Ok(Stop::StepLimit),
+        wasm::STATUS_STACK_DEPLETED => Err(Fault::StackDepleted),
+        wasm::STATUS_OUT_OF_CODE => Err(Fault::OutOfCode(cp)),
+        _ => panic!("unknown status {}", status),
+    };
+    (store.into_data().output, stacks, steps, result)
+}
+
+#[test]
+fn modules_match_the_interpreter(){
+    let root = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let mut programs = vec!();
+    for filename in ["helloworld.sos", "helloworldworldworld_macro.sos", "loop.sos", "turing/110.sos", "turing/branch.sos", "turing/while.sos"]{
+        programs.push(assemble(std::fs::read(root.join(filename)).unwrap()).unwrap().code);
+    }
+    let mut rng = Rng::new(45);
+    for _ in 0..200{
+        let mut bytes = vec![0; 1 + (rng.next_u64() % 24) as usize];
+        rng.fill(&mut bytes);
+        programs.push(bytecode(&bytes));
+    }
+    // i64::MIN / -1, overflowing add, sub, mul and shifts, division by 0 and popping from empty stacks
+    let min = format!("!1{}", "0".repeat(63));
+    programs.push(format!("{min}!/{min}!+!!^1{min}-{min}=*{min}1!!^11/!!^!!^/$~$=.!@").into_bytes());
+    programs.push(vec!());
+
+    for code in &programs{
+        for strict in [false, true]{
+            let input = b"Input\xFF\x00";
+            let expected = interpret(code, input, strict);
+            assert_eq!(instantiate(&wasm::wasm(code, strict), input), expected, "{} (strict: {})", String::from_utf8_lossy(code), strict);
+        }
+    }
+}
+
+#[test]
+fn text_form_is_the_same_module(){
+    let code = assemble(std::fs::read(std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("turing/110.sos")).unwrap()).unwrap().code;
+    for strict in [false, true]{
+        let text = wat::parse_str(wasm::wat(&code, strict)).unwrap();
+        assert_eq!(instantiate(&text, b""), instantiate(&wasm::wasm(&code, strict), b""));
+    }
+}
@@ -0,0 +1,17 @@
+use stackofstacks::assembler::assemble;
+use stackofstacks::vm::{self, BufferIo, Vm};
+
+fn stack(source:&str) -> Vec<i64>{
+    let mut vm = Vm::new(assemble(source.as_bytes().to_vec()).unwrap().code, true);
+    vm::run(&mut vm, &mut BufferIo::default(), &mut []).unwrap();
+    vm.stacks[0].clone()
+}
+
+#[test]
+fn division_wraps(){
+    // the one quotient that does not fit stays the lowest value instead of panicking
+    assert_eq!(stack("[0-9223372036854775807-1]!/!@"), [i64::MIN]);
+    // dividing by 0 gives 0 by design
+    assert_eq!(stack("[7]!!^/!@"), [0]);
+    assert_eq!(stack("[0-7][2]/!@"), [-3]);
+}